@@ -0,0 +1,203 @@
+// External imports
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+// Local imports
+use crate::math::Vec3;
+use crate::graphics::AABB;
+
+// A KD-tree over points in 3-dimensional space, with an associated value for
+// each point. This is the typical structure for photon mapping gather queries,
+// as it provides exact k-nearest-neighbour queries.
+//
+// The tree is stored implicitly in a single array. The node for a range
+// [low,high) of the array is located at its center `(low+high)/2`. Its left
+// subtree occupies [low,mid), and its right subtree occupies [mid+1,high).
+// As the tree is split on the median, it is always balanced.
+
+/// A balanced KD-tree over 3-dimensional points
+pub struct KDTree< T > {
+  nodes : Vec< KDNode< T > >
+}
+
+/// A single point in the tree. It splits space along `axis` (0=x, 1=y, 2=z)
+struct KDNode< T > {
+  point : Vec3,
+  value : T,
+  axis  : usize
+}
+
+/// An element in the max-heap of nearest neighbours, ordered by distance
+struct Candidate {
+  dis_sq : f32,
+  index  : usize
+}
+
+impl< T > KDTree< T > {
+  /// Constructs a new KD-tree containing the provided points
+  /// Every node splits its points by the median along the longest axis of
+  ///   their bounding box
+  pub fn build( points : Vec< (Vec3, T) > ) -> KDTree< T > {
+    let mut nodes : Vec< KDNode< T > > =
+      points.into_iter( ).map( |(point, value)| KDNode { point, value, axis: 0 } ).collect( );
+    build_range( &mut nodes );
+    KDTree { nodes }
+  }
+
+  /// Returns the number of points in the tree
+  pub fn len( &self ) -> usize {
+    self.nodes.len( )
+  }
+
+  /// Returns the values of the `k` points nearest to `query`, ordered from near
+  ///   to far. If the tree contains fewer than `k` points, all are returned.
+  pub fn knn< 'a >( &'a self, query : Vec3, k : usize ) -> Vec< &'a T > {
+    if k == 0 {
+      return Vec::new( );
+    }
+
+    let mut heap = BinaryHeap::with_capacity( k + 1 );
+    self.knn_range( query, k, 0, self.nodes.len( ), &mut heap );
+
+    heap.into_sorted_vec( ).into_iter( ).map( |c| &self.nodes[ c.index ].value ).collect( )
+  }
+
+  /// Finds the nearest neighbours within the subtree occupying [low,high)
+  /// `heap` contains the `k` best candidates found so far, furthest on top
+  fn knn_range( &self, query : Vec3, k : usize, low : usize, high : usize, heap : &mut BinaryHeap< Candidate > ) {
+    if low >= high {
+      return;
+    }
+
+    let mid  = ( low + high ) / 2;
+    let node = &self.nodes[ mid ];
+
+    let dis_sq = node.point.dis_sq( query );
+    if heap.len( ) < k {
+      heap.push( Candidate { dis_sq, index: mid } );
+    } else if let Some( top ) = heap.peek( ) {
+      if dis_sq < top.dis_sq {
+        heap.pop( );
+        heap.push( Candidate { dis_sq, index: mid } );
+      }
+    }
+
+    let diff = axis_value( query, node.axis ) - axis_value( node.point, node.axis );
+    let (near, far) =
+      if diff < 0.0 {
+        ( (low, mid), (mid + 1, high) )
+      } else {
+        ( (mid + 1, high), (low, mid) )
+      };
+
+    self.knn_range( query, k, near.0, near.1, heap );
+
+    // Only visit the far side if it could contain a closer point than the
+    // furthest candidate
+    let is_far_needed =
+      if heap.len( ) < k {
+        true
+      } else if let Some( top ) = heap.peek( ) {
+        diff * diff < top.dis_sq
+      } else {
+        true
+      };
+
+    if is_far_needed {
+      self.knn_range( query, k, far.0, far.1, heap );
+    }
+  }
+}
+
+/// Recursively builds the implicit tree in-place. See the top of this file
+fn build_range< T >( nodes : &mut [KDNode< T >] ) {
+  if nodes.len( ) <= 1 {
+    return;
+  }
+
+  let mut bounds = AABB::EMPTY;
+  for n in nodes.iter( ) {
+    bounds = bounds.include( n.point );
+  }
+
+  let axis =
+    if bounds.x_size( ) >= bounds.y_size( ) && bounds.x_size( ) >= bounds.z_size( ) {
+      0
+    } else if bounds.y_size( ) >= bounds.z_size( ) {
+      1
+    } else {
+      2
+    };
+
+  let mid = nodes.len( ) / 2;
+  nodes.select_nth_unstable_by( mid, |a, b| {
+    axis_value( a.point, axis ).partial_cmp( &axis_value( b.point, axis ) ).unwrap_or( Ordering::Equal )
+  } );
+  nodes[ mid ].axis = axis;
+
+  let (left, right) = nodes.split_at_mut( mid );
+  build_range( left );
+  build_range( &mut right[ 1.. ] );
+}
+
+/// Returns the x-, y-, or z-component of `v`, for axis 0, 1, or 2 respectively
+fn axis_value( v : Vec3, axis : usize ) -> f32 {
+  match axis {
+    0 => v.x,
+    1 => v.y,
+    _ => v.z
+  }
+}
+
+impl PartialEq for Candidate {
+  fn eq( &self, o : &Candidate ) -> bool {
+    self.dis_sq == o.dis_sq
+  }
+}
+
+impl Eq for Candidate { }
+
+impl PartialOrd for Candidate {
+  fn partial_cmp( &self, o : &Candidate ) -> Option< Ordering > {
+    Some( self.cmp( o ) )
+  }
+}
+
+impl Ord for Candidate {
+  fn cmp( &self, o : &Candidate ) -> Ordering {
+    self.dis_sq.partial_cmp( &o.dis_sq ).unwrap_or( Ordering::Equal )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rng::Rng;
+
+  #[test]
+  fn knn_matches_brute_force( ) {
+    let mut rng = Rng::with_state( 1234 );
+    let points : Vec< (Vec3, usize) > =
+      ( 0..500 ).map( |i| ( Vec3::new( rng.next( ), rng.next( ), rng.next( ) * 4.0 ), i ) ).collect( );
+    let tree = KDTree::build( points.clone( ) );
+    assert_eq!( tree.len( ), points.len( ) );
+
+    for _i in 0..100 {
+      let query = Vec3::new( rng.next( ), rng.next( ), rng.next( ) * 4.0 );
+
+      let mut expected = points.clone( );
+      expected.sort_by( |a, b| a.0.dis_sq( query ).partial_cmp( &b.0.dis_sq( query ) ).unwrap( ) );
+      let expected : Vec< usize > = expected.iter( ).take( 8 ).map( |p| p.1 ).collect( );
+
+      let found : Vec< usize > = tree.knn( query, 8 ).into_iter( ).cloned( ).collect( );
+      assert_eq!( found, expected );
+    }
+  }
+
+  #[test]
+  fn knn_returns_all_points_if_fewer_than_k( ) {
+    let tree = KDTree::build( vec![ ( Vec3::new( 0.0, 0.0, 0.0 ), 'a' ), ( Vec3::new( 2.0, 0.0, 0.0 ), 'b' ) ] );
+
+    assert_eq!( tree.knn( Vec3::new( 1.5, 0.0, 0.0 ), 5 ), vec![ &'b', &'a' ] );
+    assert!( tree.knn( Vec3::new( 1.5, 0.0, 0.0 ), 0 ).is_empty( ) );
+  }
+}
@@ -1,4 +1,5 @@
 pub mod stack;
+pub mod kdtree;
 mod photon_tree;
 
 pub use photon_tree::PhotonTree;
@@ -15,21 +15,29 @@ use crate::rng::Rng;
 // The sample is then taken by interpolating the probabilities in in these
 // cells, proportional to "how much the vertex is in that cell".
 
-#[derive(Debug)]
+#[derive(Clone,Debug)]
 pub struct PhotonTree {
   num_lights : usize,
   root       : Octree,
-  size       : f32
+  bounds     : AABB
 }
 
 /// The identifier of a light within the scene
 type LightId = usize;
 
+/// Identifies the binary format. (See `PhotonTree::to_bytes()`)
+static MAGIC : &[u8; 4] = b"PHT1";
+
 /// Once the number of photons in a cell exceeds this amount, it is subdivided
 static MAX_PHOTONS_IN_CELL : usize = 1024;
 
+/// The deepest octree that is accepted when deserializing. This guards against
+///   unbounded recursion on corrupt data; subdivision never gets this deep.
+static MAX_DEPTH : usize = 64;
+
 /// An octree node. Each internal node has 8 children
 /// Note that all nodes have an associated CDF.
+#[derive(Clone)]
 enum Octree {
   Node {
     cdf      : EmpiricalPDF,
@@ -45,29 +53,35 @@ impl PhotonTree {
   /// Constructs a new PhotonTree
   /// It needs to know the number of lights in the scene, such that it can
   ///   some positive probability for each light, at least.
+  /// The octree is placed around (-1024,-1024,-1024)-(1024, 1024, 1024)
   pub fn new( num_lights : usize ) -> PhotonTree {
+    // This doesn't scale on infinitely sized scenes, but suffices for now
+    PhotonTree::new_with_bounds( num_lights, Vec3::new( -1024.0, -1024.0, -1024.0 ), Vec3::new( 1024.0, 1024.0, 1024.0 ) )
+  }
+
+  /// Constructs a new PhotonTree whose octree is placed around the box with
+  ///   corners `min` and `max`. Photons outside it are ignored, so it should
+  ///   contain the scene. A tight box gives finer cells near the scene.
+  pub fn new_with_bounds( num_lights : usize, min : Vec3, max : Vec3 ) -> PhotonTree {
     PhotonTree {
       num_lights
     , root: Octree::Leaf { values: Vec::new( ), cdf: EmpiricalPDF::new( num_lights ) }
-      // Place the octree around (-1024,-1024,-1024)-(1024, 1024, 1024)
-      // This doesn't scale on infinitely sized scenes, but suffices for now
-    , size: 1024.0
+    , bounds: AABB::new1( min.x, min.y, min.z, max.x, max.y, max.z )
     }
   }
 
   /// Inserts a new photon into the tree
   /// The intensity represents the color by a single value
   ///   (typically max(r,g,b) is a good choice)
+  /// Returns `false` (and does not insert) if the photon lies outside the tree
   pub fn insert( &mut self, light_id : LightId, location : Vec3, intensity : f32 ) -> bool {
-    if location.x < -self.size && location.x > self.size &&
-       location.y < -self.size && location.y > self.size &&
-       location.z < -self.size && location.z > self.size {
+    if !self.bounds.contains_point( &location ) {
       return false;
     }
 
     self.root.insert(
       self.num_lights
-    , AABB::new1( -self.size, -self.size, -self.size, self.size, self.size, self.size )
+    , self.bounds
     , light_id
     , location
     , intensity
@@ -75,16 +89,147 @@ impl PhotonTree {
     true
   }
 
+  /// Inserts all the photons, which produces the same tree as inserting them
+  ///   one-by-one with `PhotonTree::insert(..)`. Returns the number of photons
+  ///   inserted; photons outside the tree are ignored.
+  /// The photons are first sorted into buckets for the subtrees they end up in,
+  ///   after which every subtree is filled independently. This avoids walking
+  ///   down the tree from the root for every photon.
+  pub fn insert_batch( &mut self, photons : &[( LightId, Vec3, f32 )] ) -> usize {
+    let bounds = self.bounds;
+    let inside : Vec< ( LightId, Vec3, f32 ) > =
+      photons.iter( ).filter( |p| bounds.contains_point( &p.1 ) ).cloned( ).collect( );
+
+    self.root.insert_batch( self.num_lights, bounds, &inside );
+    inside.len( )
+  }
+
+  /// Thins the photons in the tree to reduce its memory, such that roughly
+  ///   `target_count` remain. Every (non-empty) leaf may keep
+  ///   `target_count / num_leaves` photons. Of leaves that have more, every
+  ///   photon is kept with a probability proportional to its intensity; the
+  ///   intensities of those kept are divided by that probability, which
+  ///   preserves their expected sum.
+  /// The distributions in the tree remain unchanged, so sampling is unaffected.
+  ///   Returns the number of photons that remain.
+  pub fn resample( &mut self, rng : &mut Rng, target_count : usize ) -> usize {
+    let num_leaves = self.root.num_filled_leaves( ).max( 1 );
+    let quota = target_count as f32 / num_leaves as f32;
+    self.root.resample( rng, quota );
+    self.root.len( )
+  }
+
+  /// Returns the number of photons in the tree
+  pub fn len( &self ) -> usize {
+    self.root.len( )
+  }
+
+  /// Returns the number of lights for which the tree stores distributions
+  pub fn num_lights( &self ) -> usize {
+    self.num_lights
+  }
+
+  /// Computes the photon density (the summed intensity of the photons) in the
+  ///   cells of a uniform `resolution^3` grid over the bounds of the tree.
+  ///   These are normalised to [0,1], by the densest cell. Useful for debugging
+  ///   the photon distribution.
+  /// The cell at (x,y,z) is located at index `(z * resolution + y) * resolution + x`
+  pub fn sample_density_grid( &self, resolution : usize ) -> Vec< f32 > {
+    let mut grid = vec![ 0.0; resolution * resolution * resolution ];
+    if resolution == 0 {
+      return grid;
+    }
+
+    let b = &self.bounds;
+    let min  = Vec3::new( b.x_min, b.y_min, b.z_min );
+    let size = Vec3::new( b.x_size( ), b.y_size( ), b.z_size( ) );
+    let res  = resolution as f32;
+
+    // Maps a coordinate to its cell along one axis
+    let cell = |v : f32, min : f32, size : f32| {
+      ( ( ( v - min ) / size * res ) as usize ).min( resolution - 1 )
+    };
+
+    self.root.for_each_photon( &mut |_, p, intensity| {
+      let x = cell( p.x, min.x, size.x );
+      let y = cell( p.y, min.y, size.y );
+      let z = cell( p.z, min.z, size.z );
+      grid[ ( z * resolution + y ) * resolution + x ] += intensity;
+    } );
+
+    let max_density = grid.iter( ).cloned( ).fold( 0.0, f32::max );
+    if max_density > 0.0 {
+      for d in grid.iter_mut( ) {
+        *d /= max_density;
+      }
+    }
+    grid
+  }
+
+  /// Serializes the tree to a compact binary format, which can be loaded with
+  ///   `PhotonTree::from_bytes(..)`. All values are little-endian. Its layout:
+  /// * The magic bytes "PHT1"
+  /// * The number of lights (u32), and the bounds (6 f32s; mins, then maxs)
+  /// * The octree, depth-first. Every node starts with a tag (u8) and its PDF
+  ///   bins (one f32 per light). Internal nodes (tag 0) are followed by their
+  ///   8 children. Leaves (tag 1) are followed by their photon count (u32)
+  ///   and the photons as (light id (u32), x, y, z, intensity (f32s)).
+  pub fn to_bytes( &self ) -> Vec< u8 > {
+    let mut dst = Vec::new( );
+    dst.extend_from_slice( MAGIC );
+    write_u32( &mut dst, self.num_lights as u32 );
+    for v in &[ self.bounds.x_min, self.bounds.y_min, self.bounds.z_min
+              , self.bounds.x_max, self.bounds.y_max, self.bounds.z_max ] {
+      write_f32( &mut dst, *v );
+    }
+    self.root.write_bytes( &mut dst );
+    dst
+  }
+
+  /// Deserializes a tree that was serialized with `PhotonTree::to_bytes()`
+  pub fn from_bytes( data : &[u8] ) -> Result< PhotonTree, &'static str > {
+    let mut r = ByteReader { data, pos: 0 };
+
+    if r.take( 4 )? != MAGIC {
+      return Err( "Not a photon tree" );
+    }
+    let num_lights = r.u32( )? as usize;
+    let min = Vec3::new( r.f32( )?, r.f32( )?, r.f32( )? );
+    let max = Vec3::new( r.f32( )?, r.f32( )?, r.f32( )? );
+    let root = Octree::read_bytes( &mut r, num_lights, 0 )?;
+
+    if r.pos != data.len( ) {
+      return Err( "Trailing bytes after photon tree" );
+    }
+
+    let mut tree = PhotonTree::new_with_bounds( num_lights, min, max );
+    tree.root = root;
+    Ok( tree )
+  }
+
+  /// Returns the light that most strongly illuminates the smallest cell
+  ///   containing `v`. This is useful for debugging. Returns `None` if `v` is
+  ///   outside the tree
+  pub fn dominant_light( &mut self, v : Vec3 ) -> Option< LightId > {
+    if !self.bounds.contains_point( &v ) || self.num_lights == 0 {
+      return None;
+    }
+
+    let self_bounds = self.bounds;
+    let (cdf, _, _) = self.root.find_leaf( self_bounds, 0, v );
+    Some( cdf.max_prob_bin( ) )
+  }
+
   /// Samples a light source for the point `v`. The probability of picking that
   /// particular light source is also returned.
   pub fn sample( &mut self, rng : &mut Rng, v : Vec3 ) -> (LightId, f32) {
     // Interpolate the CDFs
 
-    if v.x < -self.size || v.y < -self.size || v.z < -self.size || v.x > self.size || v.y > self.size || v.z > self.size {
+    if !self.bounds.contains_point( &v ) {
       return ( rng.next_in_range(0, self.num_lights), 1.0 / self.num_lights as f32 );
     }
     
-    let self_bounds = AABB::new1( -self.size, -self.size, -self.size, self.size, self.size, self.size );
+    let self_bounds = self.bounds;
     let (_, bounds, depth) = self.root.find_leaf( self_bounds, 0, v );
     
     let (weight_x, weight_adj_x, x_off) =
@@ -160,6 +305,145 @@ impl PhotonTree {
 }
 
 impl Octree {
+  /// Returns the number of photons in the tree
+  fn len( &self ) -> usize {
+    match self {
+      Octree::Node { children, .. } => children.iter( ).map( |c| c.len( ) ).sum( ),
+      Octree::Leaf { values, .. } => values.len( )
+    }
+  }
+
+  /// Returns the number of leaves in the tree that contain photons
+  fn num_filled_leaves( &self ) -> usize {
+    match self {
+      Octree::Node { children, .. } => children.iter( ).map( |c| c.num_filled_leaves( ) ).sum( ),
+      Octree::Leaf { values, .. } => if values.is_empty( ) { 0 } else { 1 }
+    }
+  }
+
+  /// Thins every leaf with more than `quota` photons
+  ///   (See `PhotonTree::resample(..)`)
+  fn resample( &mut self, rng : &mut Rng, quota : f32 ) {
+    match self {
+      Octree::Node { children, .. } => {
+        for c in children {
+          c.resample( rng, quota );
+        }
+      },
+      Octree::Leaf { values, .. } => {
+        if values.len( ) as f32 <= quota {
+          return;
+        }
+
+        let total : f32 = values.iter( ).map( |p| p.2 ).sum( );
+        let mut kept = Vec::with_capacity( quota.ceil( ) as usize );
+
+        for &(light_id, v, intensity) in values.iter( ) {
+          // Without any intensity, every photon is equally important
+          let keep_prob =
+            if total > 0.0 {
+              ( quota * intensity / total ).min( 1.0 )
+            } else {
+              quota / values.len( ) as f32
+            };
+
+          if keep_prob > 0.0 && rng.next( ) < keep_prob {
+            kept.push( ( light_id, v, intensity / keep_prob ) );
+          }
+        }
+
+        *values = kept;
+      }
+    }
+  }
+
+  /// Calls `f` for every photon in the tree, with its light, location and
+  ///   intensity
+  fn for_each_photon< F : FnMut( LightId, Vec3, f32 ) >( &self, f : &mut F ) {
+    match self {
+      Octree::Node { children, .. } => {
+        for c in children {
+          c.for_each_photon( f );
+        }
+      },
+      Octree::Leaf { values, .. } => {
+        for (light_id, v, intensity) in values {
+          f( *light_id, *v, *intensity );
+        }
+      }
+    }
+  }
+
+  /// Appends the binary representation to `dst`. See `PhotonTree::to_bytes()`
+  fn write_bytes( &self, dst : &mut Vec< u8 > ) {
+    match self {
+      Octree::Node { cdf, children } => {
+        dst.push( 0 );
+        for b in cdf.bins( ) {
+          write_f32( dst, *b );
+        }
+        for c in children {
+          c.write_bytes( dst );
+        }
+      },
+      Octree::Leaf { cdf, values } => {
+        dst.push( 1 );
+        for b in cdf.bins( ) {
+          write_f32( dst, *b );
+        }
+        write_u32( dst, values.len( ) as u32 );
+        for (light_id, v, intensity) in values {
+          write_u32( dst, *light_id as u32 );
+          write_f32( dst, v.x );
+          write_f32( dst, v.y );
+          write_f32( dst, v.z );
+          write_f32( dst, *intensity );
+        }
+      }
+    }
+  }
+
+  /// Reads a tree from its binary representation. See `PhotonTree::to_bytes()`
+  /// Fails if the tree is deeper than `MAX_DEPTH` (start at depth 0)
+  fn read_bytes( r : &mut ByteReader, num_lights : usize, depth : usize ) -> Result< Octree, &'static str > {
+    if depth > MAX_DEPTH {
+      return Err( "Photon tree too deep" );
+    }
+
+    let tag = r.u8( )?;
+
+    let mut bins = Vec::with_capacity( num_lights );
+    for _i in 0..num_lights {
+      bins.push( r.f32( )? );
+    }
+    let cdf = EmpiricalPDF::from_bins( bins );
+
+    match tag {
+      0 => {
+        let mut children = Vec::with_capacity( 8 );
+        for _i in 0..8 {
+          children.push( Octree::read_bytes( r, num_lights, depth + 1 )? );
+        }
+        Ok( Octree::Node { cdf, children } )
+      },
+      1 => {
+        let num_values = r.u32( )? as usize;
+        // Do not trust `num_values` for the allocation, as the data may be corrupt
+        let mut values = Vec::new( );
+        for _i in 0..num_values {
+          let light_id = r.u32( )? as usize;
+          if light_id >= num_lights {
+            return Err( "Invalid light id" );
+          }
+          let v = Vec3::new( r.f32( )?, r.f32( )?, r.f32( )? );
+          values.push( ( light_id, v, r.f32( )? ) );
+        }
+        Ok( Octree::Leaf { cdf, values } )
+      },
+      _ => Err( "Invalid octree node tag" )
+    }
+  }
+
   /// Inserts a photon at `location` into the tree
   /// As octrees don't store their own bounds, this needs to be passed as well
   pub fn insert( &mut self, num_lights : usize, self_bounds : AABB, light_id : LightId, location : Vec3, intensity : f32 ) {
@@ -195,6 +479,45 @@ impl Octree {
     }
   }
 
+  /// Inserts all photons into the tree (See `PhotonTree::insert_batch(..)`)
+  /// As octrees don't store their own bounds, this needs to be passed as well
+  pub fn insert_batch( &mut self, num_lights : usize, self_bounds : AABB, photons : &[( LightId, Vec3, f32 )] ) {
+    match self {
+      Octree::Node { cdf, children } => {
+        // The photons for every child, together with that child's bounds
+        let mut buckets : Vec< ( AABB, Vec< ( LightId, Vec3, f32 ) > ) > =
+          vec![ ( AABB::EMPTY, Vec::new( ) ); 8 ];
+
+        for &(light_id, location, intensity) in photons {
+          cdf.add( light_id, intensity );
+
+          let (child_index, child_bounds) = child( self_bounds, location );
+          buckets[ child_index ].0 = child_bounds;
+          buckets[ child_index ].1.push( ( light_id, location, intensity ) );
+        }
+
+        // The children are independent of each other
+        for (c, (child_bounds, bucket)) in children.iter_mut( ).zip( buckets.iter( ) ) {
+          if !bucket.is_empty( ) {
+            c.insert_batch( num_lights, *child_bounds, bucket );
+          }
+        }
+      },
+      Octree::Leaf { .. } => {
+        for i in 0..photons.len( ) {
+          let (light_id, location, intensity) = photons[ i ];
+          self.insert( num_lights, self_bounds, light_id, location, intensity );
+
+          if let Octree::Node { .. } = self {
+            // The leaf was subdivided. Distribute the remainder over its children
+            self.insert_batch( num_lights, self_bounds, &photons[ (i+1).. ] );
+            return;
+          }
+        }
+      }
+    }
+  }
+
   /// Returns properties of the smallest cell containing `location`
   /// As nodes don't store their bounds or depth, these need to be provided
   ///   (start at depth 0)
@@ -231,6 +554,48 @@ impl Octree {
   }
 }
 
+/// Appends the little-endian representation of `v` to `dst`
+fn write_u32( dst : &mut Vec< u8 >, v : u32 ) {
+  dst.extend_from_slice( &v.to_le_bytes( ) );
+}
+
+/// Appends the little-endian representation of `v` to `dst`
+fn write_f32( dst : &mut Vec< u8 >, v : f32 ) {
+  dst.extend_from_slice( &v.to_le_bytes( ) );
+}
+
+/// Reads little-endian values from a byte slice, while checking its bounds
+struct ByteReader< 'a > {
+  data : &'a [u8],
+  pos  : usize
+}
+
+impl< 'a > ByteReader< 'a > {
+  fn take( &mut self, n : usize ) -> Result< &'a [u8], &'static str > {
+    if self.pos + n > self.data.len( ) {
+      Err( "Unexpected end of photon tree data" )
+    } else {
+      let res = &self.data[ self.pos..(self.pos + n) ];
+      self.pos += n;
+      Ok( res )
+    }
+  }
+
+  fn u8( &mut self ) -> Result< u8, &'static str > {
+    Ok( self.take( 1 )?[ 0 ] )
+  }
+
+  fn u32( &mut self ) -> Result< u32, &'static str > {
+    let b = self.take( 4 )?;
+    Ok( u32::from_le_bytes( [ b[ 0 ], b[ 1 ], b[ 2 ], b[ 3 ] ] ) )
+  }
+
+  fn f32( &mut self ) -> Result< f32, &'static str > {
+    let b = self.take( 4 )?;
+    Ok( f32::from_le_bytes( [ b[ 0 ], b[ 1 ], b[ 2 ], b[ 3 ] ] ) )
+  }
+}
+
 // Computes the child ID and AABB of an octree node
 fn child( bounds : AABB, v : Vec3 ) -> ( usize, AABB ) {
   let c = bounds.center( );
@@ -267,3 +632,52 @@ impl fmt::Debug for Octree {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rng::Rng;
+
+  /// Constructs a tree with enough photons that its root is subdivided
+  fn random_tree( ) -> PhotonTree {
+    let mut rng  = Rng::with_state( 1234 );
+    let mut tree = PhotonTree::new_with_bounds( 3, Vec3::new( -1.0, -1.0, -1.0 ), Vec3::new( 1.0, 1.0, 1.0 ) );
+    for _i in 0..( MAX_PHOTONS_IN_CELL + 500 ) {
+      let v = Vec3::new( rng.next_f32_in_range( -1.0, 1.0 ), rng.next_f32_in_range( -1.0, 1.0 ), rng.next_f32_in_range( -1.0, 1.0 ) );
+      tree.insert( rng.next_in_range( 0, 3 ), v, rng.next( ) );
+    }
+    tree
+  }
+
+  #[test]
+  fn insert_rejects_photons_outside_bounds( ) {
+    let mut tree = PhotonTree::new( 1 );
+
+    assert!( !tree.insert( 0, Vec3::new( 2000.0, 0.0, 0.0 ), 1.0 ) );
+    assert_eq!( tree.len( ), 0 );
+
+    assert!( tree.insert( 0, Vec3::new( 0.0, 0.0, 0.0 ), 1.0 ) );
+    assert_eq!( tree.len( ), 1 );
+  }
+
+  #[test]
+  fn from_bytes_restores_to_bytes( ) {
+    let tree  = random_tree( );
+    let bytes = tree.to_bytes( );
+
+    let loaded = PhotonTree::from_bytes( &bytes ).unwrap( );
+    assert_eq!( loaded.num_lights( ), tree.num_lights( ) );
+    assert_eq!( loaded.len( ), tree.len( ) );
+    assert!( loaded.to_bytes( ) == bytes );
+  }
+
+  #[test]
+  fn from_bytes_rejects_truncated_input( ) {
+    let bytes = random_tree( ).to_bytes( );
+
+    // Every prefix is invalid; checking some of them suffices
+    for len in ( 0..bytes.len( ) ).step_by( 97 ).chain( Some( bytes.len( ) - 1 ) ) {
+      assert!( PhotonTree::from_bytes( &bytes[ ..len ] ).is_err( ), "Accepted {} of {} bytes", len, bytes.len( ) );
+    }
+  }
+}
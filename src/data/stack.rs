@@ -3,7 +3,9 @@ use crate::rng::Rng;
 pub struct Stack< T > {
   default_val : T,
   data        : Vec< T >,
-  size        : usize
+  size        : usize,
+  // If false, pushing beyond the capacity panics instead of growing
+  is_growable : bool
 }
 
 impl< T: Clone + Copy > Stack< T > {
@@ -12,22 +14,44 @@ impl< T: Clone + Copy > Stack< T > {
       default_val: default_val
     , data: vec![ default_val; 1024 ]
     , size: 0
+    , is_growable: true
     }
   }
 
+  /// Constructs a stack with room for exactly `capacity` elements, which never
+  ///   grows. Pushing more elements panics. This avoids the repeated
+  ///   reallocations (and temporary doubled memory) of growing a large stack.
+  pub fn with_capacity( capacity : usize, default_val : T ) -> Stack< T > {
+    Stack {
+      default_val: default_val
+    , data: vec![ default_val; capacity ]
+    , size: 0
+    , is_growable: false
+    }
+  }
+
+  /// Returns the number of elements that fit in the stack without growing
+  pub fn capacity( &self ) -> usize {
+    self.data.len( )
+  }
+
   pub fn clear( &mut self ) {
     self.size = 0;
   }
 
   pub fn shuffle( &mut self, rng : &mut Rng ) {
+    // Fisher-Yates. See `Rng::shuffle(..)`
     for i in 0..self.size {
-      let new_i = rng.next_in_range( 0, self.size );
+      let new_i = rng.next_in_range( i, self.size );
       self.swap( i, new_i );
     }
   }
 
   pub fn push( &mut self, v : T ) {
     if self.size >= self.data.len( ) {
+      if !self.is_growable {
+        panic!( "Stack overflow" );
+      }
       self.data.reserve( self.size );
       for _i in 0..self.size {
         self.data.push( self.default_val );
@@ -53,6 +77,16 @@ impl< T: Clone + Copy > Stack< T > {
   pub fn swap( &mut self, i : usize, j : usize ) {
     self.data.swap( i, j );
   }
+
+  /// Iterates over the elements on the stack (bottom to top), without popping
+  pub fn iter( &self ) -> std::slice::Iter< T > {
+    self.data[ 0..self.size ].iter( )
+  }
+
+  /// Mutably iterates over the elements on the stack (bottom to top)
+  pub fn iter_mut( &mut self ) -> std::slice::IterMut< T > {
+    self.data[ 0..self.size ].iter_mut( )
+  }
 }
 
 // /// A Stack
@@ -3,7 +3,7 @@ use std::f32::INFINITY;
 use packed_simd::*;
 // Local imports
 use crate::graphics::ray::{Ray};
-use crate::math::Vec3;
+use crate::math::{Vec3, Mat4};
 
 /// An Axis-Aligned bounding box
 /// Fast intersection with their distance is available
@@ -30,15 +30,19 @@ pub struct AABBx4 {
 }
 
 impl AABB {
-  /// A placeholder AABB. Used for initialising arrays.
+  /// The empty AABB, which contains no points. It is the identity of
+  ///   `join(..)` and `include(..)`, so it is a suitable initial value when
+  ///   accumulating bounds. Also used for initialising arrays.
+  /// Note that it should not be intersected with rays, as its inverted bounds
+  ///   are "hit" by `hit(..)`.
   pub const EMPTY: AABB =
     AABB {
-      x_min: 0.0
-    , y_min: 0.0
-    , z_min: 0.0
-    , x_max: 0.0
-    , y_max: 0.0
-    , z_max: 0.0
+      x_min: INFINITY
+    , y_min: INFINITY
+    , z_min: INFINITY
+    , x_max: -INFINITY
+    , y_max: -INFINITY
+    , z_max: -INFINITY
     };
 
   /// Constructs a new AABB with the provided minimum and maximum corners
@@ -48,6 +52,11 @@ impl AABB {
     AABB { x_min, y_min, z_min, x_max, y_max, z_max }
   }
 
+  /// Constructs the cube with sides of length 1, centered at the origin
+  pub fn unit( ) -> AABB {
+    AABB::new1( -0.5, -0.5, -0.5, 0.5, 0.5, 0.5 )
+  }
+
   pub fn x_size( &self ) -> f32 {
     self.x_max - self.x_min
   }
@@ -60,6 +69,26 @@ impl AABB {
     self.z_max - self.z_min
   }
 
+  /// Returns the index of the axis along which the AABB is largest; 0 for the
+  ///   x-axis, 1 for the y-axis and 2 for the z-axis
+  pub fn longest_axis( &self ) -> usize {
+    let x_size = self.x_size( );
+    let y_size = self.y_size( );
+    let z_size = self.z_size( );
+
+    if x_size > y_size {
+      if x_size > z_size {
+        0
+      } else {
+        2
+      }
+    } else if y_size > z_size {
+      1
+    } else {
+      2
+    }
+  }
+
   pub fn area( &self ) -> f32 {
     let x_size : f32 = self.x_max - self.x_min;
     let y_size : f32 = self.y_max - self.y_min;
@@ -68,6 +97,18 @@ impl AABB {
     x_size * y_size * z_size
   }
 
+  /// Returns the volume of the AABB. Unlike `area()`, this is never negative;
+  ///   inverted boxes (such as `AABB::EMPTY`) have no volume
+  pub fn volume( &self ) -> f32 {
+    self.x_size( ).max( 0.0 ) * self.y_size( ).max( 0.0 ) * self.z_size( ).max( 0.0 )
+  }
+
+  /// True if the AABB encloses no volume. So, both inverted boxes (such as
+  ///   `AABB::EMPTY`) and flat boxes are empty
+  pub fn is_empty( &self ) -> bool {
+    self.x_min >= self.x_max || self.y_min >= self.y_max || self.z_min >= self.z_max
+  }
+
   /// Returns the surface of the AABB
   pub fn surface( &self ) -> f32 {
     let x_size = self.x_max - self.x_min;
@@ -119,38 +160,45 @@ impl AABB {
       && o.z_max <= self.z_max
   }
 
+  /// True if `o` lies in the interior of `self`. Unlike `contains(..)`, no
+  ///   side of `o` may touch the corresponding side of `self`.
+  pub fn strictly_contains( &self, o : &AABB ) -> bool {
+    o.x_min > self.x_min
+      && o.y_min > self.y_min
+      && o.z_min > self.z_min
+      && o.x_max < self.x_max
+      && o.y_max < self.y_max
+      && o.z_max < self.z_max
+  }
+
+  /// True if the boxes share any point. Boxes that only touch overlap.
+  pub fn overlaps( &self, o : &AABB ) -> bool {
+    self.x_min <= o.x_max && o.x_min <= self.x_max
+      && self.y_min <= o.y_max && o.y_min <= self.y_max
+      && self.z_min <= o.z_max && o.z_min <= self.z_max
+  }
+
   /// True if this box contains the point
   pub fn contains_point( &self, o : &Vec3 ) -> bool {
     self.x_min <= o.x && self.y_min <= o.y && self.z_min <= o.z &&
       self.x_max >= o.x && self.y_max >= o.y && self.z_max >= o.z
   }
 
+  /// Returns the Euclidean distance from the point to the box. Points inside
+  ///   the box have distance 0.
+  pub fn dis_to_point( &self, o : &Vec3 ) -> f32 {
+    let dx = ( self.x_min - o.x ).max( o.x - self.x_max ).max( 0.0 );
+    let dy = ( self.y_min - o.y ).max( o.y - self.y_max ).max( 0.0 );
+    let dz = ( self.z_min - o.z ).max( o.z - self.z_max ).max( 0.0 );
+    ( dx * dx + dy * dy + dz * dz ).sqrt( )
+  }
+
   /// Intersects the ray with the box. If it intersects, the minimum positive
   /// distance is returned. If it intersects "before the camera", `None` is
   /// returned. If the ray originates inside the box, then `Some(0.0)` is
   /// returned.
   pub fn hit( &self, ray : &Ray ) -> Option< f32 > {
-    let invdx = ray.inv_dir.x;
-    let invdy = ray.inv_dir.y;
-    let invdz = ray.inv_dir.z;
-
-    // "Clip" the line within the box, along each axis
-    let tx1 = ( self.x_min - ray.origin.x ) * invdx;
-    let tx2 = ( self.x_max - ray.origin.x ) * invdx;
-    let ty1 = ( self.y_min - ray.origin.y ) * invdy;
-    let ty2 = ( self.y_max - ray.origin.y ) * invdy;
-    let tz1 = ( self.z_min - ray.origin.z ) * invdz;
-    let tz2 = ( self.z_max - ray.origin.z ) * invdz;
-
-    let txmin = tx1.min(tx2);
-    let tymin = ty1.min(ty2);
-    let tzmin = tz1.min(tz2);
-    let txmax = tx1.max(tx2);
-    let tymax = ty1.max(ty2);
-    let tzmax = tz1.max(tz2);
-
-    let tmin = txmin.max(tymin).max(tzmin);
-    let tmax = txmax.min(tymax).min(tzmax);
+    let (tmin, tmax) = self.slabs( ray );
 
     if tmin > tmax { // Does not intersect
       None
@@ -166,6 +214,34 @@ impl AABB {
   /// Returns the furthest hit distance of the ray with the AABB.
   /// (As opposed to the closest distance by `AABB::hit(..)`)
   pub fn hit_furthest( &self, ray : &Ray ) -> Option< f32 > {
+    let (tmin, tmax) = self.slabs( ray );
+
+    if tmin > tmax { // Does not intersect
+      None
+    } else if tmax >= 0.0 { // Inside the box
+      Some( tmax )
+    } else { // Box behind camera
+      None
+    }
+  }
+
+  /// Returns the distances at which the ray enters and exits the box. If the
+  /// ray originates inside the box, the entry distance is 0. If the box is not
+  /// hit, or is behind the ray origin, `None` is returned.
+  pub fn hit_interval( &self, ray : &Ray ) -> Option< (f32, f32) > {
+    let (tmin, tmax) = self.slabs( ray );
+
+    if tmin > tmax || tmax < 0.0 { // Does not intersect, or behind camera
+      None
+    } else {
+      Some( ( tmin.max( 0.0 ), tmax ) )
+    }
+  }
+
+  /// The slab test. Returns the distances (along the infinite line) at which
+  /// the ray enters and exits the box. These may be negative. If the first
+  /// exceeds the second, the line does not intersect the box.
+  fn slabs( &self, ray : &Ray ) -> (f32, f32) {
     let invdx = ray.inv_dir.x;
     let invdy = ray.inv_dir.y;
     let invdz = ray.inv_dir.z;
@@ -188,13 +264,22 @@ impl AABB {
     let tmin = txmin.max(tymin).max(tzmin);
     let tmax = txmax.min(tymax).min(tzmax);
 
-    if tmin > tmax { // Does not intersect
-      None
-    } else if tmax >= 0.0 { // Inside the box
-      Some( tmax )
-    } else { // Box behind camera
-      None
+    ( tmin, tmax )
+  }
+
+  /// Returns the AABB around the box after transforming it by the matrix
+  /// This is the AABB around all 8 transformed corners, so it is generally
+  ///   larger than the transformed box itself (e.g. when rotating).
+  pub fn transform( self, mat : &Mat4 ) -> AABB {
+    let mut res = AABB::EMPTY;
+    for &x in &[ self.x_min, self.x_max ] {
+      for &y in &[ self.y_min, self.y_max ] {
+        for &z in &[ self.z_min, self.z_max ] {
+          res = res.include( mat.transform_point( Vec3::new( x, y, z ) ) );
+        }
+      }
     }
+    res
   }
 
   pub fn include( self, v : Vec3 ) -> AABB {
@@ -225,10 +310,10 @@ impl AABBx4 {
   }
 
   /// Returns the AABB around the first `n` AABBs in this structure
+  /// If `n` is 0, `AABB::EMPTY` is returned
   pub fn extract_hull( &self, n : usize ) -> AABB {
-    // assert( n > 0 )
-    let mut hull = self.extract( 0 );
-    for i in 1..n {
+    let mut hull = AABB::EMPTY;
+    for i in 0..n {
       hull = hull.join( &self.extract( i ) );
     }
     hull
@@ -299,3 +384,20 @@ impl AABBx4 {
     // ```
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f32::consts::PI;
+
+  #[test]
+  fn transform_rotated_is_wider( ) {
+    let original = AABB::unit( );
+    let rotated  = original.transform( &Mat4::rotate_y( PI / 4.0 ) );
+
+    assert!( rotated.x_size( ) > original.x_size( ) + 0.1, "{:?}", rotated );
+    assert!( rotated.z_size( ) > original.z_size( ) + 0.1, "{:?}", rotated );
+    // Rotating around the y-axis leaves the height unchanged
+    assert!( ( rotated.y_size( ) - original.y_size( ) ).abs( ) < 1e-5, "{:?}", rotated );
+  }
+}
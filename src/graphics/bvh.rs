@@ -20,6 +20,9 @@ pub struct BVHNode {
 }
 
 impl BVHNode {
+  /// The rope of nodes after which traversal is complete. (See `build_ropes`)
+  pub const NO_ROPE : u32 = u32::MAX;
+
   /// Constructs a new leaf node. A leaf contains `count` shapes in the shapes
   /// array, starting at offset `offset`.
   pub fn leaf( bounds : AABB, offset : u32, count : u32 ) -> BVHNode {
@@ -46,7 +49,16 @@ impl BVHNode {
   ///   The second is the BVH.
   /// The root node is located at index 0 in the array.
   pub fn build( shapes : &mut [Rc< dyn Tracable >], num_bins : usize ) -> (usize, Vec< BVHNode >) {
-    build_bvh( shapes, num_bins )
+    build_bvh( shapes, num_bins, 1 )
+  }
+
+  /// Constructs a 2-way BVH, like `BVHNode::build(..)`. However, any group of
+  ///   at most `max_leaf_size` shapes becomes a leaf, regardless of whether
+  ///   splitting it would improve the SAH utility. (Larger groups are still
+  ///   split by the SAH.) For scenes with many tiny shapes, this reduces the
+  ///   number of nodes.
+  pub fn build_with_config( shapes : &mut [Rc< dyn Tracable >], num_bins : usize, max_leaf_size : usize ) -> (usize, Vec< BVHNode >) {
+    build_bvh( shapes, num_bins, max_leaf_size )
   }
 
   /// Verifies whether the BVH is valid for the shapes
@@ -58,19 +70,48 @@ impl BVHNode {
     verify_bvh( shapes, num_infinite, bvh )
   }
 
+  /// Computes the "rope" (or skip pointer) for every node in the BVH. This is
+  ///   the index of the next node to visit when the node's subtree is skipped
+  ///   or finished. For a leaf this is the next node to visit after the leaf,
+  ///   which allows traversal without backtracking. (See
+  ///   `traverse_bvh_stackless` in `scene.rs`)
+  /// The returned ropes have the same indexing as `bvh`. Nodes after which
+  ///   traversal is complete have rope `NO_ROPE`.
+  /// ASSERT: The BVH must not be empty
+  pub fn build_ropes( bvh : &[BVHNode] ) -> Vec< u32 > {
+    let mut ropes = vec![ BVHNode::NO_ROPE; bvh.len( ) ];
+    // Pairs of (node index, its rope)
+    let mut stack = vec![ ( 0, BVHNode::NO_ROPE ) ];
+
+    while let Some( ( i, rope ) ) = stack.pop( ) {
+      ropes[ i ] = rope;
+
+      let n = &bvh[ i ];
+      if !n.is_leaf( ) {
+        // After the left child, visit the right child. After the right child,
+        // continue where the parent would have continued.
+        let left_index = n.left_first as usize;
+        stack.push( ( left_index, left_index as u32 + 1 ) );
+        stack.push( ( left_index + 1, rope ) );
+      }
+    }
+
+    ropes
+  }
+
   /// Returns the depth of the tree
   /// The depth is the maximum number of edges from the root to any leaf
-  pub fn depth( nodes : &Vec< BVHNode > ) -> u32 {
+  pub fn depth( nodes : &[BVHNode] ) -> u32 {
     bvh_depth( nodes )
   }
 
   /// Returns the number of nodes in the tree (this includes leaves)
-  pub fn node_count( nodes : &Vec< BVHNode > ) -> usize {
+  pub fn node_count( nodes : &[BVHNode] ) -> usize {
     BVHNode::count_node_rec( nodes, 0 )
   }
 
   // Recursively counts the number of nodes in the tree, starting at index `i`.
-  fn count_node_rec( nodes : &Vec< BVHNode >, i : usize ) -> usize {
+  fn count_node_rec( nodes : &[BVHNode], i : usize ) -> usize {
     if nodes[ i ].is_leaf( ) { // leaf node
       1
     } else {
@@ -88,7 +129,11 @@ struct ShapeRep {
   bounds   : AABB
 }
 
-/// Used to initialise "empty" array elements
+/// Used to initialise "empty" array elements. It is also the root when a scene
+///   contains no finite shapes. That root is an *internal* node (as its
+///   `count` is 0) whose first child is itself. As its inverted bounds are
+///   "hit" by rays (See `AABB::EMPTY`), traversing it would never end; so,
+///   such a BVH must not be traversed. (See `Scene::rebuild_bvh(..)`)
 static BVH_PLACEHOLDER: BVHNode =
   BVHNode {
     bounds:     AABB::EMPTY
@@ -100,7 +145,11 @@ static BVH_PLACEHOLDER: BVHNode =
 // Uses O(k * n log n) time, where `k` is the number of bins
 // Returns the number of "infinite" nodes that did not fit in the tree,
 //   together with the BVH tree.
-fn build_bvh( shapes : &mut [Rc< dyn Tracable >], num_bins : usize ) -> (usize, Vec< BVHNode >) {
+//
+// The build is sequential. Subtrees are independent, so they could be built in
+//   parallel; but shapes are shared through `Rc` (which is not `Send`), and the
+//   wasm32 target has no threads to spread the work over.
+fn build_bvh( shapes : &mut [Rc< dyn Tracable >], num_bins : usize, max_leaf_size : usize ) -> (usize, Vec< BVHNode >) {
   let (num_infinite, mut reps) = shape_reps( shapes );
 
   let rep_len = reps.len( );
@@ -114,7 +163,7 @@ fn build_bvh( shapes : &mut [Rc< dyn Tracable >], num_bins : usize ) -> (usize,
   } else {
     let mut tmp_bins = BinResult::new_many( num_bins, rep_len );
     let reps_aabb = aabb( &reps ).unwrap( );
-    dst[0] = subdivide( &mut dst, &mut reps, 0, rep_len, &reps_aabb, &mut tmp_bins );
+    subdivide( &mut dst, &mut reps, 0, 0, rep_len, &reps_aabb, max_leaf_size, &mut tmp_bins );
 
     for i in 0..reps.len( ) {
       shapes[ i + num_infinite ] = reps[ i ].shape.clone( );
@@ -194,12 +243,12 @@ fn verify_bvh_bounds( shapes : &[Rc< dyn Tracable >], num_infinite : usize, bvh
 }
 
 // Returns the depth of the BVH (See `BVHNode::depth(..)`)
-fn bvh_depth( nodes : &Vec< BVHNode > ) -> u32 {
+fn bvh_depth( nodes : &[BVHNode] ) -> u32 {
   depth_rec( nodes, 0 )
 }
 
 // Recursively finds the depth of the BVH rooted in node `i`.
-fn depth_rec( nodes : &Vec< BVHNode >, i : usize ) -> u32 {
+fn depth_rec( nodes : &[BVHNode], i : usize ) -> u32 {
   let n = &nodes[ i ];
   if n.count != 0 { // leaf
     0
@@ -209,31 +258,50 @@ fn depth_rec( nodes : &Vec< BVHNode >, i : usize ) -> u32 {
   }
 }
 
-// Subdivide the region in `shapes` (marked by `offset` and `length`)
-// It splits along the largest axis
+// Subdivide the region in `shapes` (marked by `offset` and `length`), and
+//   stores the resulting subtree in `dst`, with its root at `node_index`
+// It splits along the largest axis. Regions of at most `max_leaf_size` shapes
+//   are never split
 // (Slices are not used, as absolute offsets are stored in the BVH)
+//
+// This uses an explicit work stack instead of recursion, as degenerate inputs
+//   (e.g. many coplanar triangles) may produce very deep trees.
 fn subdivide( dst         : &mut Vec< BVHNode >
             , shapes      : &mut [ShapeRep]
+            , node_index  : usize
             , offset      : usize
             , length      : usize
             , parent_aabb : &AABB
+            , max_leaf_size : usize
               // Storage for the bins that is pre-allocated
             , tmp_bins    : &mut BinResult< ShapeRep >
-            ) -> BVHNode {
-  match split( &mut shapes[offset..(offset+length)], parent_aabb, tmp_bins ) {
-    SplitRes::DoSplit( split_index, l_aabb, r_aabb ) => {
-      let bvh_left_id = dst.len( );
-      dst.push( BVH_PLACEHOLDER );
-      dst.push( BVH_PLACEHOLDER );
-
-      dst[ bvh_left_id + 0 ] = subdivide( dst, shapes, offset, split_index, &l_aabb, tmp_bins );
-      dst[ bvh_left_id + 1 ] = subdivide( dst, shapes, offset + split_index, length - split_index, &r_aabb, tmp_bins );
-
-      BVHNode::node( l_aabb.join( &r_aabb ), bvh_left_id as u32 )
-    },
-    SplitRes::DontSplit( parent_aabb ) => {
-      // Otherwise, don't split and make a leaf for the shapes
-      BVHNode::leaf( parent_aabb, offset as u32, length as u32 )
+            ) {
+  // Elements are (node index, offset, length, parent AABB)
+  let mut work = vec![ ( node_index, offset, length, *parent_aabb ) ];
+
+  while let Some( ( node_index, offset, length, parent_aabb ) ) = work.pop( ) {
+    if length <= max_leaf_size {
+      let leaf_aabb = aabb( &shapes[offset..(offset+length)] ).unwrap( );
+      dst[ node_index ] = BVHNode::leaf( leaf_aabb, offset as u32, length as u32 );
+      continue;
+    }
+
+    match split( &mut shapes[offset..(offset+length)], &parent_aabb, tmp_bins ) {
+      SplitRes::DoSplit( split_index, l_aabb, r_aabb ) => {
+        let bvh_left_id = dst.len( );
+        dst.push( BVH_PLACEHOLDER );
+        dst.push( BVH_PLACEHOLDER );
+
+        dst[ node_index ] = BVHNode::node( l_aabb.join( &r_aabb ), bvh_left_id as u32 );
+
+        // Push the right child first, such that the left subtree is built first
+        work.push( ( bvh_left_id + 1, offset + split_index, length - split_index, r_aabb ) );
+        work.push( ( bvh_left_id + 0, offset, split_index, l_aabb ) );
+      },
+      SplitRes::DontSplit( parent_aabb ) => {
+        // Otherwise, don't split and make a leaf for the shapes
+        dst[ node_index ] = BVHNode::leaf( parent_aabb, offset as u32, length as u32 );
+      }
     }
   }
 }
@@ -285,20 +353,10 @@ fn split_longest_axis(
     , dst_bins    : &mut BinResult< ShapeRep >
     ) -> Option< (AABB, AABB, usize) > {
 
-  let x_size = parent_aabb.x_max - parent_aabb.x_min;
-  let y_size = parent_aabb.y_max - parent_aabb.y_min;
-  let z_size = parent_aabb.z_max - parent_aabb.z_min;
-
-  if x_size > y_size {
-    if x_size > z_size {
-      split_axis( shapes, |s| s.location.x, dst_bins )
-    } else {
-      split_axis( shapes, |s| s.location.z, dst_bins )
-    }
-  } else if y_size > z_size {
-    split_axis( shapes, |s| s.location.y, dst_bins )
-  } else {
-    split_axis( shapes, |s| s.location.z, dst_bins )
+  match parent_aabb.longest_axis( ) {
+    0 => split_axis( shapes, |s| s.location.x, dst_bins ),
+    1 => split_axis( shapes, |s| s.location.y, dst_bins ),
+    _ => split_axis( shapes, |s| s.location.z, dst_bins )
   }
 }
 
@@ -26,11 +26,23 @@ pub struct BVHNode4 {
 }
 
 impl BVHNode4 {
+  /// The largest number of shapes that a leaf can contain, as the shape count
+  ///   is encoded in 4 bits
+  pub const MAX_LEAF_SIZE : usize = 15;
+
   /// Constructs a new internal BVH node
   pub fn node( child_bounds : AABBx4, children : [i32; 4], num_children : u32 ) -> BVHNode4 {
     BVHNode4 { child_bounds, children, num_children }
   }
 
+  /// Decodes a leaf child (which is negative as an `i32`) into the number of
+  ///   shapes it contains, and the index of its first shape.
+  pub fn decode_leaf( leaf : u32 ) -> (usize, usize) {
+    let num_shapes  = ( ( leaf >> 27 ) & 0xF ) as usize;
+    let shape_index = ( leaf & 0x7FFFFFF ) as usize;
+    (num_shapes, shape_index)
+  }
+
   /// Collapses a 2-way BVH into a 4-way BVH.
   /// Each internal node in a 4-way BVH has at most 4 child nodes.
   /// The first element of the produced Vec is the root node in the tree
@@ -69,15 +81,41 @@ impl BVHNode4 {
     dst
   }
 
+  /// Tightens the bounds of all nodes to the current bounds of the shapes,
+  ///   without changing the structure of the tree. This is much cheaper than
+  ///   rebuilding, which makes it suitable for animated scenes. (Though the
+  ///   tree quality degrades when shapes move far)
+  /// The shapes must be in the same order as when the tree was built
+  pub fn refit( bvh : &mut Vec< BVHNode4 >, shapes : &[Rc< dyn Tracable >], num_infinite : usize ) {
+    // Children are always stored after their parent (See `collapse_with(..)`).
+    // So, traversing the nodes in reverse order updates them bottom-up
+    for i in (0..bvh.len( )).rev( ) {
+      let node = bvh[ i ];
+      let mut bounds_box = [ AABB::EMPTY, AABB::EMPTY, AABB::EMPTY, AABB::EMPTY ];
+
+      for (b, &c) in bounds_box.iter_mut( ).zip( &node.children ).take( node.num_children as usize ) {
+        *b =
+          if c < 0 { // leaf
+            leaf_bounds( shapes, num_infinite, c )
+          } else {
+            let child = &bvh[ c as usize ];
+            child.child_bounds.extract_hull( child.num_children as usize )
+          };
+      }
+
+      bvh[ i ].child_bounds = AABBx4::new( bounds_box[ 0 ], bounds_box[ 1 ], bounds_box[ 2 ], bounds_box[ 3 ] );
+    }
+  }
+
   /// Returns the number of nodes that are in the tree
   /// This includes (concisely-represented) leaf nodes
-  pub fn node_count( bvh : &Vec< BVHNode4 > ) -> usize {
+  pub fn node_count( bvh : &[BVHNode4] ) -> usize {
     BVHNode4::node_count_rec( bvh, 0 )
   }
 
   /// Recursively finds the number of nodes in the tree
   /// See `BVH4Node::node_count(..)`
-  fn node_count_rec( bvh : &Vec< BVHNode4 >, i : i32 ) -> usize {
+  fn node_count_rec( bvh : &[BVHNode4], i : i32 ) -> usize {
     if i < 0 { // leaf
       1
     } else {
@@ -91,13 +129,13 @@ impl BVHNode4 {
 
   /// Returns the depth of the tree
   /// The depth is the maximum number of edges from the root to any leaf
-  pub fn depth( bvh : &Vec< BVHNode4 > ) -> usize {
+  pub fn depth( bvh : &[BVHNode4] ) -> usize {
     BVHNode4::depth_rec( bvh, 0 )
   }
 
   /// Recursively finds the tree depth
   /// See `BVH4Node::depth(..)`
-  fn depth_rec( bvh : &Vec< BVHNode4 >, i : i32 ) -> usize {
+  fn depth_rec( bvh : &[BVHNode4], i : i32 ) -> usize {
     if i < 0 { // leaf
       0
     } else {
@@ -133,7 +171,7 @@ fn collapse_with( dst : &mut Vec< BVHNode4 >, bvh : &Vec< BVHNode >, memo : &Vec
   if bvh[ node_i ].is_leaf( ) { // leaf
     // A leaf still has an AABB, but no node in `dst`
     let shape_range = 0x80000000 | ( bvh[ node_i ].count << 27 ) | ( bvh[ node_i ].left_first );
-    vec![ ( bvh[ node_i ].bounds, unsafe { std::mem::transmute::< u32, i32 >( shape_range ) } ) ]
+    vec![ ( bvh[ node_i ].bounds, shape_range as i32 ) ]
   } else {
     let node_left_i  = bvh[ node_i ].left_first as usize;
     let node_right_i = ( node_left_i + 1 ) as usize;
@@ -312,6 +350,17 @@ fn verify_bvh( shapes : &[Rc< dyn Tracable >], num_infinite : usize, bvh : &Vec<
   a && has_all
 }
 
+/// Returns the bounds of all shapes in the leaf `i` (which is negative)
+fn leaf_bounds( shapes : &[Rc< dyn Tracable >], num_infinite : usize, i : i32 ) -> AABB {
+  let (num_shapes, shape_index) = BVHNode4::decode_leaf( i as u32 );
+
+  let mut bounds = AABB::EMPTY;
+  for s in &shapes[ (num_infinite+shape_index)..(num_infinite+shape_index+num_shapes) ] {
+    bounds = bounds.join_maybe( &s.aabb( ) );
+  }
+  bounds
+}
+
 /// Sets `true` in `contained` for each shape that is in the BVH rooted in `i`.
 fn verify_bvh_contains( contained : &mut [bool], bvh : &Vec< BVHNode4 >, i : i32 ) {
   if i >= 0 { // node
@@ -319,8 +368,7 @@ fn verify_bvh_contains( contained : &mut [bool], bvh : &Vec< BVHNode4 >, i : i32
       verify_bvh_contains( contained, bvh, bvh[ i as usize ].children[ j as usize ] );
     }
   } else { // leaf
-    let num_shapes = ( ( unsafe { std::mem::transmute::< i32, u32 >( i ) } >> 27 ) & 0x3 ) as usize;
-    let shape_index = ( unsafe { std::mem::transmute::< i32, u32 >( i ) } & 0x7FFFFFF ) as usize;
+    let (num_shapes, shape_index) = BVHNode4::decode_leaf( i as u32 );
 
     for i in 0..num_shapes {
       contained[ shape_index + i ] = true;
@@ -357,8 +405,7 @@ fn verify_bvh_bounds( shapes : &[Rc< dyn Tracable >], num_infinite : usize, bvh
 
     Some( bounds )
   } else { // leaf
-    let num_shapes = ( ( unsafe { std::mem::transmute::< i32, u32 >( i ) } >> 27 ) & 0x3 ) as usize;
-    let shape_index = ( unsafe { std::mem::transmute::< i32, u32 >( i ) } & 0x7FFFFFF ) as usize;
+    let (num_shapes, shape_index) = BVHNode4::decode_leaf( i as u32 );
 
     let mut cum_bounds = shapes[ num_infinite+shape_index ].aabb( ).unwrap( );
     for i in (num_infinite+shape_index)..(num_infinite+shape_index+num_shapes) {
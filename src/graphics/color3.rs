@@ -43,6 +43,120 @@ impl Color3 {
     Color3::new( v.x, v.y, v.z )
   }
 
+  /// Constructs a new color from the provided Vec3, *without* clamping the
+  ///   channels. This is useful for radiance values, which are only clamped
+  ///   when finally quantised.
+  pub fn from_vec3_unclamped( v : Vec3 ) -> Color3 {
+    Color3 { red: v.x, green: v.y, blue: v.z }
+  }
+
+  /// Linearly interpolates between `a` (at t=0) and `b` (at t=1)
+  pub fn lerp( a : Color3, b : Color3, t : f32 ) -> Color3 {
+    a * ( 1.0 - t ) + b * t
+  }
+
+  /// Constructs a new color from the provided hue (in degrees), saturation,
+  ///   and value. Saturation and value should be within the range [0-1]
+  pub fn from_hsv( h : f32, s : f32, v : f32 ) -> Color3 {
+    let s = clamp( s, 0.0, 1.0 );
+    let v = clamp( v, 0.0, 1.0 );
+
+    // The hue is split into 6 segments of 60 degrees each
+    let h6 = ( h.rem_euclid( 360.0 ) / 60.0 ) % 6.0;
+    let c  = v * s; // chroma
+    let x  = c * ( 1.0 - ( h6 % 2.0 - 1.0 ).abs( ) );
+    let m  = v - c;
+
+    let (r, g, b) =
+      match h6 as u32 {
+        0 => ( c, x, 0.0 ),
+        1 => ( x, c, 0.0 ),
+        2 => ( 0.0, c, x ),
+        3 => ( 0.0, x, c ),
+        4 => ( x, 0.0, c ),
+        _ => ( c, 0.0, x )
+      };
+
+    Color3::new( r + m, g + m, b + m )
+  }
+
+  /// Converts the color to (hue, saturation, value). The hue is in degrees,
+  ///   within the range [0-360). Saturation and value are within [0-1]
+  /// For grays, the hue is 0
+  pub fn to_hsv( self ) -> (f32, f32, f32) {
+    let max   = self.red.max( self.green ).max( self.blue );
+    let min   = self.red.min( self.green ).min( self.blue );
+    let delta = max - min;
+
+    let h =
+      if delta == 0.0 {
+        0.0
+      } else if max == self.red {
+        60.0 * ( ( self.green - self.blue ) / delta ).rem_euclid( 6.0 )
+      } else if max == self.green {
+        60.0 * ( ( self.blue - self.red ) / delta + 2.0 )
+      } else {
+        60.0 * ( ( self.red - self.green ) / delta + 4.0 )
+      };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    ( h, s, max )
+  }
+
+  /// Constructs the color of a black-body radiator at the provided temperature
+  ///   (in Kelvin). The temperature is clamped within the range [1000-20000]
+  /// The result is normalised such that its largest channel is 1
+  ///
+  /// The chromaticity on the Planckian locus is approximated with the cubic
+  ///   splines by Kang et al. (2002). Note that these are designed for
+  ///   temperatures of 1667K and up, and are extrapolated below that.
+  pub fn from_kelvin( temperature : f32 ) -> Color3 {
+    let t  = clamp( temperature, 1000.0, 20000.0 );
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    // CIE 1931 (x,y) chromaticity
+    let x =
+      if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+      } else {
+        -3.0258469e9 / t3 + 2.107038e6 / t2 + 0.2226347e3 / t + 0.240390
+      };
+    let x2 = x * x;
+    let x3 = x2 * x;
+    let y =
+      if t <= 2222.0 {
+        -1.1063814 * x3 - 1.3481102 * x2 + 2.1855583 * x - 0.20219683
+      } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.3741859 * x2 + 2.09137 * x - 0.16748867
+      } else {
+        3.081758 * x3 - 5.873387 * x2 + 3.7511299 * x - 0.37001483
+      };
+
+    // To CIE XYZ (with Y=1), then to linear sRGB
+    let cx = x / y;
+    let cz = ( 1.0 - x - y ) / y;
+
+    let r = (  3.2406 * cx - 1.5372 - 0.4986 * cz ).max( 0.0 );
+    let g = ( -0.9689 * cx + 1.8758 + 0.0415 * cz ).max( 0.0 );
+    let b = (  0.0557 * cx - 0.2040 + 1.0570 * cz ).max( 0.0 );
+
+    let max = r.max( g ).max( b );
+    Color3::new( r / max, g / max, b / max )
+  }
+
+  /// Returns the relative luminance of the color, using the Rec. 709
+  ///   coefficients
+  pub fn luminance( self ) -> f32 {
+    luminance( self.to_vec3( ) )
+  }
+
+  /// Returns the Euclidean distance between the two colors (in RGB-space)
+  /// Unlike with `self - other`, the channel differences are not clamped
+  pub fn dis( self, other : Color3 ) -> f32 {
+    self.to_vec3( ).dis( other.to_vec3( ) )
+  }
+
   // Converts the (r,g,b) channels to a (x,y,z) vector
   // This is convienient when clamped values are undesired
   pub fn to_vec3( self ) -> Vec3 {
@@ -50,6 +164,13 @@ impl Color3 {
   }
 }
 
+/// Returns the relative luminance of the linear RGB value stored in `v`, using
+///   the Rec. 709 coefficients. Unlike `Color3::luminance(..)`, the channels
+///   are not clamped, which is useful for light intensities.
+pub fn luminance( v : Vec3 ) -> f32 {
+  0.2126 * v.x + 0.7152 * v.y + 0.0722 * v.z
+}
+
 /// Multiply a color by a constant: Color3 * f32 = Color3
 impl ops::Mul< f32 > for Color3 {
   type Output = Color3;
@@ -68,6 +189,16 @@ impl ops::Mul< Color3 > for f32 {
   }
 }
 
+/// Elementwise multiplication of two colors: Color3 * Color3 = Color3
+/// This is typically used to modulate light by a surface albedo
+impl ops::Mul< Color3 > for Color3 {
+  type Output = Color3;
+
+  fn mul( self, c : Color3 ) -> Color3 {
+    Color3::new( self.red * c.red, self.green * c.green, self.blue * c.blue )
+  }
+}
+
 /// Elementwise multiplication of a color and a Vec3: Color3 * Vec3 = Color3
 impl ops::Mul< Vec3 > for Color3 {
   type Output = Color3;
@@ -104,6 +235,25 @@ impl ops::Add< Color3 > for Color3 {
   }
 }
 
+/// Subtraction of the channels of two Color3's
+/// Channels that would become negative are clamped to 0
+impl ops::Sub< Color3 > for Color3 {
+  type Output = Color3;
+
+  fn sub( self, v: Color3 ) -> Color3 {
+    Color3::new( self.red - v.red, self.green - v.green, self.blue - v.blue )
+  }
+}
+
+/// Negation of a color. As negative channels are invalid, this is always black
+impl ops::Neg for Color3 {
+  type Output = Color3;
+
+  fn neg( self ) -> Color3 {
+    Color3::BLACK
+  }
+}
+
 impl ops::AddAssign< Color3 > for Color3 {
   fn add_assign( &mut self, v : Color3 ) {
     self.red   = clamp( self.red   + v.red,   0.0_f32, 1.0_f32 );
@@ -111,3 +261,18 @@ impl ops::AddAssign< Color3 > for Color3 {
     self.blue  = clamp( self.blue  + v.blue,  0.0_f32, 1.0_f32 );
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mul_is_element_wise( ) {
+    let c = Color3::new( 0.5, 0.3, 0.7 ) * Color3::new( 0.4, 0.6, 0.2 );
+    let expected = Color3::new( 0.2, 0.18, 0.14 );
+
+    assert!( ( c.red   - expected.red   ).abs( ) < 1e-6, "{:?}", c );
+    assert!( ( c.green - expected.green ).abs( ) < 1e-6, "{:?}", c );
+    assert!( ( c.blue  - expected.blue  ).abs( ) < 1e-6, "{:?}", c );
+  }
+}
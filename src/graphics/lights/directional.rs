@@ -2,6 +2,7 @@ use crate::graphics::Color3;
 use crate::math::Vec3;
 
 /// A directional light
+#[derive(Clone)]
 pub struct DirectionalLight {
   // Direction from the light source to the scene
   // For directional lights this is the same for every point in the scene
@@ -11,6 +11,7 @@ use crate::graphics::Color3;
 
 /// These are the 0-sized lights. Volumetric lights have an `Emissive` material.
 /// A general light class which encapsulates the other available light sources.
+#[derive(Clone)]
 pub enum Light {
   Directional( DirectionalLight ),
   Point( PointLight ),
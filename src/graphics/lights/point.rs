@@ -2,6 +2,7 @@ use crate::graphics::Color3;
 use crate::math::Vec3;
 
 /// A point light
+#[derive(Clone)]
 pub struct PointLight {
   pub location : Vec3,
   // The color of the light source (channels may be greater than 1)
@@ -13,4 +14,12 @@ impl PointLight {
   pub fn new( location : Vec3, color : Color3, strength : f32 ) -> PointLight {
     PointLight { location, color: color.to_vec3( ) * strength }
   }
+
+  /// Picks a point on the light, like `Tracable#pick_random()` does for area
+  ///   lights. As a point light has no surface, this is always its location.
+  /// Returns (point, intensity, area); where the area is 1, such that the
+  ///   intensity is not scaled by it
+  pub fn pick_random( &self ) -> (Vec3, Vec3, f32) {
+    ( self.location, self.color, 1.0 )
+  }
 }
@@ -4,6 +4,7 @@ use crate::math::Vec3;
 /// A spot light
 /// Spot lights always originate in a single point, and shine in a cone toward
 /// a direction
+#[derive(Clone)]
 pub struct SpotLight {
   pub location  : Vec3,
   // The direction it is pointing at
@@ -1,9 +1,10 @@
 // External imports
 use std::fmt;
 use std::f32::consts::PI;
+use std::rc::Rc;
 // Local imports
 use crate::graphics::Color3;
-//use crate::graphics::Texture;
+use crate::graphics::{Texture, MipmapTexture, CheckerTexture};
 use crate::math::{ Vec2, Vec3 };
 use crate::rng::Rng;
 
@@ -15,8 +16,18 @@ use crate::rng::Rng;
 #[derive(Clone)]
 pub enum Material {
   Diffuse { color : Color3 },
+  // A diffuse material whose color varies over the surface
+  DiffuseTexture { texture : Rc< Texture > },
+  // A diffuse material whose color varies over the surface, which is filtered
+  // over the footprint of the ray. (See `Material::evaluate_filtered(..)`)
+  DiffuseMipmap { texture : Rc< MipmapTexture > },
+  // A diffuse material with a procedural checkerboard pattern
+  DiffuseChecker { checker : CheckerTexture },
   // A light source. The intensity over its whole surface
-  Emissive { intensity : Vec3 }
+  Emissive { intensity : Vec3 },
+  // A linear blend of two materials, that are evaluated separately at every
+  // surface point. (See `Material::mix(..)`)
+  Mix { a : Rc< Material >, b : Rc< Material >, t : f32 }
 }
 
 impl Material {
@@ -26,36 +37,123 @@ impl Material {
     Material::Diffuse { color }
   }
 
+  // Constructs a new diffuse material, whose color is obtained from the texture
+  pub fn diffuse_texture( texture : Rc< Texture > ) -> Material {
+    Material::DiffuseTexture { texture }
+  }
+
+  // Constructs a new diffuse material, whose color is obtained from the
+  // mipmapped texture
+  pub fn diffuse_mipmap( texture : Rc< MipmapTexture > ) -> Material {
+    Material::DiffuseMipmap { texture }
+  }
+
+  // Constructs a new diffuse material with a checkerboard pattern of the two
+  // colors. See `CheckerTexture`
+  pub fn diffuse_checker( color_a : Color3, color_b : Color3, scale : f32 ) -> Material {
+    Material::DiffuseChecker { checker: CheckerTexture::new( color_a, color_b, scale ) }
+  }
+
   // Constructs a new emissive material
   pub fn emissive( intensity : Vec3 ) -> Material {
     Material::Emissive { intensity }
   }
 
+  /// Linearly blends the two materials, where `t` in [0,1] is the weight of
+  ///   `b`. Two diffuse or two emissive materials directly blend their color or
+  ///   intensity. Other combinations are blended per surface point, when the
+  ///   material is evaluated. (See `PointMaterial::mix(..)`)
+  pub fn mix( a : Material, b : Material, t : f32 ) -> Material {
+    match (&a, &b) {
+      (Material::Diffuse { color: ca }, Material::Diffuse { color: cb }) =>
+        Material::diffuse( Color3::lerp( *ca, *cb, t ) ),
+      (Material::Emissive { intensity: ia }, Material::Emissive { intensity: ib }) =>
+        Material::emissive( Vec3::lerp( *ia, *ib, t ) ),
+      _ =>
+        Material::Mix { a: Rc::new( a ), b: Rc::new( b ), t }
+    }
+  }
+
   /// Returns true if the material is emissive
   pub fn is_emissive( &self ) -> bool {
     match self {
       Material::Emissive { .. } => true,
+      // Consistent with `PointMaterial::mix(..)`, which picks the dominant
+      // material when their types differ
+      Material::Mix { a, b, t } =>
+        if *t < 0.5 { a.is_emissive( ) } else { b.is_emissive( ) },
       _ => false
     }
   }
 
+  /// Returns the intensity emitted by the material, which is zero if it is not
+  ///   emissive. For a mix, this is consistent with `Material::is_emissive()`
+  ///   and `PointMaterial::mix(..)`.
+  pub fn emission( &self ) -> Vec3 {
+    match self {
+      Material::Emissive { intensity } => *intensity,
+      Material::Mix { a, b, t } =>
+        if a.is_emissive( ) && b.is_emissive( ) {
+          Vec3::lerp( a.emission( ), b.emission( ), *t )
+        } else if *t < 0.5 {
+          a.emission( )
+        } else {
+          b.emission( )
+        },
+      _ => Vec3::ZERO
+    }
+  }
+
   /// Evaluates the material generally to a `PointMaterial` if possible.
   /// If a material cannot be generally evaluated (as they vary per
   ///   surface-point) it returns `None`.
   pub fn evaluate_simple( &self ) -> Option< PointMaterial > {
-    Some( self.evaluate_at( &Vec2::ZERO ) )
+    match self {
+      Material::DiffuseTexture { .. } => None,
+      Material::DiffuseMipmap { .. } => None,
+      Material::DiffuseChecker { .. } => None,
+      Material::Mix { a, b, t } =>
+        match (a.evaluate_simple( ), b.evaluate_simple( )) {
+          (Some( pa ), Some( pb )) => Some( PointMaterial::mix( pa, pb, *t ) ),
+          _ => None
+        },
+      _ => Some( self.evaluate_at( &Vec2::ZERO ) )
+    }
   }
 
   /// The way `Material`s are defined, they can be evaluated at a specific
   ///   point on their 2d-space (which supposedly corresponds to a 3d surface
   ///   point). The produces a `PointMaterial`.
   /// `v` should be within the range (0,1)x(0,1)
-  pub fn evaluate_at( &self, _v : &Vec2 ) -> PointMaterial {
+  pub fn evaluate_at( &self, v : &Vec2 ) -> PointMaterial {
     match self {
       Material::Diffuse { color } =>
         PointMaterial::diffuse( *color ),
+      Material::DiffuseTexture { texture } =>
+        PointMaterial::diffuse( texture.at_bilinear( *v ) ),
+      Material::DiffuseMipmap { texture } =>
+        PointMaterial::diffuse( texture.sample( *v, 0.0 ) ),
+      Material::DiffuseChecker { checker } =>
+        PointMaterial::diffuse( checker.at( *v ) ),
       Material::Emissive { intensity } =>
-        PointMaterial::emissive( *intensity )
+        PointMaterial::emissive( *intensity ),
+      Material::Mix { a, b, t } =>
+        PointMaterial::mix( a.evaluate_at( v ), b.evaluate_at( v ), *t )
+    }
+  }
+
+  /// Evaluates the material at a point, like `Material::evaluate_at(..)`.
+  ///   However, mipmapped textures are filtered over the `footprint`, which
+  ///   is the width of the ray (in the (0,1)x(0,1) space) at the surface.
+  ///   This avoids Moire patterns on distant surfaces, or at grazing angles.
+  pub fn evaluate_filtered( &self, v : &Vec2, footprint : f32 ) -> PointMaterial {
+    match self {
+      Material::DiffuseMipmap { texture } =>
+        PointMaterial::diffuse( texture.sample( *v, texture.footprint_level( footprint ) ) ),
+      Material::Mix { a, b, t } =>
+        PointMaterial::mix( a.evaluate_filtered( v, footprint ), b.evaluate_filtered( v, footprint ), *t ),
+      _ =>
+        self.evaluate_at( v )
     }
   }
 }
@@ -71,7 +169,13 @@ pub enum PointMaterial {
   /// See `Material::Diffuse`
   Diffuse { color : Color3 },
   /// See `Material::Refract`
-  Emissive { intensity : Vec3 }
+  Emissive { intensity : Vec3 },
+  /// A scattering event inside a participating medium (See `Volume`). Light
+  ///   is scattered by the Henyey-Greenstein phase function, where `phase_g`
+  ///   in (-1,1) is its asymmetry; positive values scatter forward, negative
+  ///   values scatter backward and 0 scatters uniformly. The `albedo` is the
+  ///   fraction of light that is not absorbed.
+  Volume { albedo : Vec3, phase_g : f32 }
 }
 
 impl PointMaterial {
@@ -85,6 +189,28 @@ impl PointMaterial {
     PointMaterial::Emissive { intensity }
   }
 
+  /// See `PointMaterial::Volume`
+  pub fn volume( albedo : Vec3, phase_g : f32 ) -> PointMaterial {
+    PointMaterial::Volume { albedo, phase_g }
+  }
+
+  /// Linearly blends the two point materials, where `t` in [0,1] is the
+  ///   weight of `b`. Materials of the same type blend their parameters. As
+  ///   materials of different types cannot be blended, the one with the
+  ///   greatest weight is returned for those.
+  pub fn mix( a : PointMaterial, b : PointMaterial, t : f32 ) -> PointMaterial {
+    match (a, b) {
+      (PointMaterial::Diffuse { color: ca }, PointMaterial::Diffuse { color: cb }) =>
+        PointMaterial::diffuse( Color3::lerp( ca, cb, t ) ),
+      (PointMaterial::Emissive { intensity: ia }, PointMaterial::Emissive { intensity: ib }) =>
+        PointMaterial::emissive( Vec3::lerp( ia, ib, t ) ),
+      (PointMaterial::Volume { albedo: aa, phase_g: ga }, PointMaterial::Volume { albedo: ab, phase_g: gb }) =>
+        PointMaterial::volume( Vec3::lerp( aa, ab, t ), ga + ( gb - ga ) * t ),
+      _ =>
+        if t < 0.5 { a } else { b }
+    }
+  }
+
   pub fn is_diffuse( &self ) -> bool {
     match self {
       PointMaterial::Diffuse { .. } => true,
@@ -94,7 +220,7 @@ impl PointMaterial {
 
   /// Returns a random outgoing direction `wi`, together with the probability
   /// of obtaining that direction
-  pub fn sample_hemisphere( &self, rng : &mut Rng, _wo : &Vec3, normal : &Vec3 ) -> (Vec3, f32) {
+  pub fn sample_hemisphere( &self, rng : &mut Rng, wo : &Vec3, normal : &Vec3 ) -> (Vec3, f32) {
     match self {
       PointMaterial::Diffuse { .. } => {
         // Diffuse
@@ -113,14 +239,47 @@ impl PointMaterial {
     
         ( wi, wi.dot( *normal ) / PI )
       },
+      PointMaterial::Volume { phase_g, .. } => {
+        // Samples the Henyey-Greenstein phase function around the direction
+        // of the incoming ray. Inside volumes there is no normal.
+        let g   = *phase_g;
+        let dir = -*wo;
+        let r1  = rng.next( );
+        let r2  = rng.next( );
+
+        let cos_theta =
+          if g.abs( ) < 0.001 {
+            1.0 - 2.0 * r1
+          } else {
+            let s = ( 1.0 - g * g ) / ( 1.0 - g + 2.0 * g * r1 );
+            ( ( 1.0 + g * g - s * s ) / ( 2.0 * g ) ).max( -1.0 ).min( 1.0 )
+          };
+        let sin_theta = ( 1.0 - cos_theta * cos_theta ).max( 0.0 ).sqrt( );
+        let phi       = 2.0 * PI * r2;
+
+        let x_dir = dir.orthogonal( );
+        let z_dir = dir.cross( x_dir );
+
+        let wi = ( cos_theta * dir + sin_theta * ( phi.cos( ) * x_dir + phi.sin( ) * z_dir ) ).normalize( );
+
+        ( wi, henyey_greenstein( cos_theta, g ) )
+      },
       PointMaterial::Emissive { .. } => panic!( "Light source" )
     }
   }
 
-  pub fn brdf( &self, _normal : &Vec3, _wo : &Vec3, _wi : &Vec3 ) -> Color3 {
+  /// Evaluates the BRDF for the incoming direction `wi` and outgoing direction
+  ///   `wo`. For volumes, this is the phase function scaled by the albedo
+  ///   (which ignores the normal).
+  pub fn brdf( &self, _normal : &Vec3, wo : &Vec3, wi : &Vec3 ) -> Color3 {
     match self {
       PointMaterial::Diffuse { color } =>
         (*color) / PI,
+      PointMaterial::Volume { albedo, phase_g } => {
+        // The angle between the direction of the incoming ray and `wi`
+        let cos_theta = ( -*wo ).dot( *wi );
+        Color3::from_vec3_unclamped( *albedo * henyey_greenstein( cos_theta, *phase_g ) )
+      },
       PointMaterial::Emissive { .. } => panic!( "Light source" )
     }
   }
@@ -130,12 +289,22 @@ impl PointMaterial {
     match self {
       PointMaterial::Diffuse { color } =>
         *color,
+      PointMaterial::Volume { albedo, .. } =>
+        Color3::from_vec3( *albedo ),
       PointMaterial::Emissive { intensity } =>
         Color3::from_vec3( intensity.normalize( ) )
     }
   }
 }
 
+/// Evaluates the Henyey-Greenstein phase function, for the cosine of the angle
+///   between the incoming and outgoing directions of the light and the
+///   asymmetry `g`. It integrates to 1 over the sphere.
+fn henyey_greenstein( cos_theta : f32, g : f32 ) -> f32 {
+  let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+  ( 1.0 - g * g ) / ( 4.0 * PI * denom * denom.sqrt( ) )
+}
+
 /// Nicely prints a Material for debugging
 /// Note that not all elements are printed in all cases. When no Phong components
 ///   are printed, it may be assumed they are absent.
@@ -145,8 +314,20 @@ impl fmt::Debug for Material {
       Material::Diffuse { color } => {
         write!( f, "Material::Diffuse {{ color: {:?} }}", color )
       },
+      Material::DiffuseTexture { texture } => {
+        write!( f, "Material::DiffuseTexture {{ texture: {:?} }}", texture )
+      },
+      Material::DiffuseMipmap { texture } => {
+        write!( f, "Material::DiffuseMipmap {{ texture: {:?} }}", texture )
+      },
+      Material::DiffuseChecker { checker } => {
+        write!( f, "Material::DiffuseChecker {{ checker: {:?} }}", checker )
+      },
       Material::Emissive { intensity } => {
         write!( f, "Material::Emissive {{ intensity: {:?} }}", intensity )
+      },
+      Material::Mix { a, b, t } => {
+        write!( f, "Material::Mix {{ a: {:?}, b: {:?}, t: {} }}", a, b, t )
       }
     }
   }
@@ -1,12 +1,16 @@
 // External imports
 use std::rc::Rc;
 // Local imports
-use crate::math::{Vec3};
+use crate::math::{Vec2, Vec3};
 use crate::graphics::ray::{ Tracable };
 
 /// A 3D mesh
 pub enum Mesh {
-  Preload( Vec< Vec3 > ),
+  // The vertices, and optionally their UV-coordinates. Every 3 consecutive
+  // vertices form a triangle. If the mesh has normals, the 3 vertices of each
+  // triangle are followed by their 3 normals (so the normals are stored
+  // interleaved among the vertices)
+  Preload( Vec< Vec3 >, Option< Vec< Vec2 > > ),
   // After loading, put the triangles into Rc boxes
   // This avoids having to do this upon scene construction
   Triangled( Vec< Rc< dyn Tracable > > )
@@ -12,12 +12,12 @@ mod bvh;
 mod bvh4;
 mod sampling_strategy;
 
-pub use color3::Color3;
+pub use color3::{Color3, luminance};
 pub use material::{Material, PointMaterial};
-pub use scene::{Scene, LightEnum};
+pub use scene::{Scene, SceneBuilder, LightEnum};
 pub use mesh::{Mesh};
-pub use texture::{Texture};
+pub use texture::{Texture, HdrTexture, MipmapTexture, CheckerTexture};
 pub use aabb::{AABB, AABBx4};
 pub use bvh::{BVHNode};
 pub use bvh4::{BVHNode4};
-pub use sampling_strategy::{SamplingStrategy, RandomSamplingStrategy, AdaptiveSamplingStrategy};
+pub use sampling_strategy::{SamplingStrategy, SamplingStats, RandomSamplingStrategy, AdaptiveSamplingStrategy};
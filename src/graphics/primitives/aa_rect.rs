@@ -2,6 +2,7 @@ use crate::math::{Vec2, Vec3};
 use crate::graphics::Material;
 use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
 use crate::graphics::AABB;
+use crate::rng::Rng;
 
 /// An axis-aligned box
 #[derive(Debug, Clone)]
@@ -67,11 +68,57 @@ impl Tracable for AARect {
     self.mat.is_emissive( )
   }
 
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    let x_size = self.x_max - self.x_min;
+    let y_size = self.y_max - self.y_min;
+    let z_size = self.z_max - self.z_min;
+
+    Some( 2.0 * ( x_size * y_size + x_size * z_size + y_size * z_size ) )
+  }
+
+  /// See `Tracable#pick_random()`
+  /// Note: Returns (point, normal, intensity)
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    let x_size = self.x_max - self.x_min;
+    let y_size = self.y_max - self.y_min;
+    let z_size = self.z_max - self.z_min;
+
+    // Pick a pair of opposing faces proportional to their area, and then
+    // either face of that pair
+    let yz_area = y_size * z_size;
+    let xz_area = x_size * z_size;
+    let r = rng.next( ) * ( yz_area + xz_area + x_size * y_size );
+    let is_max = rng.next( ) < 0.5;
+
+    // A uniformly random point in the box. One coordinate is then moved onto
+    // the picked face
+    let mut p_hit = Vec3::new(
+        self.x_min + rng.next( ) * x_size
+      , self.y_min + rng.next( ) * y_size
+      , self.z_min + rng.next( ) * z_size
+      );
+    let n;
+
+    if r < yz_area {
+      p_hit.x = if is_max { self.x_max } else { self.x_min };
+      n = Vec3::new( if is_max { 1.0 } else { -1.0 }, 0.0, 0.0 );
+    } else if r < yz_area + xz_area {
+      p_hit.y = if is_max { self.y_max } else { self.y_min };
+      n = Vec3::new( 0.0, if is_max { 1.0 } else { -1.0 }, 0.0 );
+    } else {
+      p_hit.z = if is_max { self.z_max } else { self.z_min };
+      n = Vec3::new( 0.0, 0.0, if is_max { 1.0 } else { -1.0 } );
+    }
+
+    Some( (p_hit, n, self.mat.emission( )) )
+  }
+
   /// See `Tracable::trace()`
   fn trace( &self, ray: &Ray ) -> Option< Hit > {
-    let invdx = 1.0 / ray.dir.x;
-    let invdy = 1.0 / ray.dir.y;
-    let invdz = 1.0 / ray.dir.z;
+    let invdx = ray.inv_dir.x;
+    let invdy = ray.inv_dir.y;
+    let invdz = ray.inv_dir.z;
 
     // "Clip" the line within the box, along each axis
     let tx1 = ( self.x_min - ray.origin.x ) * invdx;
@@ -140,9 +187,9 @@ impl Tracable for AARect {
 
   /// See `Tracable::trace_simple()`
   fn trace_simple( &self, ray : &Ray ) -> Option< f32 > {
-    let invdx = 1.0 / ray.dir.x;
-    let invdy = 1.0 / ray.dir.y;
-    let invdz = 1.0 / ray.dir.z;
+    let invdx = ray.inv_dir.x;
+    let invdy = ray.inv_dir.y;
+    let invdz = ray.inv_dir.z;
 
     // "Clip" the line within the box, along each axis
     let tx1 = ( self.x_min - ray.origin.x ) * invdx;
@@ -0,0 +1,85 @@
+// External imports
+use std::rc::Rc;
+use std::fmt;
+// Local imports
+use crate::math::{Vec3, Mat4};
+use crate::graphics::{Scene, AABB};
+use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
+use crate::rng::Rng;
+
+/// A transformed copy of a (shared) scene, which is traced through the BVH of
+///   that scene. This allows placing many copies of a mesh, while building its
+///   BVH only once.
+/// The transformation must be *rigid* (so only rotations and translations),
+///   as distances along the rays are assumed to be preserved.
+#[derive(Clone)]
+pub struct Instance {
+  scene     : Rc< Scene >,
+  // Object space to world space
+  transform : Mat4,
+  // World space to object space
+  inverse   : Mat4
+}
+
+impl Instance {
+  /// Constructs a new instance of the scene, which is first rotated around the
+  ///   z-axis, then the x-axis, then the y-axis, and then translated by
+  ///   `translation`. (The angles are in radians)
+  pub fn new( scene : Rc< Scene >, translation : Vec3, rot_x : f32, rot_y : f32, rot_z : f32 ) -> Instance {
+    let transform =
+      Mat4::translate( translation ) * Mat4::rotate_y( rot_y ) * Mat4::rotate_x( rot_x ) * Mat4::rotate_z( rot_z );
+    // A rigid transformation is never singular
+    let inverse = transform.inverse( ).unwrap( );
+    Instance { scene, transform, inverse }
+  }
+}
+
+impl Bounded for Instance {
+  /// See `Bounded::aabb()`
+  fn aabb( &self ) -> Option< AABB > {
+    // Infinite shapes in the scene make the instance infinite
+    if self.scene.shapes.iter( ).any( |s| s.aabb( ).is_none( ) ) {
+      None
+    } else {
+      self.scene.bounds( ).map( |b| b.transform( &self.transform ) )
+    }
+  }
+}
+
+impl Tracable for Instance {
+  /// See `Tracable::is_emissive()`
+  /// The shapes of an instance are never used as area lights
+  fn is_emissive( &self ) -> bool {
+    false
+  }
+
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    None
+  }
+
+  /// See `Tracable#pick_random()`
+  fn pick_random( &self, _rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    None
+  }
+
+  /// See `Tracable::trace()`
+  fn trace( &self, ray : &Ray ) -> Option< Hit > {
+    let (_, m_hit) = self.scene.trace( &ray.transform( &self.inverse ) );
+    m_hit.map( |mut hit| {
+      hit.normal = self.transform.transform_dir( hit.normal ).normalize( );
+      hit
+    } )
+  }
+
+  /// See `Tracable::trace_simple()`
+  fn trace_simple( &self, ray : &Ray ) -> Option< f32 > {
+    self.scene.trace_simple( &ray.transform( &self.inverse ) ).1
+  }
+}
+
+impl fmt::Debug for Instance {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!( f, "Instance {{ num_shapes: {}, transform: {:?} }}", self.scene.shapes.len( ), self.transform )
+  }
+}
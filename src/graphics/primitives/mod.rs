@@ -1,13 +1,17 @@
 mod aa_rect;
+mod instance;
 mod plane;
 mod sphere;
 mod square;
 mod torus;
 mod triangle;
+mod volume;
 
 pub use aa_rect::AARect;
+pub use instance::Instance;
 pub use plane::Plane;
 pub use sphere::Sphere;
 pub use square::Square;
 pub use torus::Torus;
-pub use triangle::Triangle;
+pub use triangle::{Triangle, SmoothTriangle};
+pub use volume::Volume;
@@ -1,21 +1,47 @@
-use crate::math::{Vec2, Vec3};
+use crate::math::{Vec2, Vec3, EPSILON};
 use crate::graphics::Material;
 use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
 use crate::graphics::AABB;
+use crate::rng::Rng;
 
 /// An infinite plane in 3d
 ///
 /// It is characterised by a location and normal
+/// For texturing, its surface is mapped to UV-space along `tangent_u` and the
+///   direction orthogonal to both it and the normal. Every `1/uv_scale` units
+///   along those directions the UV-space repeats.
 #[derive(Debug, Clone)]
 pub struct Plane {
-  location : Vec3,
-  normal   : Vec3,
-  mat      : Material
+  location  : Vec3,
+  normal    : Vec3,
+  tangent_u : Vec3,
+  uv_scale  : f32,
+  mat       : Material
 }
 
 impl Plane {
+  /// Constructs a new plane. Its UV-mapping is along the x-axis (if possible),
+  ///   with a scale of 1. See `Plane::with_uv(..)`
   pub fn new( location : Vec3, normal : Vec3, mat : Material ) -> Plane {
-    Plane { location, normal, mat }
+    Plane { location, normal, tangent_u: Vec3::ZERO, uv_scale: 1.0, mat }
+      .with_uv( Vec3::new( 1.0, 0.0, 0.0 ), 1.0 )
+  }
+
+  /// Returns the plane with the provided UV-mapping. The tangent is projected
+  ///   onto the plane. If it is parallel to the normal, some other tangent is
+  ///   chosen.
+  pub fn with_uv( mut self, tangent_u : Vec3, uv_scale : f32 ) -> Plane {
+    let n = self.normal.normalize( );
+    let t = tangent_u - n * tangent_u.dot( n );
+
+    self.tangent_u =
+      if t.len_sq( ) < EPSILON {
+        n.orthogonal( )
+      } else {
+        t.normalize( )
+      };
+    self.uv_scale = uv_scale;
+    self
   }
 }
 
@@ -38,6 +64,17 @@ impl Tracable for Plane {
   fn is_emissive( &self ) -> bool {
     self.mat.is_emissive( )
   }
+
+  /// See `Tracable::surface_area()`
+  /// Planes are infinite, and thus are never used as area lights
+  fn surface_area( &self ) -> Option< f32 > {
+    None
+  }
+
+  /// See `Tracable#pick_random()`
+  fn pick_random( &self, _rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    None
+  }
   
   /// See `Tracable::trace()`
   /// Copied and adjusted from BSc ray-tracer:
@@ -69,8 +106,13 @@ impl Tracable for Plane {
       if let Some( v ) = self.mat.evaluate_simple( ) {
         v
       } else {
-        // TODO: UV mapping
-        self.mat.evaluate_at( &Vec2::ZERO )
+        let d = ray.at( t ) - self.location;
+        let u = d.dot( self.tangent_u ) * self.uv_scale;
+        let v = d.dot( self.normal.cross( self.tangent_u ) ) * self.uv_scale;
+        // At grazing angles, the ray covers a larger part of the surface
+        let cos = ( n_dot_dir / self.normal.len( ) ).abs( ).max( EPSILON );
+        let footprint = ray.width_at( t ) / cos * self.uv_scale;
+        self.mat.evaluate_filtered( &Vec2::new( u - u.floor( ), v - v.floor( ) ), footprint )
       };
     
     Some( Hit::new( t, normal, mat, true ) )
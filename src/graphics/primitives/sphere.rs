@@ -1,9 +1,10 @@
 // External imports
 use std::f32::consts::PI;
 // Local imports
-use crate::math::{Vec2, Vec3};
+use crate::math::{Vec2, Vec3, EPSILON};
 use crate::graphics::{Material, AABB};
 use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
+use crate::rng::Rng;
 
 /// A Sphere primitive
 #[derive(Debug, Clone)]
@@ -44,6 +45,25 @@ impl Tracable for Sphere {
   fn is_emissive( &self ) -> bool {
     self.mat.is_emissive( )
   }
+
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    Some( 4.0 * PI * self.radius * self.radius )
+  }
+
+  /// See `Tracable#pick_random()`
+  /// Note: Returns (point, normal, intensity)
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    // Uniformly picks a point on the sphere. The cosine of `theta` (rather
+    // than `theta` itself) is uniform, as the poles are otherwise too likely
+    let theta = ( 1.0 - 2.0 * rng.next( ) ).acos( );
+    let phi   = 2.0 * PI * rng.next( );
+
+    let n     = Vec3::from_spherical( theta, phi );
+    let p_hit = self.location + n * self.radius;
+
+    Some( (p_hit, n, self.mat.emission( )) )
+  }
   
   /// See `Tracable::trace()`
   fn trace( &self, ray : &Ray ) -> Option< Hit > {
@@ -85,9 +105,14 @@ impl Tracable for Sphere {
       if let Some( v ) = self.mat.evaluate_simple( ) {
         v
       } else {
-        let u = 0.5 + normal.z.atan2( normal.x ) / ( 2.0 * PI );
-        let v = 0.5 - normal.y.asin( ) / PI;
-        self.mat.evaluate_at( &Vec2::new( u, v ) )
+        let (theta, phi) = normal.to_spherical( );
+        let u = 0.5 + phi / ( 2.0 * PI );
+        let v = theta / PI;
+        // At grazing angles, the ray covers a larger part of the surface. The
+        // u-axis wraps around the circumference
+        let cos = normal.dot( ray.dir ).abs( ).max( EPSILON );
+        let footprint = ray.width_at( t ) / cos / ( 2.0 * PI * self.radius );
+        self.mat.evaluate_filtered( &Vec2::new( u, v ), footprint )
       };
 
     normal =
@@ -3,6 +3,7 @@ use crate::math::{Vec2, Vec3};
 use crate::graphics::Material;
 use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
 use crate::graphics::AABB;
+use crate::rng::Rng;
 
 /// A finite square plane in 3d
 /// For now, its normal always points upward
@@ -51,6 +52,31 @@ impl Tracable for Square {
   fn is_emissive( &self ) -> bool {
     self.mat.is_emissive( )
   }
+
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    Some( self.size * self.size )
+  }
+
+  /// See `Tracable#pick_random()`
+  /// Note: Returns (point, normal, intensity)
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    let p_hit = Vec3::new(
+        self.location.x + ( rng.next( ) - 0.5 ) * self.size
+      , self.location.y
+      , self.location.z + ( rng.next( ) - 0.5 ) * self.size
+      );
+
+    // The square is visible from both sides
+    let n =
+      if rng.next( ) < 0.5 {
+        Vec3::new( 0.0, 1.0, 0.0 )
+      } else {
+        Vec3::new( 0.0, -1.0, 0.0 )
+      };
+
+    Some( (p_hit, n, self.mat.emission( )) )
+  }
   
   /// See `Tracable::trace()`
   fn trace( &self, ray: &Ray ) -> Option< Hit > {
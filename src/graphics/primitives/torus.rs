@@ -1,10 +1,12 @@
 // External imports
+use std::f32::consts::PI;
 use roots::{find_roots_quartic, Roots, FloatType};
 // Local imports
 use crate::math::{Vec2, Vec3};
 use crate::graphics::Material;
 use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
 use crate::graphics::AABB;
+use crate::rng::Rng;
 
 // A torus that lies flat; that is, its gap lies in the x/z-plane
 #[derive(Debug, Clone)]
@@ -20,45 +22,25 @@ impl Torus {
   pub fn new( location : Vec3, big_r : f32, small_r : f32, mat : Material ) -> Torus {
     Torus { location, big_r, small_r, mat }
   }
-}
-
-impl Bounded for Torus {
-  /// See `Bounded::location()`
-  fn location( &self ) -> Option< Vec3 > {
-    Some( self.location )
-  }
 
-  /// See `Bounded::aabb()`
-  fn aabb( &self ) -> Option< AABB > {
+  /// Returns true if the ray certainly misses the sphere that bounds the torus
+  /// Testing this is far cheaper than solving the quartic
+  fn misses_bounding_sphere( &self, ray : &Ray ) -> bool {
     let r = self.big_r + self.small_r;
+    let d = ray.origin - self.location;
 
-    let x_min = self.location.x - r;
-    let x_max = self.location.x + r;
-    let y_min = self.location.y - self.small_r;
-    let y_max = self.location.y + self.small_r;
-    let z_min = self.location.z - r;
-    let z_max = self.location.z + r;
+    let b = d.dot( ray.dir );
+    let c = d.dot( d ) - r * r;
 
-    Some( AABB::new1(
-        x_min
-      , y_min
-      , z_min
-      , x_max
-      , y_max
-      , z_max
-      )
-    )
+    // The ray starts outside the sphere, and either points away from it or
+    // passes beside it
+    c > 0.0 && ( b > 0.0 || b * b < c )
   }
-}
 
-impl Tracable for Torus {
-  /// See `Tracable::is_emissive()`
-  fn is_emissive( &self ) -> bool {
-    self.mat.is_emissive( )
-  }
-  
-  /// See `Tracable::trace()`
-  fn trace( &self, ray: &Ray ) -> Option< Hit > {
+  /// Finds the smallest positive distance along the ray at which it intersects
+  ///   the torus. Secondly, returns the number of positive roots; when odd, the
+  ///   ray originates inside the torus.
+  fn closest_root( &self, ray : &Ray ) -> Option< (f64, usize) > {
     // The torus formula is defined as (where A=big_r and B=small_r):
     // (x^2 + y^2 + z^2 + A^2 - B^2) = 4A^2 * (x^2 + y^2)
     // This is then solved for `t`:
@@ -73,6 +55,11 @@ impl Tracable for Torus {
     // which is ugly.
     // "Grainy tori are ugly." -Dennis
 
+    // Most rays miss the torus entirely
+    if self.misses_bounding_sphere( ray ) {
+      return None;
+    }
+
     let a = self.big_r as f64;
     let b = self.small_r as f64;
     let d = ray.origin - self.location;
@@ -109,6 +96,88 @@ impl Tracable for Torus {
       for i in 1..num_roots {
         closest = closest.min( dst_roots[ i ] );
       }
+      Some( (closest, num_roots) )
+    }
+  }
+}
+
+impl Bounded for Torus {
+  /// See `Bounded::location()`
+  fn location( &self ) -> Option< Vec3 > {
+    Some( self.location )
+  }
+
+  /// See `Bounded::aabb()`
+  fn aabb( &self ) -> Option< AABB > {
+    let r = self.big_r + self.small_r;
+
+    let x_min = self.location.x - r;
+    let x_max = self.location.x + r;
+    let y_min = self.location.y - self.small_r;
+    let y_max = self.location.y + self.small_r;
+    let z_min = self.location.z - r;
+    let z_max = self.location.z + r;
+
+    Some( AABB::new1(
+        x_min
+      , y_min
+      , z_min
+      , x_max
+      , y_max
+      , z_max
+      )
+    )
+  }
+}
+
+impl Tracable for Torus {
+  /// See `Tracable::is_emissive()`
+  fn is_emissive( &self ) -> bool {
+    self.mat.is_emissive( )
+  }
+
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    Some( 4.0 * PI * PI * self.big_r * self.small_r )
+  }
+
+  /// See `Tracable#pick_random()`
+  /// Note: Returns (point, normal, intensity)
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    // The angle `phi` around the tube is not uniform over the surface, as the
+    // outside of the tube has more area than its inside. So, rejection-sample
+    // it, proportional to the distance from the torus' axis.
+    let phi = loop {
+      let phi = 2.0 * PI * rng.next( );
+      let r = ( self.big_r + self.small_r * phi.cos( ) ) / ( self.big_r + self.small_r );
+      if rng.next( ) <= r {
+        break phi;
+      }
+    };
+    let theta = 2.0 * PI * rng.next( );
+
+    let n = Vec3::new( phi.cos( ) * theta.cos( ), phi.sin( ), phi.cos( ) * theta.sin( ) );
+    let p_hit =
+      self.location + Vec3::new( theta.cos( ), 0.0, theta.sin( ) ) * self.big_r + n * self.small_r;
+
+    Some( (p_hit, n, self.mat.emission( )) )
+  }
+  
+  /// See `Tracable::trace_simple()`
+  fn trace_simple( &self, ray : &Ray ) -> Option< f32 > {
+    if let Some( (closest, _) ) = self.closest_root( ray ) {
+      Some( closest as f32 )
+    } else {
+      None
+    }
+  }
+
+  /// See `Tracable::trace()`
+  fn trace( &self, ray: &Ray ) -> Option< Hit > {
+    if let Some( (closest, num_roots) ) = self.closest_root( ray ) {
+      let a = self.big_r as f64;
+      let d = ray.origin - self.location;
+      let e = ray.dir;
 
       let px = d.x as f64 + e.x as f64 * closest;
       let py = d.y as f64 + e.y as f64 * closest;
@@ -122,6 +191,8 @@ impl Tracable for Torus {
       } else { // Outside the torus
         Some( Hit::new( closest as f32, n, self.mat.evaluate_at( &Vec2::ZERO ), true ) )
       }
+    } else {
+      None
     }
   }
 }
@@ -7,23 +7,84 @@ use crate::rng::Rng;
 
 /// A triangle in 3-dimensional space
 /// It's normal is inferred from the plane between the vertices
+/// Every vertex has a UV-coordinate, which are interpolated over the surface
 #[derive(Debug, Clone)]
 pub struct Triangle {
   v0  : Vec3,
   v1  : Vec3,
   v2  : Vec3,
+  uv0 : Vec2,
+  uv1 : Vec2,
+  uv2 : Vec2,
   mat : Material
 }
 
 impl Triangle {
   /// Constructs a new triangle with the provided vertices
+  /// Its vertices are mapped to UV-coordinates (0,0), (1,0) and (0,1)
   pub fn new( v0 : Vec3, v1 : Vec3, v2 : Vec3, mat : Material ) -> Triangle {
-    Triangle { v0, v1, v2, mat }
+    Triangle {
+      v0, v1, v2
+    , uv0: Vec2::new( 0.0, 0.0 )
+    , uv1: Vec2::new( 1.0, 0.0 )
+    , uv2: Vec2::new( 0.0, 1.0 )
+    , mat
+    }
+  }
+
+  /// Returns the triangle with the provided UV-coordinates for its vertices
+  pub fn with_uvs( mut self, uv0 : Vec2, uv1 : Vec2, uv2 : Vec2 ) -> Triangle {
+    self.uv0 = uv0;
+    self.uv1 = uv1;
+    self.uv2 = uv2;
+    self
   }
 
   /// Translates the triangle by the provided vector
-  pub fn translate( self, v : Vec3 ) -> Triangle {
-    Triangle::new( self.v0 + v, self.v1 + v, self.v2 + v, self.mat )
+  pub fn translate( mut self, v : Vec3 ) -> Triangle {
+    self.v0 += v;
+    self.v1 += v;
+    self.v2 += v;
+    self
+  }
+
+  /// Scales the triangle by the provided factor (relative to the origin)
+  pub fn scale( self, factor : f32 ) -> Triangle {
+    self.scale_nonuniform( factor, factor, factor )
+  }
+
+  /// Scales the triangle by the provided factor per axis (relative to the
+  ///   origin). The normal is inferred from the scaled vertices, so it remains
+  ///   valid.
+  pub fn scale_nonuniform( mut self, sx : f32, sy : f32, sz : f32 ) -> Triangle {
+    let s = Vec3::new( sx, sy, sz );
+    self.v0 = self.v0 * s;
+    self.v1 = self.v1 * s;
+    self.v2 = self.v2 * s;
+    self
+  }
+
+  /// Returns the barycentric coordinates (w0,w1,w2) of point `p` on the
+  ///   triangle, which are the weights of the respective vertices.
+  /// They are the ratios of the areas of the sub-triangles opposite to each
+  ///   vertex. These areas are obtained through the cross-product, where `n`
+  ///   is the *unnormalised* triangle normal.
+  fn barycentric( &self, n : Vec3, p : Vec3 ) -> (f32, f32, f32) {
+    let area_inv = 1.0 / n.dot( n );
+    let w0 = n.dot( ( self.v2 - self.v1 ).cross( p - self.v1 ) ) * area_inv;
+    let w1 = n.dot( ( self.v0 - self.v2 ).cross( p - self.v2 ) ) * area_inv;
+    ( w0, w1, 1.0 - w0 - w1 )
+  }
+
+  /// Returns the interpolated UV-coordinate at point `p` on the triangle
+  /// `n` is the *unnormalised* triangle normal. See `Triangle::barycentric(..)`
+  fn uv_at( &self, n : Vec3, p : Vec3 ) -> Vec2 {
+    let (w0, w1, w2) = self.barycentric( n, p );
+
+    Vec2::new(
+      self.uv0.x * w0 + self.uv1.x * w1 + self.uv2.x * w2
+    , self.uv0.y * w0 + self.uv1.y * w1 + self.uv2.y * w2
+    )
   }
 
   /// Returns the normal of the triangle. Assumes the triangle is clockwise
@@ -82,13 +143,14 @@ impl Tracable for Triangle {
     self.mat.is_emissive( )
   }
 
-  fn surface_area( &self ) -> f32 {
-    triangle_area( self.v0, self.v1, self.v2 )
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    Some( triangle_area( self.v0, self.v1, self.v2 ) )
   }
 
   /// See `Tracable#pick_random()`
   /// Note: Returns (point, normal, intensity)
-  fn pick_random( &self, rng : &mut Rng ) -> (Vec3, Vec3, Vec3) {
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
     let v0 = self.v0;
     let v1 = self.v1;
     let v2 = self.v2;
@@ -107,10 +169,7 @@ impl Tracable for Triangle {
       n = -n;
     }
 
-    match self.mat {
-      Material::Emissive { intensity } => (p_hit, n, intensity),
-      _ => (Vec3::ZERO, Vec3::ZERO, Vec3::ZERO)
-    }
+    Some( (p_hit, n, self.mat.emission( )) )
   }
   
   fn trace( &self, ray: &Ray ) -> Option< Hit > {
@@ -135,6 +194,7 @@ impl Tracable for Triangle {
       return None;
     }
 
+    let n_full = n;
     n = n.normalize( );
     let p = ray.at( t );
 
@@ -143,8 +203,7 @@ impl Tracable for Triangle {
         if let Some( v ) = self.mat.evaluate_simple( ) {
           v
         } else {
-          // TODO: UV mapping
-          self.mat.evaluate_at( &Vec2::ZERO )
+          self.mat.evaluate_at( &self.uv_at( n_full, p ) )
         };
       if n_dot_d > 0.0 { // Looking at the back-side
         Some( Hit::new( t, -n, mat, false ) )
@@ -190,3 +249,94 @@ impl Tracable for Triangle {
     }
   }
 }
+
+/// A triangle with a normal for every vertex, which are interpolated over its
+///   surface. This hides the faceted look of triangle meshes (Phong shading).
+/// Only the shading normal differs from a `Triangle`; the surface itself is
+///   still flat.
+#[derive(Debug, Clone)]
+pub struct SmoothTriangle {
+  tri : Triangle,
+  n0  : Vec3,
+  n1  : Vec3,
+  n2  : Vec3
+}
+
+impl SmoothTriangle {
+  /// Constructs a new triangle with the provided vertices and their normals
+  /// The normals need not be of unit length
+  pub fn new( v0 : Vec3, v1 : Vec3, v2 : Vec3, n0 : Vec3, n1 : Vec3, n2 : Vec3, mat : Material ) -> SmoothTriangle {
+    SmoothTriangle {
+      tri: Triangle::new( v0, v1, v2, mat )
+    , n0: n0.normalize( )
+    , n1: n1.normalize( )
+    , n2: n2.normalize( )
+    }
+  }
+
+  /// Returns the triangle with the provided UV-coordinates for its vertices
+  pub fn with_uvs( mut self, uv0 : Vec2, uv1 : Vec2, uv2 : Vec2 ) -> SmoothTriangle {
+    self.tri = self.tri.with_uvs( uv0, uv1, uv2 );
+    self
+  }
+
+  /// Translates the triangle by the provided vector
+  pub fn translate( mut self, v : Vec3 ) -> SmoothTriangle {
+    self.tri = self.tri.translate( v );
+    self
+  }
+
+  /// Scales the triangle by the provided factor (relative to the origin)
+  pub fn scale( self, factor : f32 ) -> SmoothTriangle {
+    self.scale_nonuniform( factor, factor, factor )
+  }
+
+  /// Scales the triangle by the provided factor per axis (relative to the
+  ///   origin)
+  pub fn scale_nonuniform( mut self, sx : f32, sy : f32, sz : f32 ) -> SmoothTriangle {
+    self.tri = self.tri.scale_nonuniform( sx, sy, sz );
+    // Normals are transformed by the inverse transpose of the scale matrix
+    let s_inv = Vec3::new( 1.0 / sx, 1.0 / sy, 1.0 / sz );
+    self.n0 = ( self.n0 * s_inv ).normalize( );
+    self.n1 = ( self.n1 * s_inv ).normalize( );
+    self.n2 = ( self.n2 * s_inv ).normalize( );
+    self
+  }
+}
+
+impl Bounded for SmoothTriangle {
+  fn aabb( &self ) -> Option< AABB > {
+    self.tri.aabb( )
+  }
+}
+
+impl Tracable for SmoothTriangle {
+  fn is_emissive( &self ) -> bool {
+    self.tri.is_emissive( )
+  }
+
+  /// See `Tracable::surface_area()`
+  fn surface_area( &self ) -> Option< f32 > {
+    self.tri.surface_area( )
+  }
+
+  /// See `Tracable#pick_random()`
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    self.tri.pick_random( rng )
+  }
+
+  fn trace( &self, ray: &Ray ) -> Option< Hit > {
+    let mut hit = self.tri.trace( ray )?;
+
+    let (w0, w1, w2) = self.tri.barycentric( self.tri.normal( ), ray.at( hit.distance ) );
+    let n = ( w0 * self.n0 + w1 * self.n1 + w2 * self.n2 ).normalize( );
+
+    // Keep the interpolated normal on the same side as the ray origin
+    hit.normal = if n.dot( ray.dir ) > 0.0 { -n } else { n };
+    Some( hit )
+  }
+
+  fn trace_simple( &self, ray: &Ray ) -> Option< f32 > {
+    self.tri.trace_simple( ray )
+  }
+}
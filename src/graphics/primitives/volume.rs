@@ -0,0 +1,101 @@
+// Local imports
+use crate::math::Vec3;
+use crate::graphics::{PointMaterial, AABB};
+use crate::graphics::ray::{Ray, Tracable, Bounded, Hit};
+use crate::rng::Rng;
+
+/// A homogeneous participating medium (such as fog or smoke) inside a box
+///
+/// Rather than at its boundary, a ray "hits" the volume at a random point
+///   inside it, where it scatters. The chance of passing through the volume
+///   without scattering decays exponentially with the distance travelled
+///   through it.
+#[derive(Debug, Clone)]
+pub struct Volume {
+  bounds  : AABB,
+  // The extinction coefficient; the expected number of scattering events per
+  // unit of distance
+  density : f32,
+  // The fraction of the light that is scattered (rather than absorbed)
+  albedo  : Vec3,
+  // The Henyey-Greenstein asymmetry parameter in (-1,1). (See
+  // `PointMaterial::Volume`)
+  phase_g : f32
+}
+
+impl Volume {
+  /// Constructs a new volume within the bounds
+  pub fn new( bounds : AABB, density : f32, albedo : Vec3, phase_g : f32 ) -> Volume {
+    Volume { bounds, density, albedo, phase_g }
+  }
+
+  /// Returns the distance at which the ray scatters in the volume, if it
+  ///   does so before leaving it
+  fn scatter_distance( &self, ray : &Ray ) -> Option< f32 > {
+    let (t_enter, t_exit) = self.bounds.hit_interval( ray )?;
+
+    // The random numbers are derived from the ray itself. Hence, tracing the
+    // same ray always gives the same event; as `Scene::trace(..)` traces the
+    // closest shape twice.
+    let mut rng = Rng::with_state( ray_seed( ray ) );
+
+    // Delta tracking. As the medium is homogeneous, its density is also the
+    // majorant; so, the first tentative collision is always a real one.
+    // (`ln(0)` is undefined, so avoid `r == 0`)
+    let r = rng.next( ).max( std::f32::MIN_POSITIVE );
+    let t = t_enter - r.ln( ) / self.density;
+
+    if t <= t_exit {
+      Some( t )
+    } else {
+      None
+    }
+  }
+}
+
+impl Bounded for Volume {
+  /// See `Bounded::aabb()`
+  fn aabb( &self ) -> Option< AABB > {
+    Some( self.bounds )
+  }
+}
+
+impl Tracable for Volume {
+  /// See `Tracable::is_emissive()`
+  fn is_emissive( &self ) -> bool {
+    false
+  }
+
+  /// See `Tracable::surface_area()`
+  /// A volume has no surface, so it is never an area light
+  fn surface_area( &self ) -> Option< f32 > {
+    None
+  }
+
+  /// See `Tracable#pick_random()`
+  fn pick_random( &self, _rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) > {
+    None
+  }
+
+  /// See `Tracable::trace()`
+  fn trace( &self, ray : &Ray ) -> Option< Hit > {
+    let t = self.scatter_distance( ray )?;
+    // There is no surface. The "normal" is used as the incoming direction
+    Some( Hit::new( t, ray.dir, PointMaterial::volume( self.albedo, self.phase_g ), true ) )
+  }
+
+  /// See `Tracable::trace_simple()`
+  fn trace_simple( &self, ray : &Ray ) -> Option< f32 > {
+    self.scatter_distance( ray )
+  }
+}
+
+/// Hashes the origin and direction of the ray into a (non-zero) seed
+fn ray_seed( ray : &Ray ) -> u32 {
+  let mut h = 0x811C9DC5_u32;
+  for v in &[ ray.origin.x, ray.origin.y, ray.origin.z, ray.dir.x, ray.dir.y, ray.dir.z ] {
+    h = ( h ^ v.to_bits( ) ).wrapping_mul( 0x01000193 );
+  }
+  // The xorshift state must not be 0
+  h | 1
+}
@@ -1,8 +1,9 @@
 // External imports
 use std::fmt;
 // Local imports
-use crate::math::Vec3;
+use crate::math::{Vec3, Mat4, EPSILON};
 use crate::graphics::{PointMaterial, AABB, Color3};
+use crate::graphics::lights::Light;
 use crate::rng::Rng;
 
 // A module with `Ray` and `Hit` structures, that are useful for raytracing
@@ -13,6 +14,11 @@ use crate::rng::Rng;
 // * Bounded
 // * Tracable
 // * Marchable
+// * closest_sdf
+
+/// The maximum number of steps that is marched for a single shadow ray (See
+///   `Marchable::soft_shadow(..)`)
+static MAX_SHADOW_STEPS : u32 = 256;
 
 /// A half-line in 3-dimensional space
 ///
@@ -22,20 +28,52 @@ use crate::rng::Rng;
 pub struct Ray {
   pub origin  : Vec3,
   pub dir     : Vec3,
-  pub inv_dir : Vec3
+  pub inv_dir : Vec3,
+  /// The growth of the ray's width per unit of distance along it. So, the ray
+  ///   approximates a cone; which, for camera rays, covers a single pixel.
+  ///   Textures are filtered over this width. (See `MipmapTexture`)
+  pub spread  : f32
 }
 
 impl Ray {
-  /// Constructs a new `Ray`
+  /// Constructs a new `Ray`, which is infinitely thin
   /// The direction should be of unit length
   pub fn new( origin : Vec3, dir : Vec3 ) -> Ray {
-    Ray { origin, dir, inv_dir: Vec3::new( 1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z ) }
+    Ray { origin, dir, inv_dir: Vec3::new( 1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z ), spread: 0.0 }
+  }
+
+  /// Returns the ray with the provided spread (See `Ray::spread`)
+  pub fn with_spread( mut self, spread : f32 ) -> Ray {
+    self.spread = spread;
+    self
+  }
+
+  /// Returns the width of the ray at the provided distance from its origin
+  pub fn width_at( self, distance : f32 ) -> f32 {
+    self.spread * distance
   }
 
   /// Evaluates the ray at the provided distance from its origin
   pub fn at( self, distance : f32 ) -> Vec3 {
     self.origin + distance * self.dir
   }
+
+  /// Transforms the ray by the matrix. The origin is transformed as a point,
+  ///   and the direction as a direction (so without translation). The
+  ///   resulting direction is normalized.
+  /// Note that distances along the transformed ray differ from those along the
+  ///   original ray, if the matrix scales.
+  pub fn transform( self, mat : &Mat4 ) -> Ray {
+    Ray::new( mat.transform_point( self.origin ), mat.transform_dir( self.dir ).normalize( ) )
+      .with_spread( self.spread )
+  }
+
+  /// Transforms only the direction of the ray by the matrix, which is then
+  ///   normalized. The origin remains the same.
+  pub fn transform_dir( self, mat : &Mat4 ) -> Ray {
+    Ray::new( self.origin, mat.transform_dir( self.dir ).normalize( ) )
+      .with_spread( self.spread )
+  }
 }
 
 /// A `Hit` in 3-dimensional space
@@ -93,16 +131,17 @@ pub trait Tracable : Bounded {
   fn is_emissive( &self ) -> bool;
 
   /// Returns the surface area of the shape
-  fn surface_area( &self ) -> f32 {
-    panic!( "Not implemented" );
-  }
+  /// This is required for shapes that are used as area lights (for NEE).
+  ///   Shapes without a finite surface (such as planes) return `None`, and are
+  ///   never used as area lights.
+  fn surface_area( &self ) -> Option< f32 >;
 
-  /// Pick a random point *on the sphere around p*, which points toward this
-  /// shape. Secondly, returns the normal. Thirdly, also returns the intensity
+  /// Picks a uniformly random point on the surface of the shape. Secondly,
+  /// returns the normal. Thirdly, also returns the intensity
   /// So: (point, normal, intensity)
-  fn pick_random( &self, _rng : &mut Rng ) -> (Vec3, Vec3, Vec3) {
-    panic!( "Not implemented" );
-  }
+  /// This is required for shapes that are used as area lights (for NEE).
+  ///   Exactly when `surface_area()` returns `None`, this returns `None`.
+  fn pick_random( &self, rng : &mut Rng ) -> Option< (Vec3, Vec3, Vec3) >;
 
   /// Traces a ray with limited properties evaluated at the hit.
   /// That is, no normal or materials are included. Only its distance from the
@@ -133,4 +172,160 @@ pub trait Marchable : Bounded {
   /// If `p` is not inside the object, it is advised to return the color of the
   /// surface point closest to `p`.
   fn color( &self, p : &Vec3 ) -> Color3;
+
+  /// The material of the object at point `p`, which (unlike `color(..)`) also
+  ///   provides the BRDF and hemisphere sampling. (See `PointMaterial`)
+  /// By default, the object is diffuse with the color given by `color(..)`.
+  fn material( &self, p : &Vec3 ) -> PointMaterial {
+    PointMaterial::diffuse( self.color( p ) )
+  }
+
+  /// Marches a shadow ray from `origin` along the (unit) direction `dir` up to
+  ///   a distance of `max_dis`. Returns the fraction (in [0,1]) of the light
+  ///   that is *not* occluded by the object; so 0 means fully in shadow.
+  /// Instead of a binary occlusion test, the closest distance to the object
+  ///   relative to the marched distance, `k * sdf / t`, estimates the
+  ///   penumbra. A greater `softness` value `k` gives harder shadows.
+  /// It takes at most `MAX_SHADOW_STEPS` steps, after which the penumbra so far
+  ///   is returned. (A ray parallel to a surface may never get further away)
+  fn soft_shadow( &self, origin : &Vec3, dir : &Vec3, max_dis : f32, softness : f32 ) -> f32 {
+    let mut res = 1.0_f32;
+    let mut t   = EPSILON;
+
+    for _i in 0..MAX_SHADOW_STEPS {
+      if t >= max_dis {
+        break;
+      }
+      let d = self.sdf( &( *origin + t * *dir ) );
+      if d < EPSILON {
+        return 0.0;
+      }
+      res = res.min( softness * d / t );
+      t += d;
+    }
+
+    res.max( 0.0 ).min( 1.0 )
+  }
+
+  /// Estimates the ambient occlusion at point `p` on the surface with the
+  ///   provided `normal`. Returns a value in [0,1], where 1 means unoccluded.
+  /// It samples the SDF at `iterations` points along the normal, each `step`
+  ///   apart. Without nearby geometry, the SDF at these points equals their
+  ///   distance to `p`; any shortfall indicates occlusion. This avoids casting
+  ///   rays over the hemisphere.
+  fn ambient_occlusion( &self, p : Vec3, normal : Vec3, step : f32, iterations : u32 ) -> f32 {
+    let mut d = 0.0;
+    for i in 1..=iterations {
+      let dis = step * i as f32;
+      d += ( dis - self.sdf( &( p + normal * dis ) ) ) / dis;
+    }
+
+    // Every iteration contributes at most 1 (when the SDF is not negative)
+    let scale = 1.0 / iterations.max( 1 ) as f32;
+    ( 1.0 - d * scale ).max( 0.0 ).min( 1.0 )
+  }
+
+  /// Sums the (diffuse) light arriving from all `lights` at the point
+  ///   `hit_loc` on the surface with the provided `normal`; which is not yet
+  ///   multiplied by the surface color. Every light casts a (hard) shadow ray
+  ///   that is marched through this object.
+  fn all_lights_contribution( &self, hit_loc : Vec3, normal : Vec3, lights : &[Light] ) -> Vec3 {
+    let origin = hit_loc + normal * ( 2.0 * EPSILON );
+    let mut acc = Vec3::ZERO;
+
+    for light in lights {
+      // The (unit) direction toward the light, its distance, and the light
+      // that arrives at `hit_loc` (if it were not occluded)
+      let (to_light, dis, radiance) =
+        match light {
+          Light::Directional( l ) => {
+            ( -l.direction.normalize( ), std::f32::INFINITY, l.color.to_vec3( ) )
+          },
+          Light::Point( l ) => {
+            let dis_sq = l.location.dis_sq( hit_loc );
+            ( ( l.location - hit_loc ).normalize( ), dis_sq.sqrt( ), l.color / dis_sq )
+          },
+          Light::Spot( l ) => {
+            let to_light = ( l.location - hit_loc ).normalize( );
+            if ( -to_light ).dot( l.direction.normalize( ) ) < l.angle.cos( ) {
+              continue; // Outside the cone
+            }
+            let dis_sq = l.location.dis_sq( hit_loc );
+            ( to_light, dis_sq.sqrt( ), l.color / dis_sq )
+          }
+        };
+
+      let cos_i = to_light.dot( normal );
+      if cos_i > 0.0 {
+        // An infinite softness makes the shadow binary
+        let visibility = self.soft_shadow( &origin, &to_light, dis, std::f32::INFINITY );
+        acc += radiance * ( cos_i * visibility );
+      }
+    }
+
+    acc
+  }
+}
+
+/// Evaluates the scene SDF at point `p`, which is the minimum of the SDFs of
+///   all `shapes`. Returns the index of the closest shape with its distance,
+///   or `None` if there are no shapes.
+/// The (expensive) SDF is only evaluated for shapes whose AABB contains `p`,
+///   or which are infinite. For any other shape the distance to its AABB is
+///   used instead. As that never exceeds the true distance, it remains a safe
+///   step size when marching; once the march enters the box, the exact SDF is
+///   evaluated.
+pub fn closest_sdf( shapes : &[Box< dyn Marchable >], p : &Vec3 ) -> Option< (usize, f32) > {
+  let mut closest : Option< (usize, f32) > = None;
+
+  for (i, s) in shapes.iter( ).enumerate( ) {
+    let d =
+      match s.aabb( ) {
+        Some( b ) if !b.contains_point( p ) => b.dis_to_point( p ),
+        _ => s.sdf( p )
+      };
+
+    match closest {
+      Some( (_, cd) ) if cd <= d => { },
+      _ => closest = Some( (i, d) )
+    }
+  }
+
+  closest
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The ground plane y=0, as a Signed Distance Function
+  #[derive(Debug)]
+  struct Ground;
+
+  impl Bounded for Ground {
+    fn aabb( &self ) -> Option< AABB > {
+      None
+    }
+  }
+
+  impl Marchable for Ground {
+    fn sdf( &self, p : &Vec3 ) -> f32 {
+      p.y
+    }
+
+    fn color( &self, _p : &Vec3 ) -> Color3 {
+      Color3::WHITE
+    }
+  }
+
+  #[test]
+  fn soft_shadow_terminates_parallel_to_surface( ) {
+    // The distance to the plane stays 1 along the ray, so without a step limit
+    // this would never reach the (infinite) maximum distance
+    let origin = Vec3::new( 0.0, 1.0, 0.0 );
+    let dir    = Vec3::new( 1.0, 0.0, 0.0 );
+    let res    = Ground.soft_shadow( &origin, &dir, std::f32::INFINITY, 8.0 );
+
+    assert!( res > 0.0 && res < 1.0, "{}", res );
+  }
 }
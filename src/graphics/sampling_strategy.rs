@@ -21,6 +21,30 @@ pub trait SamplingStrategy {
 
   /// Resets the sampling strategy
   fn reset( &mut self );
+
+  /// Returns statistics on the samples taken since the last reset
+  /// By default, none are tracked; so all are 0
+  fn stats( &self ) -> SamplingStats {
+    SamplingStats::default( )
+  }
+
+  /// Returns the number of samples taken since the last reset
+  /// By default, these are not tracked; so it is 0
+  fn total_samples( &self ) -> usize {
+    0
+  }
+}
+
+/// Statistics of a sampling strategy (See `SamplingStrategy#stats()`)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SamplingStats {
+  /// The number of samples taken
+  pub total_samples     : usize,
+  /// The largest number of samples assigned to a single pixel in the current
+  ///   pass over the pixels
+  pub max_spp_this_pass : usize,
+  /// The number of samples still queued for the current pass
+  pub queue_len         : usize
 }
 
 // ### Random Sampling Strategy ###
@@ -32,7 +56,9 @@ pub struct RandomSamplingStrategy {
   y      : usize,
   width  : usize,
   height : usize,
-  rng    : Rc< RefCell< Rng > >
+  rng    : Rc< RefCell< Rng > >,
+
+  num_sampled : usize
 }
 
 impl RandomSamplingStrategy {
@@ -47,7 +73,7 @@ impl RandomSamplingStrategy {
         t.write( x + vx, y + vy, c );
       }
     }
-    RandomSamplingStrategy { x, y, width, height, rng }
+    RandomSamplingStrategy { x, y, width, height, rng, num_sampled: 0 }
   }
 }
 
@@ -55,6 +81,7 @@ impl SamplingStrategy for RandomSamplingStrategy {
   /// See `SamplingStrategy#next()`
   fn next( &mut self ) -> (usize, usize) {
     let mut rng = self.rng.borrow_mut( );
+    self.num_sampled += 1;
     ( self.x + rng.next_in_range( 0, self.width ), self.y + rng.next_in_range( 0, self.height ) )
   }
 
@@ -67,7 +94,23 @@ impl SamplingStrategy for RandomSamplingStrategy {
   }
 
   /// See `SamplingStrategy#reset()`
-  fn reset( &mut self ) { }
+  fn reset( &mut self ) {
+    self.num_sampled = 0;
+  }
+
+  /// See `SamplingStrategy#stats()`
+  /// Pixels are not sampled in passes, nor queued; so only the total is known
+  fn stats( &self ) -> SamplingStats {
+    SamplingStats {
+      total_samples: self.num_sampled
+    , ..SamplingStats::default( )
+    }
+  }
+
+  /// See `SamplingStrategy#total_samples()`
+  fn total_samples( &self ) -> usize {
+    self.num_sampled
+  }
 }
 
 // ### Adaptive Sampling Strategy ###
@@ -84,6 +127,16 @@ pub struct AdaptiveSamplingStrategy {
 
   num_sampled  : usize,
   next_samples : Stack< ( usize, usize ) >,
+  // The largest number of samples queued for a pixel in the current pass
+  max_spp      : usize,
+
+  /// Pixels whose variance is below this threshold are considered converged,
+  ///   and are no longer sampled. (Default: `1e-5`)
+  pub convergence_eps : f32,
+  /// Pixels with fewer samples are never considered converged. Otherwise, a
+  ///   pixel whose first samples happen to be equal (e.g. as it only rarely
+  ///   hits a small light) would stop being sampled. (Default: `64`)
+  pub min_converged_spp : usize,
 
   // A visualisation of the sampling strategy
   sampling_target : Rc< RefCell< SimpleRenderTarget > >
@@ -110,6 +163,9 @@ impl AdaptiveSamplingStrategy {
       , rng
       , num_sampled:  0
       , next_samples: Stack::new( ( 0, 0 ) )
+      , max_spp:      0
+      , convergence_eps: 1e-5
+      , min_converged_spp: 64
       , sampling_target
       };
     strat.reset( );
@@ -129,7 +185,7 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
       let target = self.target.borrow( );
       let mut sampling_target = self.sampling_target.borrow_mut( );
 
-      // Estimate the error of the pixels
+      // Estimate the error of the pixels by their sample variance
       let mut mse = vec![ 0.0; self.width * self.height ];
       let mut mse_sum = 0.0;
       let mut mse_min = INFINITY;
@@ -137,11 +193,7 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
 
       for y in 0..self.height {
         for x in 0..self.width {
-          let v0 = target.read_clamped( self.x + x, self.y + y );
-          let v1 = target.gaussian3( self.x + x, self.y + y );
-          let v2 = target.gaussian5( self.x + x, self.y + y );
-
-          mse[ y * self.width + x ] = v0.dis_sq( v1 ).max( v0.dis_sq( v2 ) );
+          mse[ y * self.width + x ] = target.variance( self.x + x, self.y + y );
           mse_sum += mse[ y * self.width + x ];
           mse_min = mse_min.min( mse[ y * self.width + x ] );
           mse_max = mse_max.max( mse[ y * self.width + x ] );
@@ -150,9 +202,20 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
 
       // Queue the pixels based on their error, and fill the sampling visual buffer
       let mse_avg = mse_sum / ( self.width * self.height ) as f32;
+      self.max_spp = 0;
 
       for y in 0..self.height {
         for x in 0..self.width {
+          let is_converged =
+            mse[ y * self.width + x ] < self.convergence_eps &&
+              target.sample_count( self.x + x, self.y + y ) >= self.min_converged_spp;
+
+          if is_converged {
+            // Converged. Spend the budget on other pixels instead
+            sampling_target.write( self.x + x, self.y + y, Vec3::ZERO );
+            continue;
+          }
+
           let mut scaled_mse = // scale to [0,1]
             if mse[ y * self.width + x ] < mse_avg {
               0.5 * ( ( mse[ y * self.width + x ] - mse_min ) / ( mse_avg - mse_min ) )
@@ -161,6 +224,7 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
             };
           scaled_mse = scaled_mse.min( 1.0 ).max( 0.0 );
           let spp = ( 1.0 + scaled_mse * 32.0 ).ceil( ) as usize;
+          self.max_spp = self.max_spp.max( spp );
           for _i in 0..spp {
             self.next_samples.push( ( self.x + x, self.y + y ) );
           }
@@ -173,7 +237,18 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
         }
       }
 
+      if self.next_samples.len( ) == 0 {
+        // All pixels have converged. Keep refining them uniformly
+        for y in 0..self.height {
+          for x in 0..self.width {
+            self.next_samples.push( ( self.x + x, self.y + y ) );
+          }
+        }
+        self.max_spp = 1;
+      }
+
       if let Some( v ) = self.next_samples.pop( ) {
+        self.num_sampled += 1;
         v
       } else {
         panic!( "Sampling error" );
@@ -192,7 +267,14 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
 
   /// See `SamplingStrategy#reset()`
   fn reset( &mut self ) {
+    // At most 33 samples are queued per pixel (See `next()`). So preallocate
+    // these, instead of repeatedly growing the stack
+    let max_samples = self.width * self.height * 33;
+    if self.next_samples.capacity( ) != max_samples {
+      self.next_samples = Stack::with_capacity( max_samples, ( 0, 0 ) );
+    }
     self.next_samples.clear( );
+    self.num_sampled = 0;
 
     // The first w*h*4 samples are not adaptive, because there is nothing to
     // adapt to yet
@@ -203,6 +285,7 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
         }
       }
     }
+    self.max_spp = 4;
 
     {
       // First, it's not adaptive, so show 1 sample per pixel
@@ -217,14 +300,28 @@ impl SamplingStrategy for AdaptiveSamplingStrategy {
 
     self.next_samples.shuffle( &mut self.rng.borrow_mut( ) );
   }
+
+  /// See `SamplingStrategy#stats()`
+  fn stats( &self ) -> SamplingStats {
+    SamplingStats {
+      total_samples:     self.num_sampled
+    , max_spp_this_pass: self.max_spp
+    , queue_len:         self.next_samples.len( )
+    }
+  }
+
+  /// See `SamplingStrategy#total_samples()`
+  fn total_samples( &self ) -> usize {
+    self.num_sampled
+  }
 }
 
 /// Transforms a value in the range [0,1] to a sampling density color
 /// The average (0.5) is blue. Below average is green. Above average is red
 fn mix_color( v : f32 ) -> Vec3 {
   if v < 0.5 { // Green to blue
-    Vec3::new( 0.0, 1.0, 0.0 ) * ( 1.0 - 2.0 * v ) + Vec3::new( 0.0, 0.0, 1.0 ) * 2.0 * v
+    Vec3::lerp( Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 0.0, 0.0, 1.0 ), 2.0 * v )
   } else {
-    Vec3::new( 0.0, 0.0, 1.0 ) * ( 1.0 - 2.0 * ( v - 0.5 ) ) + Vec3::new( 1.0, 0.0, 0.0 ) * 2.0 * ( v - 0.5 )
+    Vec3::lerp( Vec3::new( 0.0, 0.0, 1.0 ), Vec3::new( 1.0, 0.0, 0.0 ), 2.0 * ( v - 0.5 ) )
   }
 }
@@ -2,40 +2,138 @@
 use std::f32::{INFINITY};
 use std::rc::Rc;
 // Local imports
-use crate::graphics::{Color3, AABB};
+use crate::graphics::{Color3, AABB, HdrTexture};
 use crate::graphics::ray::{Ray, Hit, Tracable};
 use crate::graphics::lights::Light;
 use crate::math::{Vec3, EPSILON};
 use crate::graphics::{BVHNode, BVHNode4};
+use crate::data::PhotonTree;
+use crate::rng::Rng;
 
 // A scene description for a path tracer
 
 /// The possible BVH representations
+#[derive(Clone)]
 enum BVHEnum {
   BVH2( usize, Vec< BVHNode > ),
+  // A 2-way BVH with ropes, which is traversed without a stack
+  BVH2Ropes( usize, Vec< BVHNode >, Vec< u32 > ),
   BVH4( usize, Vec< BVHNode4 > ),
   BVHNone
 }
 
+#[derive(Clone)]
 pub enum LightEnum {
   /// Point light
   Point( Light ),
-  /// Area light. Index in the `shapes` array (of `Scene`)
-  Area( usize ) 
+  /// Area light. Index in the `shapes` array (of `Scene`), and the surface
+  ///   area of that shape. (The area is stored, as it is needed for every
+  ///   light sample)
+  Area( usize, f32 )
 }
 
 /// A Scene consists of shapes and lights
 /// The camera is *not* part of the scene
 ///
 /// (For specific scenes, look at the `/scenes.rs` file)
+#[derive(Clone)]
 pub struct Scene {
   pub background : Color3,
+  // If present, rays that hit nothing obtain their radiance from this
+  // equirectangular environment map, instead of the background color
+  pub env_map    : Option< Rc< HdrTexture > >,
   pub lights     : Vec< LightEnum >,
   pub shapes     : Vec< Rc< dyn Tracable > >,
-      bvh        : BVHEnum
+      bvh        : BVHEnum,
+  // The configuration with which the BVH was last built. It is reused when
+  // shapes are added or removed. (See `Scene::rebuild_bvh(..)`)
+      bvh_bins   : usize,
+      is_bvh4    : bool,
+  // Groups of at most this many shapes always become BVH leaves
+  // (See `BVHNode::build_with_config(..)`)
+      bvh_leaf_size : usize,
+  // See `Scene::set_bvh_ropes_enabled(..)`
+      bvh_ropes_enabled : bool,
+  // See `Scene::set_infinite_shapes_enabled(..)`
+      infinite_shapes_enabled : bool
+}
+
+/// Constructs a `Scene` through a fluent interface, which avoids mixing up the
+///   arguments of `Scene::new(..)`
+/// By default, the background is black and a 2-way BVH is built with 16 bins.
+pub struct SceneBuilder {
+  background : Color3,
+  env_map    : Option< Rc< HdrTexture > >,
+  lights     : Vec< Light >,
+  shapes     : Vec< Rc< dyn Tracable > >,
+  bvh_bins   : usize,
+  is_bvh4    : bool
+}
+
+impl SceneBuilder {
+  /// Constructs a builder for an empty scene
+  pub fn new( ) -> SceneBuilder {
+    SceneBuilder {
+      background: Color3::BLACK
+    , env_map:    None
+    , lights:     Vec::new( )
+    , shapes:     Vec::new( )
+    , bvh_bins:   16
+    , is_bvh4:    false
+    }
+  }
+
+  /// Sets the color of rays that hit nothing
+  pub fn background( mut self, background : Color3 ) -> SceneBuilder {
+    self.background = background;
+    self
+  }
+
+  /// Sets the equirectangular environment map, which replaces the background
+  ///   color for rays that hit nothing
+  pub fn env_map( mut self, env_map : Rc< HdrTexture > ) -> SceneBuilder {
+    self.env_map = Some( env_map );
+    self
+  }
+
+  /// Adds the shape. Emissive shapes become area lights
+  pub fn add_shape( mut self, shape : Rc< dyn Tracable > ) -> SceneBuilder {
+    self.shapes.push( shape );
+    self
+  }
+
+  /// Adds all the shapes. See `SceneBuilder::add_shape(..)`
+  pub fn add_shapes( mut self, shapes : Vec< Rc< dyn Tracable > > ) -> SceneBuilder {
+    self.shapes.extend( shapes );
+    self
+  }
+
+  /// Adds the (0-sized) light
+  pub fn add_light( mut self, light : Light ) -> SceneBuilder {
+    self.lights.push( light );
+    self
+  }
+
+  /// Sets the number of bins used when building the BVH
+  pub fn bvh_bins( mut self, num_bins : usize ) -> SceneBuilder {
+    self.bvh_bins = num_bins;
+    self
+  }
+
+  /// If true, the 2-way BVH is collapsed into a 4-way BVH
+  pub fn use_bvh4( mut self, is_bvh4 : bool ) -> SceneBuilder {
+    self.is_bvh4 = is_bvh4;
+    self
+  }
+
+  /// Constructs the scene, and builds its BVH
+  pub fn build( self ) -> Scene {
+    Scene::with_config( self.background, self.env_map, self.lights, self.shapes, self.bvh_bins, self.is_bvh4 )
+  }
 }
 
 type ShapeId = usize;
+type LightId = usize;
 
 impl Scene {
   /// Constructs a new scene with the specified lights and shapes
@@ -44,37 +142,114 @@ impl Scene {
             , lights     : Vec< Light >
             , shapes     : Vec< Rc< dyn Tracable > >
             ) -> Scene {
-    let mut num_area_lights = 0;
+    Scene::with_config( background, None, lights, shapes, 16, false )
+  }
 
-    for s in &shapes {
-      num_area_lights += if s.is_emissive( ) { 1 } else { 0 }
-    }
+  /// Constructs a new scene, and builds its BVH with the provided
+  ///   configuration. (See `Scene::rebuild_bvh(..)`)
+  fn with_config( background : Color3
+                , env_map    : Option< Rc< HdrTexture > >
+                , lights     : Vec< Light >
+                , shapes     : Vec< Rc< dyn Tracable > >
+                , bvh_bins   : usize
+                , is_bvh4    : bool
+                ) -> Scene {
+    let light_enums = lights.into_iter( ).map( |l| LightEnum::Point( l ) ).collect( );
 
-    let mut light_enums = Vec::with_capacity( lights.len( ) + num_area_lights );
+    // Note that the area lights are added when building the BVH
+    let mut scene =
+      Scene {
+        background
+      , env_map
+      , lights: light_enums
+      , shapes
+      , bvh: BVHEnum::BVHNone
+      , bvh_bins
+      , is_bvh4
+      , bvh_leaf_size: 1
+      , bvh_ropes_enabled: false
+      , infinite_shapes_enabled: true
+      };
+    scene.rebuild_bvh( bvh_bins, is_bvh4 );
+    scene
+  }
 
-    for l in lights {
-      light_enums.push( LightEnum::Point( l ) );
+  /// Adds the shape to the scene. If it is emissive, it also becomes an area
+  ///   light. The BVH is rebuilt afterward, with its current configuration.
+  pub fn add_shape( &mut self, shape : Rc< dyn Tracable > ) {
+    self.shapes.push( shape );
+    self.rebuild_bvh( self.bvh_bins, self.is_bvh4 );
+  }
+
+  /// Removes the shape from the scene, after which the BVH is rebuilt with its
+  ///   current configuration
+  /// Returns `false` if the shape is not in the scene
+  pub fn remove_shape( &mut self, shape : &Rc< dyn Tracable > ) -> bool {
+    // Compare the addresses only, as vtable pointers may differ
+    let is_shape = |s : &Rc< dyn Tracable >|
+      Rc::as_ptr( s ) as *const u8 == Rc::as_ptr( shape ) as *const u8;
+
+    if let Some( i ) = self.shapes.iter( ).position( is_shape ) {
+      self.shapes.remove( i );
+      self.rebuild_bvh( self.bvh_bins, self.is_bvh4 );
+      true
+    } else {
+      false
     }
+  }
 
-    let mut scene = Scene { background, lights: vec![], bvh: BVHEnum::BVHNone, shapes };
-    scene.rebuild_bvh( 16, false );
+  /// Updates the area lights to the emissive shapes at their current index in
+  ///   `shapes`. Building the BVH reorders the shapes, after which this must be
+  ///   called.
+  fn update_area_lights( &mut self ) {
+    self.lights.retain( |l|
+      match l {
+        LightEnum::Area( .. ) => false,
+        _ => true
+      }
+    );
 
-    for i in 0..scene.shapes.len( ) {
-      if scene.shapes[ i ].is_emissive( ) {
-        light_enums.push( LightEnum::Area( i ) );
+    for i in 0..self.shapes.len( ) {
+      if self.shapes[ i ].is_emissive( ) {
+        // Shapes without a finite surface cannot be sampled as area lights
+        if let Some( area ) = self.shapes[ i ].surface_area( ) {
+          self.lights.push( LightEnum::Area( i, area ) );
+        }
       }
     }
-
-    Scene { background, lights: light_enums, bvh: scene.bvh, shapes: scene.shapes }
   }
 
   /// Rebuilds the BVH, and returns the number of nodes
   /// The BVH is build with the provided number of bins in `num_bins`.
-  /// If `is_bvh4` is true, a 4-way BVH is built. Otherwise a 2-way BVH is built.
+  /// If `is_bvh4` is true, a 4-way BVH is built. Otherwise a 2-way BVH is built,
+  ///   which has ropes when these are enabled.
+  /// 
+  /// As this reorders the shapes, the area lights are updated as well.
+  /// If the scene contains no finite shapes, no BVH is used (and 0 is returned)
   /// 
   /// To disable the BVH see `Scene::disable_bvh(..)`
   pub fn rebuild_bvh( &mut self, num_bins : usize, is_bvh4 : bool ) -> u32 {
-    let (num_inf, bvh) = BVHNode::build( &mut self.shapes, num_bins );
+    self.bvh_bins = num_bins;
+    self.is_bvh4  = is_bvh4;
+
+    // Leaves of a 4-way BVH cannot encode more shapes
+    let max_leaf_size =
+      if is_bvh4 {
+        self.bvh_leaf_size.min( BVHNode4::MAX_LEAF_SIZE )
+      } else {
+        self.bvh_leaf_size
+      };
+    let (num_inf, bvh) = BVHNode::build_with_config( &mut self.shapes, num_bins, max_leaf_size );
+
+    if num_inf == self.shapes.len( ) {
+      // Without finite shapes, the BVH consists of only its placeholder root,
+      // which cannot be traversed. (See `BVH_PLACEHOLDER`) So, the (infinite)
+      // shapes are traced directly.
+      self.bvh = BVHEnum::BVHNone;
+      self.update_area_lights( );
+      return 0;
+    }
+
     let num_nodes;
 
     if is_bvh4 {
@@ -90,45 +265,227 @@ impl Scene {
     } else {
       num_nodes = BVHNode::node_count( &bvh );
       self.bvh = BVHEnum::BVH2( num_inf, bvh );
+
+      if self.bvh_ropes_enabled {
+        self.add_bvh_ropes( );
+      }
     }
 
+    self.update_area_lights( );
+
     num_nodes as u32
   }
 
+  /// Sets the number of shapes below which BVH nodes always become leaves (See
+  ///   `BVHNode::build_with_config(..)`), and rebuilds the BVH with its current
+  ///   configuration. For a 4-way BVH, the size is clamped to
+  ///   `BVHNode4::MAX_LEAF_SIZE`.
+  /// Returns the number of nodes in the new BVH
+  pub fn set_bvh_leaf_size( &mut self, max_leaf_size : usize ) -> u32 {
+    self.bvh_leaf_size = max_leaf_size.max( 1 );
+    self.rebuild_bvh( self.bvh_bins, self.is_bvh4 )
+  }
+
+  /// Enables or disables the infinite shapes (e.g. planes) in the scene, which
+  ///   are not part of the BVH. When disabled, only the finite shapes are
+  ///   traced. This is useful for performance experiments.
+  /// Note that this has no effect when the BVH is disabled
+  pub fn set_infinite_shapes_enabled( &mut self, is_enabled : bool ) {
+    self.infinite_shapes_enabled = is_enabled;
+  }
+
+  /// Sets the equirectangular environment map, which replaces the background
+  ///   color for rays that hit nothing. `None` restores the background color.
+  pub fn set_env_map( &mut self, env_map : Option< Rc< HdrTexture > > ) {
+    self.env_map = env_map;
+  }
+
+  /// Returns the infinite shapes, which are the first `num_inf` shapes when a
+  ///   BVH is built. None are returned if these are disabled
+  fn infinite_shapes( &self, num_inf : usize ) -> &[Rc< dyn Tracable >] {
+    if self.infinite_shapes_enabled {
+      &self.shapes[..num_inf]
+    } else {
+      &[]
+    }
+  }
+
+  /// Enables or disables stackless traversal of the 2-way BVH, which follows
+  ///   "ropes" between nodes. (See `BVHNode::build_ropes(..)`)
+  /// Nodes are visited in a fixed order, instead of closest-first. This avoids
+  ///   recursion, but typically visits more nodes.
+  /// The setting is kept when the BVH is rebuilt. It has no effect on 4-way
+  ///   BVHs.
+  pub fn set_bvh_ropes_enabled( &mut self, is_enabled : bool ) {
+    self.bvh_ropes_enabled = is_enabled;
+
+    if is_enabled {
+      self.add_bvh_ropes( );
+    } else {
+      self.bvh =
+        match std::mem::replace( &mut self.bvh, BVHEnum::BVHNone ) {
+          BVHEnum::BVH2Ropes( num_inf, bvh, _ ) => BVHEnum::BVH2( num_inf, bvh ),
+          other => other
+        };
+    }
+  }
+
+  /// Adds ropes to the 2-way BVH, if the scene has one
+  fn add_bvh_ropes( &mut self ) {
+    self.bvh =
+      match std::mem::replace( &mut self.bvh, BVHEnum::BVHNone ) {
+        BVHEnum::BVH2( num_inf, bvh ) => {
+          let ropes = BVHNode::build_ropes( &bvh );
+          BVHEnum::BVH2Ropes( num_inf, bvh, ropes )
+        },
+        other => other
+      };
+  }
+
   /// Disables the BVH. On the next render, no BVH is used.
   pub fn disable_bvh( &mut self ) {
     self.bvh = BVHEnum::BVHNone;
   }
 
-  /// Is the ray from point `p` to `point_on_shape` occluded by anything (other than `shape` itself)?
-  pub fn shadow_ray( &self, p : &Vec3, point_on_shape : &Vec3, shape : Option< ShapeId > ) -> (usize, bool) {
+  /// Returns the AABB around all finite shapes in the scene. If the scene
+  ///   contains only infinite shapes (e.g. planes), `None` is returned
+  pub fn bounds( &self ) -> Option< AABB > {
+    let mut res = None;
+    for s in &self.shapes {
+      if let Some( b ) = s.aabb( ) {
+        res = Some( b.join_maybe( &res ) );
+      }
+    }
+    res
+  }
+
+  /// Is the ray from point `p` to `point_on_shape` occluded by anything (other than the shape itself)?
+  /// The shape on which `point_on_shape` lies is assumed to be only hit at that
+  ///   point. Hence, hits with that shape are avoided by stopping the ray just
+  ///   before it.
+  pub fn shadow_ray( &self, p : &Vec3, point_on_shape : &Vec3 ) -> (usize, bool) {
     let mut dir = *point_on_shape - *p;
     let dir_len = dir.len( );
     dir         = dir / dir_len;
     let ray     = Ray::new( *p + dir * EPSILON, dir );
 
-    let (num_bvh_hits, res) = self.trace_g( &ray );
+    self.trace_shadow_fast( &ray, dir_len - 2.0 * EPSILON )
+  }
 
-    if let Some( ( dis, shape_id ) ) = res {
-      if dis < dir_len {
-        if let Some( light_shape_id ) = shape {
-          if shape_id == light_shape_id {
-            // It's only "occluded" by the shape to which the shadow ray was cast
-            ( num_bvh_hits, false )
-          } else {
-            // It is occluded by some other shape
-            ( num_bvh_hits, true )
+  /// Estimates the direct illumination at `hit_point` (with surface normal
+  ///   `normal`) by sampling a single point on a single light source, which is
+  ///   next event estimation. If `photons` are provided, the light source is
+  ///   sampled from them. Otherwise, all lights are equally likely.
+  /// Returns the number of BVH node traversals, the picked light, the incoming
+  ///   radiance (scaled by the geometry term and divided by the chance of
+  ///   picking the light) and the chance of picking that light.
+  /// If `is_debug` is true, no shadow ray is cast and the verbatim intensity
+  ///   of the light is returned instead. (See `RenderInstance::is_debug_photons`)
+  pub fn lights_contribution( &self
+                            , hit_point : Vec3
+                            , normal    : Vec3
+                            , rng       : &mut Rng
+                            , photons   : Option< &mut PhotonTree >
+                            , is_debug  : bool
+                            ) -> (usize, LightId, Vec3, f32) {
+    let (light_id, light_chance) =
+      if let Some( photons ) = photons {
+        photons.sample( rng, hit_point )
+      } else {
+        let num_lights = self.lights.len( );
+        (rng.next_in_range( 0, num_lights ), 1.0 / num_lights as f32)
+      };
+
+    self.light_contribution( hit_point, normal, rng, light_id, light_chance, is_debug )
+  }
+
+  /// Estimates the direct illumination at `hit_point` (with surface normal
+  ///   `normal`) from the light source `light_id`, which was selected with
+  ///   chance `light_chance`. Otherwise, this is the same as
+  ///   `Scene::lights_contribution(..)`, which selects the light itself.
+  pub fn light_contribution( &self
+                           , hit_point    : Vec3
+                           , normal       : Vec3
+                           , rng          : &mut Rng
+                           , light_id     : LightId
+                           , light_chance : f32
+                           , is_debug     : bool
+                           ) -> (usize, LightId, Vec3, f32) {
+    // The point on the light, its intensity, and its surface area (scaled by
+    // the cosine on the light's side). A point light has no surface, so its
+    // "area" is used without the cosine.
+    let (point_on_light, intensity, light_area) =
+      match self.lights[ light_id ] {
+        LightEnum::Point( Light::Point( ref light ) ) => {
+          light.pick_random( )
+        },
+        LightEnum::Point( _ ) => {
+          // Directional lights and spot lights are not supported, for now
+          panic!( "TODO: DirectionalLight and SpotLight" );
+        },
+        LightEnum::Area( light_shape_id, light_area ) => {
+          let light_shape = &self.shapes[ light_shape_id ];
+          // Area lights always have a surface (see `update_area_lights()`)
+          let (point_on_light, light_normal, intensity) = light_shape.pick_random( rng ).unwrap( );
+          let cos_o = ( hit_point - point_on_light ).normalize( ).dot( light_normal );
+
+          if cos_o <= 0.0 {
+            return (0, light_id, Vec3::ZERO, light_chance);
           }
-        } else {
-          // It is occluded by some other shape
-          ( num_bvh_hits, true )
+          (point_on_light, intensity, light_area * cos_o)
         }
+      };
+
+    let mut to_light = point_on_light - hit_point;
+    let dis_sq = to_light.len_sq( );
+    to_light = to_light / dis_sq.sqrt( );
+
+    let cos_i = to_light.dot( normal );
+
+    if cos_i <= 0.0 {
+      (0, light_id, Vec3::ZERO, light_chance)
+    } else if is_debug {
+      // Physically *inaccurate* light-selection debug render
+      (0, light_id, intensity, light_chance)
+    } else {
+      let (num_bvh_hits, is_occluded) = self.shadow_ray( &hit_point, &point_on_light );
+
+      if is_occluded {
+        (num_bvh_hits, light_id, Vec3::ZERO, light_chance)
       } else {
-        // The hit is beyond `point_on_shape`
-        ( num_bvh_hits, false )
+        let solid_angle = light_area / dis_sq;
+        let radiance    = intensity * solid_angle * cos_i * ( 1.0 / light_chance );
+        (num_bvh_hits, light_id, radiance, light_chance)
+      }
+    }
+  }
+
+  /// Returns true if the ray hits any shape before `max_dis`. Unlike
+  ///   `Scene::trace(..)`, traversal stops at the first hit found, which need
+  ///   not be the closest.
+  /// The first tuple-element is the number of BVH node traversals
+  pub fn trace_shadow_fast( &self, ray : &Ray, max_dis : f32 ) -> (usize, bool) {
+    match &self.bvh {
+      BVHEnum::BVH2( numinf, bvh ) | BVHEnum::BVH2Ropes( numinf, bvh, _ ) => {
+        if any_shape_hit( ray, self.infinite_shapes( *numinf ), max_dis ) {
+          (0, true)
+        } else if aabb_distance( ray, &bvh[ 0 ].bounds, max_dis ).is_some( ) {
+          let (d, is_hit) = traverse_bvh_any( ray, *numinf, &bvh, &self.shapes, 0, max_dis );
+          (d + 1, is_hit)
+        } else {
+          (1, false)
+        }
+      },
+      BVHEnum::BVH4( numinf, bvh ) => {
+        if any_shape_hit( ray, self.infinite_shapes( *numinf ), max_dis ) {
+          (0, true)
+        } else {
+          traverse_bvh4_any( ray, *numinf, &bvh, &self.shapes, 0, max_dis )
+        }
+      },
+      _ => {
+        (0, any_shape_hit( ray, &self.shapes, max_dis ))
       }
-    } else {
-      ( num_bvh_hits, false ) // Not occluded
     }
   }
 
@@ -143,6 +500,40 @@ impl Scene {
     }
   }
 
+  /// Traces a ray into the scene and returns *all* elements hit, ordered from
+  ///   near to far. Note that only the first hit with each shape is included.
+  ///   (Which is what `Tracable::trace(..)` provides)
+  /// This is useful for compositing multiple transparent layers front-to-back
+  /// The first tuple-element is the number of BVH node traversals
+  pub fn trace_all( &self, ray : &Ray ) -> (usize, Vec< Hit >) {
+    let mut hits = Vec::new( );
+
+    let num_bvh_hits =
+      match &self.bvh {
+        BVHEnum::BVH2( numinf, bvh ) | BVHEnum::BVH2Ropes( numinf, bvh, _ ) => {
+          collect_shape_hits( ray, self.infinite_shapes( *numinf ), 0, &mut hits );
+          if aabb_distance( ray, &bvh[ 0 ].bounds, INFINITY ).is_some( ) {
+            1 + collect_bvh_hits( ray, *numinf, &bvh, &self.shapes, 0, &mut hits )
+          } else {
+            1
+          }
+        },
+        BVHEnum::BVH4( numinf, bvh ) => {
+          collect_shape_hits( ray, self.infinite_shapes( *numinf ), 0, &mut hits );
+          collect_bvh4_hits( ray, *numinf, &bvh, &self.shapes, 0, &mut hits )
+        },
+        _ => {
+          collect_shape_hits( ray, &self.shapes, 0, &mut hits );
+          0
+        }
+      };
+
+    hits.sort_unstable_by( |a, b| a.0.partial_cmp( &b.0 ).unwrap_or( std::cmp::Ordering::Equal ) );
+
+    let hits = hits.into_iter( ).filter_map( |(_, shape_id)| self.shapes[ shape_id ].trace( ray ) ).collect( );
+    ( num_bvh_hits, hits )
+  }
+
   /// Traces a ray into the scene and returns the distance to the first element
   /// hit. Typically this is faster than calling `Scene::trace(..)` as
   /// computation of properties (such as normals) is avoided.
@@ -157,20 +548,60 @@ impl Scene {
     }
   }
 
+  /// Returns the identifier (the index in `shapes`) of any shape whose AABB
+  ///   contains `point`; which is found through the BVH. Shapes without an
+  ///   AABB (such as planes) are never found.
+  /// Note that rebuilding the BVH (e.g. through `Scene::add_shape(..)`,
+  ///   `Scene::remove_shape(..)` or `Scene::set_bvh_leaf_size(..)`) reorders
+  ///   the shapes, which changes their identifiers.
+  pub fn find_shape_by_aabb( &self, point : Vec3 ) -> Option< ShapeId > {
+    match &self.bvh {
+      BVHEnum::BVH2( numinf, bvh ) | BVHEnum::BVH2Ropes( numinf, bvh, _ ) => {
+        if bvh[ 0 ].bounds.contains_point( &point ) {
+          find_bvh_aabb( &point, *numinf, &bvh, &self.shapes, 0 )
+        } else {
+          None
+        }
+      },
+      BVHEnum::BVH4( numinf, bvh ) => {
+        find_bvh4_aabb( &point, *numinf, &bvh, &self.shapes, 0 )
+      },
+      _ => {
+        find_shape_aabb( &point, &self.shapes, 0 )
+      }
+    }
+  }
+
+  /// Returns the identifier (the index in `shapes`) of the shape that is first
+  ///   hit by the ray. This is useful for selecting shapes in the viewport.
+  /// Note that rebuilding the BVH reorders the shapes, which changes their
+  ///   identifiers.
+  pub fn pick_shape( &self, ray : &Ray ) -> Option< ShapeId > {
+    self.trace_g( ray ).1.map( |(_, shape_id)| shape_id )
+  }
+
   /// General trace function. It returns the distance and reference to the first object hit.
   /// The first tuple-element is the number of BVH node traversals
   fn trace_g< 'a >( &'a self, ray : &Ray ) -> (usize, Option< (f32, ShapeId) >) {
     match &self.bvh {
       BVHEnum::BVH2( numinf, bvh ) => {
-        if let Some( h1 ) = trace_shapes( ray, &self.shapes[..*numinf] ) {
+        if let Some( h1 ) = trace_shapes( ray, self.infinite_shapes( *numinf ) ) {
           let (d2, h2) = traverse_bvh_guarded( ray, *numinf, &bvh, &self.shapes, 0, h1.0 );
           (d2, closest( Some( h1 ), h2 ))
         } else {
           traverse_bvh_guarded( ray, *numinf, &bvh, &self.shapes, 0, INFINITY )
         }
       },
+      BVHEnum::BVH2Ropes( numinf, bvh, ropes ) => {
+        if let Some( h1 ) = trace_shapes( ray, self.infinite_shapes( *numinf ) ) {
+          let (d2, h2) = traverse_bvh_stackless( ray, *numinf, &bvh, &ropes, &self.shapes, h1.0 );
+          (d2, closest( Some( h1 ), h2 ))
+        } else {
+          traverse_bvh_stackless( ray, *numinf, &bvh, &ropes, &self.shapes, INFINITY )
+        }
+      },
       BVHEnum::BVH4( numinf, bvh ) => {
-        if let Some( h1 ) = trace_shapes( ray, &self.shapes[..*numinf] ) {
+        if let Some( h1 ) = trace_shapes( ray, self.infinite_shapes( *numinf ) ) {
           let (d2, h2) = traverse_bvh4( ray, *numinf, &bvh, &self.shapes, 0, h1.0 );
           (d2, closest( Some( h1 ), h2 ))
         } else {
@@ -287,6 +718,258 @@ fn traverse_bvh< 'a >(
   }
 }
 
+/// Traverses a 2-way BVH from its root by following the ropes, without a
+///   stack. (See `BVHNode::build_ropes(..)`)
+/// Children are always visited left-first, but subtrees beyond the closest
+///   hit found so far are skipped.
+fn traverse_bvh_stackless< 'a >(
+      ray         : &Ray
+    , num_inf     : usize
+    , bvh         : &[BVHNode]
+    , ropes       : &[u32]
+    , shapes      : &'a [Rc< dyn Tracable >]
+    , mut max_dis : f32 ) -> (usize, Option< (f32, ShapeId) >) {
+
+  let mut num_traversed = 0;
+  let mut res = None;
+  let mut node_i = 0;
+
+  while node_i != BVHNode::NO_ROPE {
+    let node = &bvh[ node_i as usize ];
+    num_traversed += 1;
+
+    if aabb_distance( ray, &node.bounds, max_dis ).is_some( ) {
+      if node.is_leaf( ) {
+        let offset = node.left_first as usize;
+        let size = node.count as usize;
+
+        if let Some( ( dis, i ) ) = trace_shapes_md( ray, &shapes[(num_inf+offset)..(num_inf+offset+size)], max_dis ) {
+          max_dis = dis;
+          res = Some( ( dis, num_inf + offset + i ) );
+        }
+        node_i = ropes[ node_i as usize ];
+      } else {
+        node_i = node.left_first;
+      }
+    } else {
+      node_i = ropes[ node_i as usize ];
+    }
+  }
+
+  ( num_traversed, res )
+}
+
+/// Returns true if any shape in the 2-way BVH rooted at `node_i` is hit before
+///   `max_dis`. Like `traverse_bvh(..)`, the AABB of `node_i` itself is *not*
+///   intersected with the ray.
+fn traverse_bvh_any(
+      ray     : &Ray
+    , num_inf : usize
+    , bvh     : &[BVHNode]
+    , shapes  : &[Rc< dyn Tracable >]
+    , node_i  : usize
+    , max_dis : f32 ) -> (usize, bool) {
+
+  let node = &bvh[ node_i ];
+
+  if node.count != 0 { // leaf
+    let offset = node.left_first as usize;
+    let size = node.count as usize;
+
+    ( 1, any_shape_hit( ray, &shapes[(num_inf+offset)..(num_inf+offset+size)], max_dis ) )
+  } else { // node
+    let left_index = node.left_first as usize;
+    let mut num_traversed = 1;
+
+    for child_i in left_index..(left_index + 2) {
+      if aabb_distance( ray, &bvh[ child_i ].bounds, max_dis ).is_some( ) {
+        let (d, is_hit) = traverse_bvh_any( ray, num_inf, bvh, shapes, child_i, max_dis );
+        num_traversed += d;
+
+        if is_hit {
+          return ( num_traversed, true );
+        }
+      }
+    }
+
+    ( num_traversed, false )
+  }
+}
+
+/// Returns true if any shape in the 4-way BVH rooted at `node_i` is hit before
+///   `max_dis`
+fn traverse_bvh4_any(
+      ray     : &Ray
+    , num_inf : usize
+    , bvh     : &[BVHNode4]
+    , shapes  : &[Rc< dyn Tracable >]
+    , node_i  : i32
+    , max_dis : f32 ) -> (usize, bool) {
+
+  if node_i < 0 { // leaf
+    let (num_shapes, shape_index) = BVHNode4::decode_leaf( node_i as u32 );
+
+    ( 1, any_shape_hit( ray, &shapes[(num_inf+shape_index)..(num_inf+shape_index+num_shapes)], max_dis ) )
+  } else { // node
+    let node = &bvh[ node_i as usize ];
+    let hits = node.child_bounds.hit( ray ); // The SIMD intersection
+    let mut num_traversed = 1;
+
+    for i in 0..( node.num_children as usize ) {
+      let h = hits.extract( i );
+
+      if h >= 0.0 && h < max_dis {
+        let (d, is_hit) = traverse_bvh4_any( ray, num_inf, bvh, shapes, node.children[ i ], max_dis );
+        num_traversed += d;
+
+        if is_hit {
+          return ( num_traversed, true );
+        }
+      }
+    }
+
+    ( num_traversed, false )
+  }
+}
+
+/// Returns any shape in the 2-way BVH rooted at `node_i` whose AABB contains
+///   `point`. The AABB of `node_i` itself is *not* checked.
+fn find_bvh_aabb(
+      point   : &Vec3
+    , num_inf : usize
+    , bvh     : &[BVHNode]
+    , shapes  : &[Rc< dyn Tracable >]
+    , node_i  : usize ) -> Option< ShapeId > {
+
+  let node = &bvh[ node_i ];
+
+  if node.count != 0 { // leaf
+    let offset = node.left_first as usize;
+    let size = node.count as usize;
+
+    find_shape_aabb( point, &shapes[(num_inf+offset)..(num_inf+offset+size)], num_inf + offset )
+  } else { // node
+    let left_index = node.left_first as usize;
+
+    for child_i in left_index..(left_index + 2) {
+      if bvh[ child_i ].bounds.contains_point( point ) {
+        if let Some( shape_id ) = find_bvh_aabb( point, num_inf, bvh, shapes, child_i ) {
+          return Some( shape_id );
+        }
+      }
+    }
+
+    None
+  }
+}
+
+/// Returns any shape in the 4-way BVH rooted at `node_i` whose AABB contains
+///   `point`
+fn find_bvh4_aabb(
+      point   : &Vec3
+    , num_inf : usize
+    , bvh     : &[BVHNode4]
+    , shapes  : &[Rc< dyn Tracable >]
+    , node_i  : i32 ) -> Option< ShapeId > {
+
+  if node_i < 0 { // leaf
+    let (num_shapes, shape_index) = BVHNode4::decode_leaf( node_i as u32 );
+
+    find_shape_aabb( point, &shapes[(num_inf+shape_index)..(num_inf+shape_index+num_shapes)], num_inf + shape_index )
+  } else { // node
+    let node = &bvh[ node_i as usize ];
+
+    for i in 0..( node.num_children as usize ) {
+      if node.child_bounds.extract( i ).contains_point( point ) {
+        if let Some( shape_id ) = find_bvh4_aabb( point, num_inf, bvh, shapes, node.children[ i ] ) {
+          return Some( shape_id );
+        }
+      }
+    }
+
+    None
+  }
+}
+
+/// Returns the first shape in `shapes` whose AABB contains `point`. The
+///   returned identifier is offset by `offset`; which is the index of
+///   `shapes[0]` in the scene.
+fn find_shape_aabb( point : &Vec3, shapes : &[Rc< dyn Tracable >], offset : usize ) -> Option< ShapeId > {
+  for (i, s) in shapes.iter( ).enumerate( ) {
+    if let Some( b ) = s.aabb( ) {
+      if b.contains_point( point ) {
+        return Some( offset + i );
+      }
+    }
+  }
+  None
+}
+
+/// Adds all shapes hit by the ray in the 2-way BVH rooted at `node_i` to
+///   `dst`, together with their distance. The AABB of `node_i` is *not*
+///   intersected with the ray.
+/// Returns the number of traversed BVH nodes
+fn collect_bvh_hits(
+      ray     : &Ray
+    , num_inf : usize
+    , bvh     : &[BVHNode]
+    , shapes  : &[Rc< dyn Tracable >]
+    , node_i  : usize
+    , dst     : &mut Vec< (f32, ShapeId) > ) -> usize {
+
+  let node = &bvh[ node_i ];
+
+  if node.count != 0 { // leaf
+    let offset = node.left_first as usize;
+    let size = node.count as usize;
+
+    collect_shape_hits( ray, &shapes[(num_inf+offset)..(num_inf+offset+size)], num_inf + offset, dst );
+    1
+  } else { // node
+    let left_index = node.left_first as usize;
+    let mut num_traversed = 1;
+
+    for child_i in left_index..(left_index + 2) {
+      if aabb_distance( ray, &bvh[ child_i ].bounds, INFINITY ).is_some( ) {
+        num_traversed += collect_bvh_hits( ray, num_inf, bvh, shapes, child_i, dst );
+      }
+    }
+
+    num_traversed
+  }
+}
+
+/// Adds all shapes hit by the ray in the 4-way BVH rooted at `node_i` to
+///   `dst`, together with their distance.
+/// Returns the number of traversed BVH nodes
+fn collect_bvh4_hits(
+      ray     : &Ray
+    , num_inf : usize
+    , bvh     : &[BVHNode4]
+    , shapes  : &[Rc< dyn Tracable >]
+    , node_i  : i32
+    , dst     : &mut Vec< (f32, ShapeId) > ) -> usize {
+
+  if node_i < 0 { // leaf
+    let (num_shapes, shape_index) = BVHNode4::decode_leaf( node_i as u32 );
+
+    collect_shape_hits( ray, &shapes[(num_inf+shape_index)..(num_inf+shape_index+num_shapes)], num_inf + shape_index, dst );
+    1
+  } else { // node
+    let node = &bvh[ node_i as usize ];
+    let hits = node.child_bounds.hit( ray ); // The SIMD intersection
+    let mut num_traversed = 1;
+
+    for i in 0..( node.num_children as usize ) {
+      if hits.extract( i ) >= 0.0 {
+        num_traversed += collect_bvh4_hits( ray, num_inf, bvh, shapes, node.children[ i ], dst );
+      }
+    }
+
+    num_traversed
+  }
+}
+
 /// Traverses a BVH starting at node `node_i`.
 #[allow(dead_code)]
 fn traverse_bvh4< 'a >(
@@ -299,9 +982,7 @@ fn traverse_bvh4< 'a >(
   
 
   if node_i < 0 { // leaf
-    let ni = unsafe { std::mem::transmute::< i32, u32 >( node_i ) };
-    let num_shapes = ( ( ni >> 27 ) & 0x3 ) as usize;
-    let shape_index = ( ni & 0x7FFFFFF ) as usize;
+    let (num_shapes, shape_index) = BVHNode4::decode_leaf( node_i as u32 );
 
     if let Some( ( dis, res ) ) = trace_shapes_md( ray, &shapes[(num_inf+shape_index)..(num_inf+shape_index+num_shapes)], max_dis ) {
       (1, Some((dis, num_inf+shape_index+res)))
@@ -421,6 +1102,30 @@ fn closest< 'a, T >( a: Option< (f32, T) >
   }
 }
 
+/// Adds every shape in `shapes` that is hit at a positive distance to `dst`,
+///   together with that distance. The shape ids are offset by `offset`
+fn collect_shape_hits( ray : &Ray, shapes : &[Rc< dyn Tracable >], offset : usize, dst : &mut Vec< (f32, ShapeId) > ) {
+  for i in 0..shapes.len( ) {
+    if let Some( dis ) = shapes[ i ].trace_simple( ray ) {
+      if dis > 0.0 {
+        dst.push( ( dis, offset + i ) );
+      }
+    }
+  }
+}
+
+/// Returns true if any shape in `shapes` is hit at a positive distance before
+///   `max_dis`
+fn any_shape_hit( ray : &Ray, shapes : &[Rc< dyn Tracable >], max_dis : f32 ) -> bool {
+  shapes.iter( ).any( |s|
+    if let Some( dis ) = s.trace_simple( ray ) {
+      0.0 < dis && dis < max_dis
+    } else {
+      false
+    }
+  )
+}
+
 /// Intersects the ray with all shapes in `shapes`, and returns the element
 ///   whose distance is closest (but not negative).
 fn trace_shapes< 'a >( ray     : &Ray
@@ -480,3 +1185,26 @@ fn is_hit_within_sq( m_hit : Option< f32 >, d_sq : f32 ) -> bool {
     false
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graphics::Material;
+  use crate::graphics::primitives::Plane;
+
+  #[test]
+  fn scene_without_finite_shapes_is_traced( ) {
+    let ground : Rc< dyn Tracable > =
+      Rc::new( Plane::new( Vec3::ZERO, Vec3::new( 0.0, 1.0, 0.0 ), Material::diffuse( Color3::WHITE ) ) );
+    let ray = Ray::new( Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 0.0, -1.0, 0.0 ) );
+
+    for &is_bvh4 in &[ false, true ] {
+      let mut scene = SceneBuilder::new( ).add_shape( ground.clone( ) ).use_bvh4( is_bvh4 ).build( );
+      scene.set_bvh_ropes_enabled( true );
+
+      let (_, hit) = scene.trace_simple( &ray );
+      assert!( ( hit.unwrap( ) - 1.0 ).abs( ) < 1e-4 );
+      assert!( !scene.trace_shadow_fast( &Ray::new( Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ) ), INFINITY ).1 );
+    }
+  }
+}
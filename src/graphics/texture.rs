@@ -1,21 +1,109 @@
 // External imports
 use std::fmt;
+use std::cell::RefCell;
+use std::f32::consts::PI;
 // Local imports
 use crate::graphics::Color3;
-use crate::math::Vec2;
+use crate::math::{Vec2, Vec3};
+use crate::rng::Rng;
 
 /// A RGB texture
 #[derive(Clone)]
 pub struct Texture {
   pub data   : Vec< (u8,u8,u8) >,
   pub width  : u32,
-  pub height : u32
+  pub height : u32,
+  // For every texel, the cumulative chance of picking any texel before it,
+  // where texels are picked proportional to their luminance. It is computed
+  // on its first use (see `Texture::sample_luminance(..)`), so it is empty
+  // until then. When `data` changes, it must be invalidated. (See
+  // `Texture::invalidate_luminance_cdf()`)
+  luminance_cdf : RefCell< Vec< f32 > >
 }
 
 impl Texture {
   /// Creates a black RGB texture of the provided size
   pub fn new( width : u32, height : u32 ) -> Texture {
-    Texture { width, height, data: vec![(0,0,0); (width * height) as usize] }
+    Texture { width, height, data: vec![(0,0,0); (width * height) as usize], luminance_cdf: RefCell::new( Vec::new( ) ) }
+  }
+
+  /// Returns the mean of the luminances of all texels
+  pub fn average_luminance( &self ) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..self.data.len( ) {
+      sum += self.texel_luminance( i );
+    }
+    sum / self.data.len( ) as f32
+  }
+
+  /// Samples a random location in (0,1)x(0,1), where the chance of picking a
+  ///   texel is proportional to its luminance. This allows importance sampling
+  ///   of emissive textures. A fully black texture is sampled uniformly.
+  pub fn sample_luminance( &self, rng : &mut Rng ) -> Vec2 {
+    if self.luminance_cdf.borrow( ).is_empty( ) {
+      *self.luminance_cdf.borrow_mut( ) = self.compute_luminance_cdf( );
+    }
+
+    let cdf = self.luminance_cdf.borrow( );
+    let r = rng.next( );
+
+    // Binary search through the CDF
+    let mut low  = 0;
+    let mut high = cdf.len( );
+
+    while low + 1 < high {
+      let mid = ( low + high ) / 2;
+      if cdf[ mid ] <= r {
+        low = mid;
+      } else {
+        high = mid;
+      }
+    }
+
+    // Pick a random point within the texel
+    let ix = low as u32 % self.width;
+    let iy = low as u32 / self.width;
+    Vec2::new( ( ix as f32 + rng.next( ) ) / self.width as f32
+             , ( iy as f32 + rng.next( ) ) / self.height as f32
+             )
+  }
+
+  /// Discards the CDF over the texel luminances, such that it is recomputed
+  ///   upon its next use. Call this after modifying `data`.
+  pub fn invalidate_luminance_cdf( &self ) {
+    self.luminance_cdf.borrow_mut( ).clear( );
+  }
+
+  /// Computes the CDF over the texel luminances (See `Texture::luminance_cdf`)
+  fn compute_luminance_cdf( &self ) -> Vec< f32 > {
+    let n = self.data.len( );
+    let mut cdf = Vec::with_capacity( n );
+    let mut sum = 0.0;
+
+    for i in 0..n {
+      cdf.push( sum );
+      sum += self.texel_luminance( i );
+    }
+
+    if sum > 0.0 {
+      for c in cdf.iter_mut( ) {
+        *c /= sum;
+      }
+    } else {
+      for (i, c) in cdf.iter_mut( ).enumerate( ) {
+        *c = i as f32 / n as f32;
+      }
+    }
+    cdf
+  }
+
+  /// Returns the luminance of the `i`th texel in `data`
+  fn texel_luminance( &self, i : usize ) -> f32 {
+    let (r,g,b) = self.data[ i ];
+    Color3::new( r as f32 / 255_f32
+               , g as f32 / 255_f32
+               , b as f32 / 255_f32
+               ).luminance( )
   }
 
   /// Evaluates the texture at the given location in (0,1)x(0,1)
@@ -29,6 +117,200 @@ impl Texture {
                , b as f32 / 255_f32
                )
   }
+
+  /// Evaluates the texture at the given location in (0,1)x(0,1), by bilinearly
+  ///   interpolating the four texels surrounding it. Like `Texture::at(..)`,
+  ///   any value outside that range wraps around to the start again
+  pub fn at_bilinear( &self, v : Vec2 ) -> Color3 {
+    // Texel centers are located at (ix+0.5,iy+0.5)
+    let x = v.x * self.width as f32 - 0.5;
+    let y = v.y * self.height as f32 - 0.5;
+    let x_floor = x.floor( );
+    let y_floor = y.floor( );
+    let fx = x - x_floor;
+    let fy = y - y_floor;
+    let ix = x_floor as i32;
+    let iy = y_floor as i32;
+
+    let tl = self.texel( ix,     iy );
+    let tr = self.texel( ix + 1, iy );
+    let bl = self.texel( ix,     iy + 1 );
+    let br = self.texel( ix + 1, iy + 1 );
+
+    Color3::lerp( Color3::lerp( tl, tr, fx ), Color3::lerp( bl, br, fx ), fy )
+  }
+
+  /// Returns the color of the texel at (ix,iy). Indices outside the texture
+  ///   wrap around
+  fn texel( &self, ix : i32, iy : i32 ) -> Color3 {
+    let wx = ix.rem_euclid( self.width as i32 ) as u32;
+    let wy = iy.rem_euclid( self.height as i32 ) as u32;
+    let (r,g,b) = self.data[ ( wy * self.width + wx ) as usize ];
+    Color3::new( r as f32 / 255_f32
+               , g as f32 / 255_f32
+               , b as f32 / 255_f32
+               )
+  }
+}
+
+/// A texture with a pyramid of downsampled versions of itself
+/// Level 0 is the original texture; every next level has half the resolution
+///   of the previous level, until a 1x1 texture remains.
+#[derive(Clone)]
+pub struct MipmapTexture {
+  pub levels : Vec< Texture >
+}
+
+impl MipmapTexture {
+  /// Constructs the mipmap pyramid for the provided texture
+  pub fn new( base : Texture ) -> MipmapTexture {
+    let mut levels = vec![ base ];
+
+    loop {
+      let prev = &levels[ levels.len( ) - 1 ];
+      if prev.width == 1 && prev.height == 1 {
+        break;
+      }
+      let next = downsample( prev );
+      levels.push( next );
+    }
+
+    MipmapTexture { levels }
+  }
+
+  /// Evaluates the texture at the given location in (0,1)x(0,1), at the given
+  ///   (fractional) mipmap level. It bilinearly samples the two nearest levels,
+  ///   and linearly interpolates between those. (So, trilinear filtering)
+  /// Levels outside the available range are clamped.
+  pub fn sample( &self, v : Vec2, level : f32 ) -> Color3 {
+    let max_level = ( self.levels.len( ) - 1 ) as f32;
+    let level = level.max( 0.0 ).min( max_level );
+
+    let l0 = level.floor( ) as usize;
+    let l1 = ( l0 + 1 ).min( self.levels.len( ) - 1 );
+    let t  = level - l0 as f32;
+
+    Color3::lerp( self.levels[ l0 ].at_bilinear( v ), self.levels[ l1 ].at_bilinear( v ), t )
+  }
+
+  /// Returns the (fractional) mipmap level at which a texel has the size of
+  ///   the footprint. The footprint is a width in (0,1)x(0,1) space.
+  pub fn footprint_level( &self, footprint : f32 ) -> f32 {
+    let base = &self.levels[ 0 ];
+    ( footprint * base.width.max( base.height ) as f32 ).log2( )
+  }
+}
+
+/// A floating-point RGB texture, with unclamped channels
+/// This can represent High Dynamic Range images (such as environment maps)
+#[derive(Clone)]
+pub struct HdrTexture {
+  pub data   : Vec< Vec3 >,
+  pub width  : usize,
+  pub height : usize
+}
+
+impl HdrTexture {
+  /// Creates a black HDR texture of the provided size
+  pub fn new( width : usize, height : usize ) -> HdrTexture {
+    HdrTexture { width, height, data: vec![Vec3::ZERO; width * height] }
+  }
+
+  /// Evaluates the texture at the given location in (0,1)x(0,1), by bilinearly
+  ///   interpolating the four texels surrounding it. Any value outside that
+  ///   range wraps around to the start again
+  pub fn at_bilinear( &self, v : Vec2 ) -> Vec3 {
+    // Texel centers are located at (ix+0.5,iy+0.5)
+    let x = v.x * self.width as f32 - 0.5;
+    let y = v.y * self.height as f32 - 0.5;
+    let x_floor = x.floor( );
+    let y_floor = y.floor( );
+    let fx = x - x_floor;
+    let fy = y - y_floor;
+    let ix = x_floor as i32;
+    let iy = y_floor as i32;
+
+    let tl = self.texel( ix,     iy );
+    let tr = self.texel( ix + 1, iy );
+    let bl = self.texel( ix,     iy + 1 );
+    let br = self.texel( ix + 1, iy + 1 );
+
+    Vec3::lerp( Vec3::lerp( tl, tr, fx ), Vec3::lerp( bl, br, fx ), fy )
+  }
+
+  /// Evaluates the texture as an equirectangular (latitude-longitude)
+  ///   environment map in the (unit) direction `dir`. The horizontal axis
+  ///   covers the longitude, and the vertical axis the latitude; where the top
+  ///   row is straight up (along the positive y-axis).
+  pub fn sample_equirectangular( &self, dir : Vec3 ) -> Vec3 {
+    let u = dir.z.atan2( dir.x ) / ( 2.0 * PI ) + 0.5;
+    let v = 0.5 - dir.y.max( -1.0 ).min( 1.0 ).asin( ) / PI;
+    self.at_bilinear( Vec2::new( u, v ) )
+  }
+
+  /// Returns the value of the texel at (ix,iy). Indices outside the texture
+  ///   wrap around
+  fn texel( &self, ix : i32, iy : i32 ) -> Vec3 {
+    let wx = ix.rem_euclid( self.width as i32 ) as usize;
+    let wy = iy.rem_euclid( self.height as i32 ) as usize;
+    self.data[ wy * self.width + wx ]
+  }
+}
+
+/// A procedural texture with a checkerboard pattern of two colors
+/// There are `scale` squares along each axis of the (0,1)x(0,1) space
+#[derive(Clone,Copy,Debug)]
+pub struct CheckerTexture {
+  pub color_a : Color3,
+  pub color_b : Color3,
+  pub scale   : f32
+}
+
+impl CheckerTexture {
+  /// Constructs a new checkerboard texture
+  pub fn new( color_a : Color3, color_b : Color3, scale : f32 ) -> CheckerTexture {
+    CheckerTexture { color_a, color_b, scale }
+  }
+
+  /// Evaluates the texture at the given location
+  pub fn at( &self, v : Vec2 ) -> Color3 {
+    let cell = ( v.x * self.scale ).floor( ) + ( v.y * self.scale ).floor( );
+    if ( cell as i32 ).rem_euclid( 2 ) == 0 {
+      self.color_a
+    } else {
+      self.color_b
+    }
+  }
+}
+
+/// Halves the resolution of the texture, by averaging 2x2 texel blocks
+/// Odd sizes are rounded down; the last row/column is then clamped at the edge
+fn downsample( src : &Texture ) -> Texture {
+  let width  = ( src.width / 2 ).max( 1 );
+  let height = ( src.height / 2 ).max( 1 );
+  let mut dst = Texture::new( width, height );
+
+  for y in 0..height {
+    for x in 0..width {
+      let x0 = ( 2 * x ).min( src.width - 1 );
+      let x1 = ( 2 * x + 1 ).min( src.width - 1 );
+      let y0 = ( 2 * y ).min( src.height - 1 );
+      let y1 = ( 2 * y + 1 ).min( src.height - 1 );
+
+      let mut sum = (0_u32, 0_u32, 0_u32);
+      for (sx, sy) in &[ (x0, y0), (x1, y0), (x0, y1), (x1, y1) ] {
+        let (r,g,b) = src.data[ ( sy * src.width + sx ) as usize ];
+        sum.0 += r as u32;
+        sum.1 += g as u32;
+        sum.2 += b as u32;
+      }
+
+      dst.data[ ( y * width + x ) as usize ] =
+        ( ( ( sum.0 + 2 ) / 4 ) as u8, ( ( sum.1 + 2 ) / 4 ) as u8, ( ( sum.2 + 2 ) / 4 ) as u8 );
+    }
+  }
+
+  dst
 }
 
 /// Performs mathematically correct module on `u32`s.
@@ -43,3 +325,73 @@ impl fmt::Debug for Texture {
     write!( f, "Texture {{ width: {}, height: {} }}", self.width, self.height )
   }
 }
+
+impl fmt::Debug for HdrTexture {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!( f, "HdrTexture {{ width: {}, height: {} }}", self.width, self.height )
+  }
+}
+
+impl fmt::Debug for MipmapTexture {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!( f, "MipmapTexture {{ #levels: {}, base: {:?} }}", self.levels.len( ), self.levels[ 0 ] )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A 2x1 texture with a black and a white texel
+  fn black_white( ) -> Texture {
+    let mut t = Texture::new( 2, 1 );
+    t.data[ 1 ] = (255, 255, 255);
+    t
+  }
+
+  #[test]
+  fn bilinear_matches_nearest_at_texel_centers( ) {
+    let t = black_white( );
+
+    for &u in &[ 0.25, 0.75 ] {
+      let a = t.at( Vec2::new( u, 0.5 ) );
+      let b = t.at_bilinear( Vec2::new( u, 0.5 ) );
+      assert!( ( a.red - b.red ).abs( ) < 1e-6, "u = {}: {} != {}", u, a.red, b.red );
+    }
+  }
+
+  #[test]
+  fn bilinear_blends_between_texel_centers( ) {
+    let t = black_white( );
+
+    // Halfway between the texel centers, nearest-neighbour picks either texel
+    let nearest  = t.at( Vec2::new( 0.5, 0.5 ) );
+    let bilinear = t.at_bilinear( Vec2::new( 0.5, 0.5 ) );
+    assert!( nearest.red == 0.0 || nearest.red == 1.0 );
+    assert!( ( bilinear.red - 0.5 ).abs( ) < 1e-6 );
+
+    // A quarter of the way, it is mostly the black texel
+    let bilinear = t.at_bilinear( Vec2::new( 0.375, 0.5 ) );
+    assert!( ( bilinear.red - 0.25 ).abs( ) < 1e-6 );
+  }
+
+  #[test]
+  fn sample_luminance_follows_invalidated_data( ) {
+    let mut rng = Rng::with_state( 1234 );
+    let mut t   = black_white( );
+
+    // Only the (right) white texel is ever picked
+    for _i in 0..100 {
+      assert!( t.sample_luminance( &mut rng ).x >= 0.5 );
+    }
+
+    // Swap the texels, as happens when JavaScript uploads the texture data
+    t.data[ 0 ] = (255, 255, 255);
+    t.data[ 1 ] = (0, 0, 0);
+    t.invalidate_luminance_cdf( );
+
+    for _i in 0..100 {
+      assert!( t.sample_luminance( &mut rng ).x <= 0.5 );
+    }
+  }
+}
@@ -29,7 +29,7 @@ pub fn test_photon_tree( ) {
     // Light source 0 and 1 contribute on the left
     tree.insert( rng.next_in_range( 0, 2 ), Vec3::new( 0.5 * rng.next( ), rng.next( ), rng.next( ) ), rng.next( ) );
     // Light source 2 and 4 contribute on the right
-    tree.insert( rng.next_in_range( 2, 4 ), Vec3::new( 0.5 + 0.5 * rng.next( ), rng.next( ), rng.next( ) ), rng.next( ) );
+    tree.insert( rng.next_in_range( 2, 4 ), Vec3::new( rng.next_f32_in_range( 0.5, 1.0 ), rng.next( ), rng.next( ) ), rng.next( ) );
   }
   //println!( "{:?}", tree );
   println!( "Added: {:?}", SystemTime::now( ).duration_since( t1 ) );
@@ -86,7 +86,7 @@ fn test_hemisphere( ) {
 
   let mut sum = Vec3::ZERO;
 
-  let n = Vec3::unit( rng.next( ) * 2.0 - 1.0, rng.next( ) * 2.0 - 1.0, rng.next( ) * 2.0 - 1.0 );
+  let n = Vec3::unit( rng.next_f32_in_range( -1.0, 1.0 ), rng.next_f32_in_range( -1.0, 1.0 ), rng.next_f32_in_range( -1.0, 1.0 ) );
   println!( "{:?}", n );
 
   let mut min_x = INFINITY;
@@ -27,6 +27,21 @@ impl EmpiricalPDF {
       }
   }
 
+  /// Constructs an empirical PDF with the provided (relative) scales per bin
+  pub fn from_bins( bins : Vec< f32 > ) -> EmpiricalPDF {
+    let num_bins = bins.len( );
+    EmpiricalPDF {
+        bins
+      , cum_bins:         vec![ 0.0 as f32; num_bins ]
+      , has_updated_bins: true
+      }
+  }
+
+  /// Returns the (relative) scales of all bins
+  pub fn bins( &self ) -> &[f32] {
+    &self.bins
+  }
+
   /// Sets a (relative) scale for one particular bin
   pub fn set( &mut self, bin_id : usize, val : f32 ) {
     self.bins[ bin_id ]   = val;
@@ -39,6 +54,24 @@ impl EmpiricalPDF {
     self.has_updated_bins = true;
   }
 
+  /// Adds the (relative) scales of the bins in `other`, multiplied by `weight`,
+  /// to the bins of this PDF. Both PDFs should have the same number of bins.
+  pub fn merge( &mut self, other : &EmpiricalPDF, weight : f32 ) {
+    for i in 0..self.bins.len( ) {
+      self.bins[ i ] += weight * other.bins[ i ];
+    }
+    self.has_updated_bins = true;
+  }
+
+  /// Resets all bins to their initial value (1.0), such that every bin is
+  /// equally likely
+  pub fn reset_uniform( &mut self ) {
+    for b in self.bins.iter_mut( ) {
+      *b = 1.0;
+    }
+    self.has_updated_bins = true;
+  }
+
   /// Randomly samples a bin, based on its probability
   pub fn sample( &mut self, rng : &mut Rng ) -> usize {
     self.recheck_cdf( );
@@ -74,6 +107,48 @@ impl EmpiricalPDF {
     bin_prob
   }
 
+  /// Iterates over the chances of hitting each bin, in order
+  /// Unlike repeatedly calling `bin_prob(..)`, the CDF is only checked once
+  /// (Though `&mut self` is needed to update the CDF beforehand)
+  pub fn iter_probs( &mut self ) -> impl Iterator< Item = f32 > + '_ {
+    self.recheck_cdf( );
+
+    let cum_bins = &self.cum_bins;
+    ( 0..cum_bins.len( ) ).map( move |i|
+      if i + 1 == cum_bins.len( ) {
+        1.0 - cum_bins[ i ]
+      } else {
+        cum_bins[ i + 1 ] - cum_bins[ i ]
+      }
+    )
+  }
+
+  /// Returns the index of the bin with the highest chance of being hit
+  /// If multiple bins share the highest chance, the first is returned
+  pub fn max_prob_bin( &self ) -> usize {
+    // The chances are proportional to the bins, so the CDF is not needed
+    let mut max_i = 0;
+    for i in 1..self.bins.len( ) {
+      if self.bins[ i ] > self.bins[ max_i ] {
+        max_i = i;
+      }
+    }
+    max_i
+  }
+
+  /// Returns the index of the bin with the lowest chance of being hit
+  /// If multiple bins share the lowest chance, the first is returned
+  pub fn min_prob_bin( &self ) -> usize {
+    // See `max_prob_bin()`
+    let mut min_i = 0;
+    for i in 1..self.bins.len( ) {
+      if self.bins[ i ] < self.bins[ min_i ] {
+        min_i = i;
+      }
+    }
+    min_i
+  }
+
   // Makes sure local CDF is up-to-date (which is necessary after a bin has
   // changed)
   fn recheck_cdf( &mut self ) {
@@ -0,0 +1,219 @@
+// External imports
+use std::ops;
+use std::fmt;
+// Local imports
+use crate::math::Vec3;
+
+/// A 4x4 matrix for affine (and projective) transformations in 3-dimensional
+///   space. The elements are stored in *column-major* order; so element
+///   (row,col) is located at index `col * 4 + row`.
+#[derive(Copy,Clone)]
+pub struct Mat4( pub [f32; 16] );
+
+impl Mat4 {
+  /// Returns the identity matrix
+  pub fn identity( ) -> Mat4 {
+    Mat4( [ 1.0, 0.0, 0.0, 0.0
+          , 0.0, 1.0, 0.0, 0.0
+          , 0.0, 0.0, 1.0, 0.0
+          , 0.0, 0.0, 0.0, 1.0
+          ] )
+  }
+
+  /// Returns the matrix that translates points by `v`
+  pub fn translate( v : Vec3 ) -> Mat4 {
+    Mat4( [ 1.0, 0.0, 0.0, 0.0
+          , 0.0, 1.0, 0.0, 0.0
+          , 0.0, 0.0, 1.0, 0.0
+          , v.x, v.y, v.z, 1.0
+          ] )
+  }
+
+  /// Returns the matrix that scales every axis by the respective component
+  ///   of `v`
+  pub fn scale( v : Vec3 ) -> Mat4 {
+    Mat4( [ v.x, 0.0, 0.0, 0.0
+          , 0.0, v.y, 0.0, 0.0
+          , 0.0, 0.0, v.z, 0.0
+          , 0.0, 0.0, 0.0, 1.0
+          ] )
+  }
+
+  /// Returns the matrix that rotates around the x-axis. See `Vec3::rot_x(..)`
+  pub fn rotate_x( angle : f32 ) -> Mat4 {
+    // [ 1 0  0 0 ]
+    // [ 0 c -s 0 ]
+    // [ 0 s  c 0 ]
+    // [ 0 0  0 1 ]
+    let c = angle.cos( );
+    let s = angle.sin( );
+    Mat4( [ 1.0, 0.0, 0.0, 0.0
+          , 0.0,   c,   s, 0.0
+          , 0.0,  -s,   c, 0.0
+          , 0.0, 0.0, 0.0, 1.0
+          ] )
+  }
+
+  /// Returns the matrix that rotates around the y-axis. See `Vec3::rot_y(..)`
+  pub fn rotate_y( angle : f32 ) -> Mat4 {
+    // [  c 0 s 0 ]
+    // [  0 1 0 0 ]
+    // [ -s 0 c 0 ]
+    // [  0 0 0 1 ]
+    let c = angle.cos( );
+    let s = angle.sin( );
+    Mat4( [   c, 0.0,  -s, 0.0
+          , 0.0, 1.0, 0.0, 0.0
+          ,   s, 0.0,   c, 0.0
+          , 0.0, 0.0, 0.0, 1.0
+          ] )
+  }
+
+  /// Returns the matrix that rotates around the z-axis
+  pub fn rotate_z( angle : f32 ) -> Mat4 {
+    // [ c -s 0 0 ]
+    // [ s  c 0 0 ]
+    // [ 0  0 1 0 ]
+    // [ 0  0 0 1 ]
+    let c = angle.cos( );
+    let s = angle.sin( );
+    Mat4( [   c,   s, 0.0, 0.0
+          ,  -s,   c, 0.0, 0.0
+          , 0.0, 0.0, 1.0, 0.0
+          , 0.0, 0.0, 0.0, 1.0
+          ] )
+  }
+
+  /// Returns the element at the given row and column
+  pub fn at( &self, row : usize, col : usize ) -> f32 {
+    self.0[ col * 4 + row ]
+  }
+
+  /// Matrix multiplication: `self * o`
+  /// So, when transforming, `o` is applied first and then `self`
+  pub fn mul( self, o : Mat4 ) -> Mat4 {
+    let mut res = [ 0.0; 16 ];
+    for col in 0..4 {
+      for row in 0..4 {
+        let mut sum = 0.0;
+        for k in 0..4 {
+          sum += self.at( row, k ) * o.at( k, col );
+        }
+        res[ col * 4 + row ] = sum;
+      }
+    }
+    Mat4( res )
+  }
+
+  /// Transforms the point `p`. That is, `p` is treated as (x,y,z,1), such that
+  ///   translation is applied. The homogeneous divide is performed afterward.
+  pub fn transform_point( &self, p : Vec3 ) -> Vec3 {
+    let m = &self.0;
+    let x = m[ 0 ] * p.x + m[ 4 ] * p.y + m[  8 ] * p.z + m[ 12 ];
+    let y = m[ 1 ] * p.x + m[ 5 ] * p.y + m[  9 ] * p.z + m[ 13 ];
+    let z = m[ 2 ] * p.x + m[ 6 ] * p.y + m[ 10 ] * p.z + m[ 14 ];
+    let w = m[ 3 ] * p.x + m[ 7 ] * p.y + m[ 11 ] * p.z + m[ 15 ];
+
+    if w == 1.0 || w == 0.0 {
+      Vec3::new( x, y, z )
+    } else {
+      Vec3::new( x / w, y / w, z / w )
+    }
+  }
+
+  /// Transforms the direction `d`. That is, `d` is treated as (x,y,z,0), such
+  ///   that no translation is applied.
+  /// Note that the result is *not* normalised.
+  pub fn transform_dir( &self, d : Vec3 ) -> Vec3 {
+    let m = &self.0;
+    Vec3::new(
+      m[ 0 ] * d.x + m[ 4 ] * d.y + m[  8 ] * d.z
+    , m[ 1 ] * d.x + m[ 5 ] * d.y + m[  9 ] * d.z
+    , m[ 2 ] * d.x + m[ 6 ] * d.y + m[ 10 ] * d.z
+    )
+  }
+
+  /// Returns the transpose of the matrix
+  pub fn transpose( &self ) -> Mat4 {
+    let mut res = [ 0.0; 16 ];
+    for col in 0..4 {
+      for row in 0..4 {
+        res[ row * 4 + col ] = self.0[ col * 4 + row ];
+      }
+    }
+    Mat4( res )
+  }
+
+  /// Returns the inverse of the matrix. If the matrix is singular, `None` is
+  ///   returned.
+  /// Adapted from the MESA implementation of `gluInvertMatrix`, which expands
+  ///   the cofactors.
+  pub fn inverse( &self ) -> Option< Mat4 > {
+    let m = &self.0;
+    let mut inv = [ 0.0; 16 ];
+
+    inv[  0 ] =  m[ 5 ] * m[ 10 ] * m[ 15 ] - m[ 5 ] * m[ 11 ] * m[ 14 ] - m[ 9 ] * m[ 6 ] * m[ 15 ]
+              +  m[ 9 ] * m[  7 ] * m[ 14 ] + m[ 13 ] * m[ 6 ] * m[ 11 ] - m[ 13 ] * m[ 7 ] * m[ 10 ];
+    inv[  4 ] = -m[ 4 ] * m[ 10 ] * m[ 15 ] + m[ 4 ] * m[ 11 ] * m[ 14 ] + m[ 8 ] * m[ 6 ] * m[ 15 ]
+              -  m[ 8 ] * m[  7 ] * m[ 14 ] - m[ 12 ] * m[ 6 ] * m[ 11 ] + m[ 12 ] * m[ 7 ] * m[ 10 ];
+    inv[  8 ] =  m[ 4 ] * m[  9 ] * m[ 15 ] - m[ 4 ] * m[ 11 ] * m[ 13 ] - m[ 8 ] * m[ 5 ] * m[ 15 ]
+              +  m[ 8 ] * m[  7 ] * m[ 13 ] + m[ 12 ] * m[ 5 ] * m[ 11 ] - m[ 12 ] * m[ 7 ] * m[  9 ];
+    inv[ 12 ] = -m[ 4 ] * m[  9 ] * m[ 14 ] + m[ 4 ] * m[ 10 ] * m[ 13 ] + m[ 8 ] * m[ 5 ] * m[ 14 ]
+              -  m[ 8 ] * m[  6 ] * m[ 13 ] - m[ 12 ] * m[ 5 ] * m[ 10 ] + m[ 12 ] * m[ 6 ] * m[  9 ];
+    inv[  1 ] = -m[ 1 ] * m[ 10 ] * m[ 15 ] + m[ 1 ] * m[ 11 ] * m[ 14 ] + m[ 9 ] * m[ 2 ] * m[ 15 ]
+              -  m[ 9 ] * m[  3 ] * m[ 14 ] - m[ 13 ] * m[ 2 ] * m[ 11 ] + m[ 13 ] * m[ 3 ] * m[ 10 ];
+    inv[  5 ] =  m[ 0 ] * m[ 10 ] * m[ 15 ] - m[ 0 ] * m[ 11 ] * m[ 14 ] - m[ 8 ] * m[ 2 ] * m[ 15 ]
+              +  m[ 8 ] * m[  3 ] * m[ 14 ] + m[ 12 ] * m[ 2 ] * m[ 11 ] - m[ 12 ] * m[ 3 ] * m[ 10 ];
+    inv[  9 ] = -m[ 0 ] * m[  9 ] * m[ 15 ] + m[ 0 ] * m[ 11 ] * m[ 13 ] + m[ 8 ] * m[ 1 ] * m[ 15 ]
+              -  m[ 8 ] * m[  3 ] * m[ 13 ] - m[ 12 ] * m[ 1 ] * m[ 11 ] + m[ 12 ] * m[ 3 ] * m[  9 ];
+    inv[ 13 ] =  m[ 0 ] * m[  9 ] * m[ 14 ] - m[ 0 ] * m[ 10 ] * m[ 13 ] - m[ 8 ] * m[ 1 ] * m[ 14 ]
+              +  m[ 8 ] * m[  2 ] * m[ 13 ] + m[ 12 ] * m[ 1 ] * m[ 10 ] - m[ 12 ] * m[ 2 ] * m[  9 ];
+    inv[  2 ] =  m[ 1 ] * m[  6 ] * m[ 15 ] - m[ 1 ] * m[  7 ] * m[ 14 ] - m[ 5 ] * m[ 2 ] * m[ 15 ]
+              +  m[ 5 ] * m[  3 ] * m[ 14 ] + m[ 13 ] * m[ 2 ] * m[  7 ] - m[ 13 ] * m[ 3 ] * m[  6 ];
+    inv[  6 ] = -m[ 0 ] * m[  6 ] * m[ 15 ] + m[ 0 ] * m[  7 ] * m[ 14 ] + m[ 4 ] * m[ 2 ] * m[ 15 ]
+              -  m[ 4 ] * m[  3 ] * m[ 14 ] - m[ 12 ] * m[ 2 ] * m[  7 ] + m[ 12 ] * m[ 3 ] * m[  6 ];
+    inv[ 10 ] =  m[ 0 ] * m[  5 ] * m[ 15 ] - m[ 0 ] * m[  7 ] * m[ 13 ] - m[ 4 ] * m[ 1 ] * m[ 15 ]
+              +  m[ 4 ] * m[  3 ] * m[ 13 ] + m[ 12 ] * m[ 1 ] * m[  7 ] - m[ 12 ] * m[ 3 ] * m[  5 ];
+    inv[ 14 ] = -m[ 0 ] * m[  5 ] * m[ 14 ] + m[ 0 ] * m[  6 ] * m[ 13 ] + m[ 4 ] * m[ 1 ] * m[ 14 ]
+              -  m[ 4 ] * m[  2 ] * m[ 13 ] - m[ 12 ] * m[ 1 ] * m[  6 ] + m[ 12 ] * m[ 2 ] * m[  5 ];
+    inv[  3 ] = -m[ 1 ] * m[  6 ] * m[ 11 ] + m[ 1 ] * m[  7 ] * m[ 10 ] + m[ 5 ] * m[ 2 ] * m[ 11 ]
+              -  m[ 5 ] * m[  3 ] * m[ 10 ] - m[  9 ] * m[ 2 ] * m[  7 ] + m[  9 ] * m[ 3 ] * m[  6 ];
+    inv[  7 ] =  m[ 0 ] * m[  6 ] * m[ 11 ] - m[ 0 ] * m[  7 ] * m[ 10 ] - m[ 4 ] * m[ 2 ] * m[ 11 ]
+              +  m[ 4 ] * m[  3 ] * m[ 10 ] + m[  8 ] * m[ 2 ] * m[  7 ] - m[  8 ] * m[ 3 ] * m[  6 ];
+    inv[ 11 ] = -m[ 0 ] * m[  5 ] * m[ 11 ] + m[ 0 ] * m[  7 ] * m[  9 ] + m[ 4 ] * m[ 1 ] * m[ 11 ]
+              -  m[ 4 ] * m[  3 ] * m[  9 ] - m[  8 ] * m[ 1 ] * m[  7 ] + m[  8 ] * m[ 3 ] * m[  5 ];
+    inv[ 15 ] =  m[ 0 ] * m[  5 ] * m[ 10 ] - m[ 0 ] * m[  6 ] * m[  9 ] - m[ 4 ] * m[ 1 ] * m[ 10 ]
+              +  m[ 4 ] * m[  2 ] * m[  9 ] + m[  8 ] * m[ 1 ] * m[  6 ] - m[  8 ] * m[ 2 ] * m[  5 ];
+
+    let det = m[ 0 ] * inv[ 0 ] + m[ 1 ] * inv[ 4 ] + m[ 2 ] * inv[ 8 ] + m[ 3 ] * inv[ 12 ];
+
+    if det == 0.0 {
+      None
+    } else {
+      let det_inv = 1.0 / det;
+      for v in inv.iter_mut( ) {
+        *v *= det_inv;
+      }
+      Some( Mat4( inv ) )
+    }
+  }
+}
+
+/// Matrix multiplication. See `Mat4::mul(..)`
+impl ops::Mul< Mat4 > for Mat4 {
+  type Output = Mat4;
+
+  fn mul( self, o : Mat4 ) -> Mat4 {
+    Mat4::mul( self, o )
+  }
+}
+
+impl fmt::Debug for Mat4 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!( f, "mat4(" )?;
+    for row in 0..4 {
+      write!( f, "[{}, {}, {}, {}]", self.at( row, 0 ), self.at( row, 1 ), self.at( row, 2 ), self.at( row, 3 ) )?;
+    }
+    write!( f, ")" )
+  }
+}
@@ -1,9 +1,14 @@
 mod vec2;
 mod vec3;
+mod mat4;
+mod quat;
 mod empirical_pdf;
+mod noise;
 
 pub use vec2::Vec2;
 pub use vec3::Vec3;
+pub use mat4::Mat4;
+pub use quat::Quat;
 pub use empirical_pdf::EmpiricalPDF;
 
 // Some arbitrary math utilities
@@ -0,0 +1,153 @@
+// Local imports
+use crate::math::{clamp, Vec3};
+
+// Ken Perlin's "Improved Noise" (2002), in 3 dimensions. Adapted from the
+// reference implementation: https://mrl.nyu.edu/~perlin/noise/
+//
+// Exports:
+// * perlin3
+// * fbm3
+
+/// The permutation table from the reference implementation. Lookups wrap
+/// around at 256, which avoids having to store it twice.
+static PERM : [u8; 256] =
+  [ 151, 160, 137,  91,  90,  15, 131,  13, 201,  95,  96,  53, 194, 233,   7, 225
+  , 140,  36, 103,  30,  69, 142,   8,  99,  37, 240,  21,  10,  23, 190,   6, 148
+  , 247, 120, 234,  75,   0,  26, 197,  62,  94, 252, 219, 203, 117,  35,  11,  32
+  ,  57, 177,  33,  88, 237, 149,  56,  87, 174,  20, 125, 136, 171, 168,  68, 175
+  ,  74, 165,  71, 134, 139,  48,  27, 166,  77, 146, 158, 231,  83, 111, 229, 122
+  ,  60, 211, 133, 230, 220, 105,  92,  41,  55,  46, 245,  40, 244, 102, 143,  54
+  ,  65,  25,  63, 161,   1, 216,  80,  73, 209,  76, 132, 187, 208,  89,  18, 169
+  , 200, 196, 135, 130, 116, 188, 159,  86, 164, 100, 109, 198, 173, 186,   3,  64
+  ,  52, 217, 226, 250, 124, 123,   5, 202,  38, 147, 118, 126, 255,  82,  85, 212
+  , 207, 206,  59, 227,  47,  16,  58,  17, 182, 189,  28,  42, 223, 183, 170, 213
+  , 119, 248, 152,   2,  44, 154, 163,  70, 221, 153, 101, 155, 167,  43, 172,   9
+  , 129,  22,  39, 253,  19,  98, 108, 110,  79, 113, 224, 232, 178, 185, 112, 104
+  , 218, 246,  97, 228, 251,  34, 242, 193, 238, 210, 144,  12, 191, 179, 162, 241
+  ,  81,  51, 145, 235, 249,  14, 239, 107,  49, 192, 214,  31, 181, 199, 106, 157
+  , 184,  84, 204, 176, 115, 121,  50,  45, 127,   4, 150, 254, 138, 236, 205,  93
+  , 222, 114,  67,  29,  24,  72, 243, 141, 128, 195,  78,  66, 215,  61, 156, 180
+  ];
+
+/// Evaluates 3-dimensional gradient noise at `p`
+/// The result lies within the range [-1,1], and is exactly 0 at every integer
+///   lattice point (e.g. the origin).
+pub fn perlin3( p : Vec3 ) -> f32 {
+  let fx = p.x.floor( );
+  let fy = p.y.floor( );
+  let fz = p.z.floor( );
+
+  // The unit cube that contains the point
+  let xi = fx as i32 as usize & 255;
+  let yi = fy as i32 as usize & 255;
+  let zi = fz as i32 as usize & 255;
+
+  // The relative location of the point within that cube
+  let x = p.x - fx;
+  let y = p.y - fy;
+  let z = p.z - fz;
+
+  let u = fade( x );
+  let v = fade( y );
+  let w = fade( z );
+
+  // Hash the coordinates of the 8 cube corners
+  let a  = perm( xi ) + yi;
+  let aa = perm( a ) + zi;
+  let ab = perm( a + 1 ) + zi;
+  let b  = perm( xi + 1 ) + yi;
+  let ba = perm( b ) + zi;
+  let bb = perm( b + 1 ) + zi;
+
+  // And blend the results from the 8 corners of the cube
+  let res =
+    lerp( w, lerp( v, lerp( u, grad( perm( aa ),     x,       y,       z       )
+                             , grad( perm( ba ),     x - 1.0, y,       z       ) )
+                    , lerp( u, grad( perm( ab ),     x,       y - 1.0, z       )
+                             , grad( perm( bb ),     x - 1.0, y - 1.0, z       ) ) )
+           , lerp( v, lerp( u, grad( perm( aa + 1 ), x,       y,       z - 1.0 )
+                             , grad( perm( ba + 1 ), x - 1.0, y,       z - 1.0 ) )
+                    , lerp( u, grad( perm( ab + 1 ), x,       y - 1.0, z - 1.0 )
+                             , grad( perm( bb + 1 ), x - 1.0, y - 1.0, z - 1.0 ) ) ) );
+
+  // The theoretical bounds are slightly beyond [-1,1], so enforce them
+  clamp( res, -1.0, 1.0 )
+}
+
+/// Fractal Brownian Motion. Sums `octaves` layers of `perlin3`, where every
+///   next layer has its frequency multiplied by `lacunarity` and its amplitude
+///   multiplied by `gain`.
+/// The sum is normalised by the total amplitude, so the result remains within
+///   the range [-1,1].
+pub fn fbm3( p : Vec3, octaves : u32, lacunarity : f32, gain : f32 ) -> f32 {
+  let mut sum       = 0.0;
+  let mut amp_sum   = 0.0;
+  let mut amplitude = 1.0;
+  let mut frequency = 1.0;
+
+  for _i in 0..octaves {
+    sum       += amplitude * perlin3( p * frequency );
+    amp_sum   += amplitude;
+    amplitude *= gain;
+    frequency *= lacunarity;
+  }
+
+  if amp_sum > 0.0 {
+    sum / amp_sum
+  } else {
+    0.0
+  }
+}
+
+/// Looks up a value in the permutation table
+fn perm( i : usize ) -> usize {
+  PERM[ i & 255 ] as usize
+}
+
+/// The quintic interpolation curve: 6t^5 - 15t^4 + 10t^3
+/// Its first and second derivatives are 0 at both t=0 and t=1
+fn fade( t : f32 ) -> f32 {
+  t * t * t * ( t * ( t * 6.0 - 15.0 ) + 10.0 )
+}
+
+fn lerp( t : f32, a : f32, b : f32 ) -> f32 {
+  a + t * ( b - a )
+}
+
+/// Dots (x,y,z) with one of the 12 cube-edge gradient vectors, which is
+///   selected by the low 4 bits of `hash`
+fn grad( hash : usize, x : f32, y : f32, z : f32 ) -> f32 {
+  let h = hash & 15;
+  let u = if h < 8 { x } else { y };
+  let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+  ( if h & 1 == 0 { u } else { -u } ) + ( if h & 2 == 0 { v } else { -v } )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rng::Rng;
+
+  #[test]
+  fn perlin3_is_zero_at_lattice_points( ) {
+    assert_eq!( perlin3( Vec3::ZERO ), 0.0 );
+
+    for &(x, y, z) in &[ (1, 0, 0), (-3, 7, 2), (12, -5, -9), (255, 256, 257) ] {
+      assert_eq!( perlin3( Vec3::new( x as f32, y as f32, z as f32 ) ), 0.0 );
+    }
+  }
+
+  #[test]
+  fn perlin3_lies_within_unit_range( ) {
+    let mut rng = Rng::with_state( 1234 );
+
+    for _i in 0..100000 {
+      let p = Vec3::new( rng.next( ) * 64.0 - 32.0, rng.next( ) * 64.0 - 32.0, rng.next( ) * 64.0 - 32.0 );
+      let v = perlin3( p );
+      assert!( ( -1.0..=1.0 ).contains( &v ), "perlin3 out of range: {}", v );
+
+      let f = fbm3( p, 4, 2.0, 0.5 );
+      assert!( ( -1.0..=1.0 ).contains( &f ), "fbm3 out of range: {}", f );
+    }
+  }
+}
@@ -0,0 +1,133 @@
+// External imports
+use std::ops;
+use std::fmt;
+// Local imports
+use crate::math::{Mat4, Vec3};
+
+/// A quaternion, which is used to represent rotations in 3-dimensional space
+/// Unlike a sequence of Euler-angle rotations, these do not suffer from gimbal
+///   lock and can be interpolated smoothly.
+/// Rotation quaternions should be of unit length.
+#[derive(Copy,Clone)]
+pub struct Quat {
+  pub x : f32,
+  pub y : f32,
+  pub z : f32,
+  pub w : f32
+}
+
+impl Quat {
+  /// Constructs a new quaternion with the provided components
+  pub fn new( x : f32, y : f32, z : f32, w : f32 ) -> Quat {
+    Quat { x, y, z, w }
+  }
+
+  /// Returns the quaternion that represents no rotation
+  pub fn identity( ) -> Quat {
+    Quat::new( 0.0, 0.0, 0.0, 1.0 )
+  }
+
+  /// Constructs the rotation of `angle` radians around `axis`
+  /// The axis does not need to be of unit length
+  pub fn from_axis_angle( axis : Vec3, angle : f32 ) -> Quat {
+    let a = axis.normalize( );
+    let s = ( 0.5 * angle ).sin( );
+    let c = ( 0.5 * angle ).cos( );
+    Quat::new( a.x * s, a.y * s, a.z * s, c )
+  }
+
+  /// Computes the dot product with the provided quaternion
+  pub fn dot( self, o : Quat ) -> f32 {
+    self.x * o.x + self.y * o.y + self.z * o.z + self.w * o.w
+  }
+
+  /// Returns the length
+  pub fn len( self ) -> f32 {
+    self.dot( self ).sqrt( )
+  }
+
+  /// Scales the quaternion such that its length becomes 1
+  pub fn normalize( self ) -> Quat {
+    let l_inv = 1.0 / self.len( );
+    Quat::new( self.x * l_inv, self.y * l_inv, self.z * l_inv, self.w * l_inv )
+  }
+
+  /// Returns the conjugate. For unit quaternions this is the inverse rotation
+  pub fn conjugate( self ) -> Quat {
+    Quat::new( -self.x, -self.y, -self.z, self.w )
+  }
+
+  /// Spherical linear interpolation between `self` (at t=0) and `o` (at t=1)
+  /// It always interpolates along the shortest arc.
+  pub fn slerp( self, o : Quat, t : f32 ) -> Quat {
+    let mut cos_theta = self.dot( o );
+    let mut b = o;
+
+    // `q` and `-q` represent the same rotation. Pick the nearest
+    if cos_theta < 0.0 {
+      cos_theta = -cos_theta;
+      b = Quat::new( -o.x, -o.y, -o.z, -o.w );
+    }
+
+    let (wa, wb) =
+      if cos_theta > 0.9995 {
+        // The quaternions are nearly equal. Avoid dividing by `sin(theta) ~ 0`
+        ( 1.0 - t, t )
+      } else {
+        let theta     = cos_theta.acos( );
+        let sin_theta = theta.sin( );
+        ( ( ( 1.0 - t ) * theta ).sin( ) / sin_theta, ( t * theta ).sin( ) / sin_theta )
+      };
+
+    Quat::new(
+      wa * self.x + wb * b.x
+    , wa * self.y + wb * b.y
+    , wa * self.z + wb * b.z
+    , wa * self.w + wb * b.w
+    ).normalize( )
+  }
+
+  /// Returns the rotation matrix that corresponds with this (unit) quaternion
+  pub fn to_mat4( self ) -> Mat4 {
+    let (x, y, z, w) = (self.x, self.y, self.z, self.w);
+
+    // Column-major. See `Mat4`
+    Mat4( [ 1.0 - 2.0 * ( y * y + z * z ), 2.0 * ( x * y + w * z ), 2.0 * ( x * z - w * y ), 0.0
+          , 2.0 * ( x * y - w * z ), 1.0 - 2.0 * ( x * x + z * z ), 2.0 * ( y * z + w * x ), 0.0
+          , 2.0 * ( x * z + w * y ), 2.0 * ( y * z - w * x ), 1.0 - 2.0 * ( x * x + y * y ), 0.0
+          , 0.0, 0.0, 0.0, 1.0
+          ] )
+  }
+}
+
+/// Composition of rotations: `a * b` first rotates by `b`, then by `a`
+impl ops::Mul< Quat > for Quat {
+  type Output = Quat;
+
+  fn mul( self, o : Quat ) -> Quat {
+    Quat::new(
+      self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y
+    , self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x
+    , self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w
+    , self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z
+    )
+  }
+}
+
+/// Rotates the vector by the (unit) quaternion
+impl ops::Mul< Vec3 > for Quat {
+  type Output = Vec3;
+
+  fn mul( self, v : Vec3 ) -> Vec3 {
+    // v' = v + 2w (q x v) + 2 q x (q x v)
+    let q = Vec3::new( self.x, self.y, self.z );
+    let t = 2.0 * q.cross( v );
+    v + self.w * t + q.cross( t )
+  }
+}
+
+impl fmt::Debug for Quat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!( f, "quat({}, {}, {}, {})", self.x, self.y, self.z, self.w )
+  }
+}
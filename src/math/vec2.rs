@@ -1,14 +1,77 @@
+// External imports
+use std::ops;
+
 /// A vector in 2-dimensional space
+/// It is laid out as two consecutive `f32`s, as buffers of them are shared
+///   with JavaScript. (See `allocate_mesh_uvs(..)` in `wasm_interface.rs`)
 #[derive(Copy, Clone, Debug)]
+#[repr(C)]
 pub struct Vec2 {
   pub x : f32,
   pub y : f32
 }
 
 impl Vec2 {
+  /// The vector that lies at the origin, which has 0 length; (0,0)
   pub const ZERO: Vec2 = Vec2 { x: 0.0, y: 0.0 };
 
+  /// Constructs a new vector with the provided components
   pub fn new( x : f32, y : f32 ) -> Vec2 {
     Vec2 { x, y }
   }
 }
+
+impl ops::Neg for Vec2 {
+  type Output = Vec2;
+
+  fn neg( self ) -> Vec2 {
+    Vec2::new( -self.x, -self.y )
+  }
+}
+
+impl ops::Add< Vec2 > for Vec2 {
+  type Output = Vec2;
+
+  fn add( self, addend: Vec2 ) -> Vec2 {
+    Vec2::new( self.x + addend.x, self.y + addend.y )
+  }
+}
+
+impl ops::Sub< Vec2 > for Vec2 {
+  type Output = Vec2;
+
+  fn sub( self, subtrahend: Vec2 ) -> Vec2 {
+    Vec2::new( self.x - subtrahend.x, self.y - subtrahend.y )
+  }
+}
+
+impl ops::Mul< f32 > for Vec2 {
+  type Output = Vec2;
+
+  fn mul( self, multiplier: f32 ) -> Vec2 {
+    Vec2::new( multiplier * self.x, multiplier * self.y )
+  }
+}
+
+impl ops::Mul< Vec2 > for f32 {
+  type Output = Vec2;
+
+  fn mul( self, v: Vec2 ) -> Vec2 {
+    Vec2::new( self * v.x, self * v.y )
+  }
+}
+
+impl ops::Div< f32 > for Vec2 {
+  type Output = Vec2;
+
+  fn div( self, divisor: f32 ) -> Vec2 {
+    Vec2::new( self.x / divisor, self.y / divisor )
+  }
+}
+
+impl ops::AddAssign< Vec2 > for Vec2 {
+  fn add_assign( &mut self, v : Vec2 ) {
+    self.x += v.x;
+    self.y += v.y;
+  }
+}
@@ -1,8 +1,14 @@
+// External imports
 use std::ops;
 use std::fmt;
+// Local imports
+use crate::graphics::Color3;
 
 /// A vector in 3-dimensional space
+/// It is laid out as three consecutive `f32`s, as buffers of them are shared
+///   with JavaScript. (See `allocate_hdr_texture(..)` in `wasm_interface.rs`)
 #[derive(Copy,Clone)]
+#[repr(C)]
 pub struct Vec3 {
   pub x : f32,
   pub y : f32,
@@ -18,6 +24,12 @@ impl Vec3 {
     Vec3 { x, y, z }
   }
 
+  /// Constructs a new vector with the (r,g,b) channels of the color as its
+  ///   (x,y,z) components. This is the inverse of `Color3::to_vec3()`
+  pub fn from_color3( c : Color3 ) -> Vec3 {
+    c.to_vec3( )
+  }
+
   /// Constructs a new unit vector in the provided direction
   pub fn unit( x : f32, y : f32, z : f32 ) -> Vec3 {
     Vec3::new( x, y, z ).normalize( )
@@ -33,24 +45,17 @@ impl Vec3 {
     self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
   }
 
-  /// Returns some vector that is orthogonal to the current
+  /// Returns some unit vector that is orthogonal to the current
+  /// The current vector should be of unit length
+  ///
+  /// Uses the construction from "Building an Orthonormal Basis, Revisited" by
+  ///   T. Duff et al. (which improves on J. R. Frisvad's method). It is
+  ///   branchless and numerically stable for all input directions.
   pub fn orthogonal( self ) -> Vec3 {
-    if self.z.abs( ) > 0.1 {
-      let v1 = 1.0;
-      let v2 = 1.0;
-      let v3 = -( self.x * v1 + self.y * v2 ) / self.z;
-      Vec3::unit( v1, v2, v3 )
-    } else if self.x.abs( ) > 0.1 {
-      let v2 = 1.0;
-      let v3 = 1.0;
-      let v1 = -( self.y * v2 + self.z * v3 ) / self.x;
-      Vec3::unit( v1, v2, v3 )
-    } else {
-      let v1 = 1.0;
-      let v3 = 1.0;
-      let v2 = -( self.x * v1 + self.z * v3 ) / self.y;
-      Vec3::unit( v1, v2, v3 )
-    }
+    let sign = 1.0_f32.copysign( self.z );
+    let a = -1.0 / ( sign + self.z );
+    let b = self.x * self.y * a;
+    Vec3::new( 1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x )
   }
 
   /// Computes the crosss product with the provided Vec3
@@ -92,6 +97,60 @@ impl Vec3 {
     Vec3::new( self.x.exp( ), self.y.exp( ), self.z.exp( ) )
   }
 
+  /// Returns the largest of the three components
+  pub fn max_component( self ) -> f32 {
+    self.x.max( self.y ).max( self.z )
+  }
+
+  /// Constructs the unit vector with the provided spherical coordinates
+  /// `theta` is the angle with the positive y-axis (in [0,pi]), and `phi` is
+  ///   the angle around the y-axis (in [-pi,pi]), starting at the positive
+  ///   x-axis and rotating towards the positive z-axis.
+  pub fn from_spherical( theta : f32, phi : f32 ) -> Vec3 {
+    Vec3::new( theta.sin( ) * phi.cos( ), theta.cos( ), theta.sin( ) * phi.sin( ) )
+  }
+
+  /// Returns the spherical coordinates (theta, phi) of this unit vector
+  /// This is the inverse of `Vec3::from_spherical(..)`
+  pub fn to_spherical( self ) -> (f32, f32) {
+    // Clamp, as rounding errors may otherwise produce NaN
+    ( self.y.max( -1.0 ).min( 1.0 ).acos( ), self.z.atan2( self.x ) )
+  }
+
+  /// Linearly interpolates between `a` (at t=0) and `b` (at t=1)
+  pub fn lerp( a : Vec3, b : Vec3, t : f32 ) -> Vec3 {
+    a * ( 1.0 - t ) + b * t
+  }
+
+  /// Clamps every component within the range [min_val, max_val]
+  pub fn clamp_scalar( self, min_val : f32, max_val : f32 ) -> Vec3 {
+    Vec3::new(
+      max_val.min( min_val.max( self.x ) )
+    , max_val.min( min_val.max( self.y ) )
+    , max_val.min( min_val.max( self.z ) )
+    )
+  }
+
+  /// Clamps every component within the range given by the respective
+  /// components of `min_val` and `max_val`
+  pub fn clamp_per( self, min_val : Vec3, max_val : Vec3 ) -> Vec3 {
+    Vec3::new(
+      max_val.x.min( min_val.x.max( self.x ) )
+    , max_val.y.min( min_val.y.max( self.y ) )
+    , max_val.z.min( min_val.z.max( self.z ) )
+    )
+  }
+
+  /// Returns the absolute value of every component
+  pub fn abs( self ) -> Vec3 {
+    Vec3::new( self.x.abs( ), self.y.abs( ), self.z.abs( ) )
+  }
+
+  /// Returns the sign of every component (see `f32::signum()`)
+  pub fn sign( self ) -> Vec3 {
+    Vec3::new( self.x.signum( ), self.y.signum( ), self.z.signum( ) )
+  }
+
   pub fn rot_y( &self, angle : f32 ) -> Vec3 {
     // [  c 0 s ] [x]
     // [  0 1 0 ] [y]
@@ -188,4 +247,26 @@ impl fmt::Debug for Vec3 {
     write!( f, "vec3({}, {}, {})", self.x, self.y, self.z )
   }
 }
-          
\ No newline at end of file
+          
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rng::Rng;
+
+  #[test]
+  fn orthogonal_is_orthogonal_unit_vector( ) {
+    let mut rng = Rng::with_state( 1234 );
+    // Gaussian components give directions uniformly over the sphere. The
+    // poles are added explicitly, as those are where the construction flips
+    let mut dirs = vec![ Vec3::new( 0.0, 0.0, 1.0 ), Vec3::new( 0.0, 0.0, -1.0 ) ];
+    for _i in 0..1000 {
+      dirs.push( Vec3::new( rng.next_gaussian( 0.0, 1.0 ), rng.next_gaussian( 0.0, 1.0 ), rng.next_gaussian( 0.0, 1.0 ) ).normalize( ) );
+    }
+
+    for d in dirs {
+      let o = d.orthogonal( );
+      assert!( d.dot( o ).abs( ) < 1e-5, "{:?} not orthogonal to {:?}", o, d );
+      assert!( ( o.len( ) - 1.0 ).abs( ) < 1e-5, "{:?} is not of unit length", o );
+    }
+  }
+}
@@ -1,11 +1,14 @@
 // Stdlib imports
 use crate::math::Vec3;
+use crate::graphics::luminance;
 
 /// A pixel buffer
 pub struct RenderTarget {
   pub viewport_width  : usize,
   pub viewport_height : usize,
   acc_buffer          : Vec< Vec3 >,
+  // The accumulated *squares* of the samples. Used for variance estimation
+  acc_sq_buffer       : Vec< Vec3 >,
   acc_count           : Vec< usize >,
   result              : Vec< u8 >
 }
@@ -29,22 +32,24 @@ static GAUSS5: [f32; 25] =
 impl RenderTarget {
   /// Constructs a new render target with the given viewport size
   pub fn new( viewport_width : usize, viewport_height : usize ) -> RenderTarget {
-    let acc_buffer = vec![ Vec3::ZERO; viewport_width * viewport_height ];
-    let acc_count  = vec![ 0; viewport_width * viewport_height ];
-    let mut result = vec![ 0; viewport_width * viewport_height * 4 ];
+    let acc_buffer    = vec![ Vec3::ZERO; viewport_width * viewport_height ];
+    let acc_sq_buffer = vec![ Vec3::ZERO; viewport_width * viewport_height ];
+    let acc_count     = vec![ 0; viewport_width * viewport_height ];
+    let mut result    = vec![ 0; viewport_width * viewport_height * 4 ];
 
     for i in 0..(viewport_width * viewport_height) {
       result[ i * 4 + 3 ] = 255;
     }
 
-    RenderTarget { viewport_width, viewport_height, acc_buffer, acc_count, result }
+    RenderTarget { viewport_width, viewport_height, acc_buffer, acc_sq_buffer, acc_count, result }
   }
 
   /// Clears the render target
   pub fn clear( &mut self ) {
     for i in 0..(self.viewport_width * self.viewport_height) {
-      self.acc_buffer[ i ] = Vec3::ZERO;
-      self.acc_count[ i ]  = 0;
+      self.acc_buffer[ i ]    = Vec3::ZERO;
+      self.acc_sq_buffer[ i ] = Vec3::ZERO;
+      self.acc_count[ i ]     = 0;
       self.result[ i * 4 + 0 ] = 0;
       self.result[ i * 4 + 1 ] = 0;
       self.result[ i * 4 + 2 ] = 0;
@@ -54,14 +59,44 @@ impl RenderTarget {
   /// Writes the given value *for a single sample* to the target
   pub fn write( &mut self, x : usize, y : usize, v : Vec3 ) {
     let i = self.viewport_width * y + x;
-    self.acc_buffer[ i ] += v;
-    self.acc_count[ i ]  += 1;
+    self.acc_buffer[ i ]    += v;
+    self.acc_sq_buffer[ i ] += v * v;
+    self.acc_count[ i ]     += 1;
+    self.update_result( i );
+  }
+
+  /// Writes a batch of `(x, y, value)` samples to the target, which is
+  ///   equivalent to calling `write(..)` for each. The samples are first
+  ///   accumulated in order of their pixel index, after which the displayed
+  ///   result is updated once per touched pixel.
+  pub fn accumulate_batch( &mut self, samples : &[(usize, usize, Vec3)] ) {
+    let mut indices : Vec< usize > =
+      samples.iter( ).map( |&(x, y, _)| self.viewport_width * y + x ).collect( );
+    let mut order : Vec< usize > = ( 0..samples.len( ) ).collect( );
+    order.sort_by_key( |&j| indices[ j ] );
+
+    for &j in &order {
+      let i = indices[ j ];
+      let v = samples[ j ].2;
+      self.acc_buffer[ i ]    += v;
+      self.acc_sq_buffer[ i ] += v * v;
+      self.acc_count[ i ]     += 1;
+    }
 
-    let v     = self.acc_buffer[ i ];
+    indices.sort_unstable( );
+    indices.dedup( );
+    for i in indices {
+      self.update_result( i );
+    }
+  }
+
+  // Updates the displayed (averaged) color of the pixel at linear index `i`
+  fn update_result( &mut self, i : usize ) {
     let count = self.acc_count[ i ];
-    self.result[ i * 4 + 0 ] = ( ( v.x / count as f32 ).min( 1.0 ).max( 0.0 ) * 255.0 ) as u8;
-    self.result[ i * 4 + 1 ] = ( ( v.y / count as f32 ).min( 1.0 ).max( 0.0 ) * 255.0 ) as u8;
-    self.result[ i * 4 + 2 ] = ( ( v.z / count as f32 ).min( 1.0 ).max( 0.0 ) * 255.0 ) as u8;
+    let c     = ( self.acc_buffer[ i ] / count as f32 ).clamp_scalar( 0.0, 1.0 ) * 255.0;
+    self.result[ i * 4 + 0 ] = c.x as u8;
+    self.result[ i * 4 + 1 ] = c.y as u8;
+    self.result[ i * 4 + 2 ] = c.z as u8;
   }
 
   /// Reads the averaged value (over all samples) for the given pixel
@@ -73,7 +108,39 @@ impl RenderTarget {
   /// Reads the averaged value (over all samples) for the given pixel
   pub fn read_clamped( &self, x : usize, y : usize ) -> Vec3 {
     let i = self.viewport_width * y + x;
-    clamp( self.acc_buffer[ i ] / self.acc_count[ i ] as f32 )
+    ( self.acc_buffer[ i ] / self.acc_count[ i ] as f32 ).clamp_scalar( 0.0, 1.0 )
+  }
+
+  /// Returns the (biased) sample variance of the given pixel. The variances of
+  /// the three color channels are weighted by their contribution to the
+  /// luminance, which approximates the perceived error.
+  /// If no samples were taken for the pixel, 0 is returned
+  pub fn variance( &self, x : usize, y : usize ) -> f32 {
+    let i = self.viewport_width * y + x;
+    let count = self.acc_count[ i ];
+
+    if count == 0 {
+      0.0
+    } else {
+      let mean    = self.acc_buffer[ i ] / count as f32;
+      let mean_sq = self.acc_sq_buffer[ i ] / count as f32;
+      luminance( mean_sq - mean * mean ).max( 0.0 )
+    }
+  }
+
+  /// Returns the number of samples taken for the given pixel
+  pub fn sample_count( &self, x : usize, y : usize ) -> usize {
+    self.acc_count[ self.viewport_width * y + x ]
+  }
+
+  /// Returns the smallest number of samples taken for any pixel
+  pub fn min_sample_count( &self ) -> usize {
+    self.acc_count.iter( ).cloned( ).min( ).unwrap_or( 0 )
+  }
+
+  /// Returns the largest number of samples taken for any pixel
+  pub fn max_sample_count( &self ) -> usize {
+    self.acc_count.iter( ).cloned( ).max( ).unwrap_or( 0 )
   }
 
   /// Returns a reference to the averaged pixel buffer
@@ -81,6 +148,29 @@ impl RenderTarget {
     &self.result
   }
 
+  /// Returns the averaged (linear-light) color of every pixel as packed
+  ///   `[r, g, b, r, g, b, ...]` values, row by row. Unlike `results()`, these
+  ///   are not clamped nor quantized, which makes them suitable for tone
+  ///   mapping elsewhere. Pixels without any samples are black.
+  pub fn to_raw_f32( &self ) -> Vec< f32 > {
+    let mut raw = Vec::with_capacity( self.acc_buffer.len( ) * 3 );
+
+    for i in 0..self.acc_buffer.len( ) {
+      let count = self.acc_count[ i ];
+      let c =
+        if count == 0 {
+          Vec3::ZERO
+        } else {
+          self.acc_buffer[ i ] / count as f32
+        };
+      raw.push( c.x );
+      raw.push( c.y );
+      raw.push( c.z );
+    }
+
+    raw
+  }
+
   // Applies the 3x3 Guassian kernel to the pixel at (x,y)
   // [1 2 1]
   // [2 4 2]
@@ -170,9 +260,23 @@ impl SimpleRenderTarget {
   pub fn write( &mut self, x : usize, y : usize, v : Vec3 ) {
     let i = self.viewport_width * y + x;
 
-    self.result[ i * 4 + 0 ] = ( ( v.x as f32 ).min( 1.0 ).max( 0.0 ) * 255.0 ) as u8;
-    self.result[ i * 4 + 1 ] = ( ( v.y as f32 ).min( 1.0 ).max( 0.0 ) * 255.0 ) as u8;
-    self.result[ i * 4 + 2 ] = ( ( v.z as f32 ).min( 1.0 ).max( 0.0 ) * 255.0 ) as u8;
+    let c = v.clamp_scalar( 0.0, 1.0 ) * 255.0;
+    self.result[ i * 4 + 0 ] = c.x as u8;
+    self.result[ i * 4 + 1 ] = c.y as u8;
+    self.result[ i * 4 + 2 ] = c.z as u8;
+  }
+
+  /// Reads the value of the given pixel, as it was last written. As it is
+  ///   reconstructed from the quantized result, it is clamped to [0,1] and
+  ///   only accurate up to 1/255
+  pub fn read( &self, x : usize, y : usize ) -> Vec3 {
+    let i = self.viewport_width * y + x;
+
+    Vec3::new(
+      self.result[ i * 4 + 0 ] as f32
+    , self.result[ i * 4 + 1 ] as f32
+    , self.result[ i * 4 + 2 ] as f32
+    ) / 255.0
   }
 
   /// Returns a reference to the averaged pixel buffer
@@ -180,7 +284,3 @@ impl SimpleRenderTarget {
     &self.result
   }
 }
-
-fn clamp( v : Vec3 ) -> Vec3 {
-  Vec3::new( v.x.max( 0.0 ).min( 1.0 ), v.y.max( 0.0 ).min( 1.0 ), v.z.max( 0.0 ).min( 1.0 ) )
-}
@@ -3,16 +3,43 @@ use crate::math::Vec3;
 /// It turns out the Rust `rand` module does not compile to WebAssembly
 /// So I implemented my own, which is the XOR shift
 pub struct Rng {
-  state : u32
+  state  : u32,
+  // The second sample of the last Box-Muller transform, if it is not used yet
+  // It is normally distributed with mean 0 and standard deviation 1
+  // (See `Rng::next_gaussian(..)`)
+  cached : Option< f32 >
 }
 
 impl Rng {
   pub fn new( ) -> Rng {
-    Rng { state: 0xBABABEBE }
+    Rng { state: 0xBABABEBE, cached: None }
   }
 
   pub fn with_state( state : u32 ) -> Rng {
-    Rng { state }
+    Rng { state, cached: None }
+  }
+
+  /// Returns the current state of the generator. Restoring it with
+  ///   `Rng::set_state(..)` replays the same stream of numbers
+  pub fn get_state( &self ) -> u32 {
+    self.state
+  }
+
+  /// Replaces the state of the generator (See `Rng::get_state()`). As the
+  ///   xorshift state must not be 0, a state of 0 is replaced by 1.
+  pub fn set_state( &mut self, state : u32 ) {
+    self.state  = state.max( 1 );
+    // The cached gaussian sample belongs to the old stream
+    self.cached = None;
+  }
+
+  /// Creates a new generator, whose stream is independent of this one (and of
+  ///   those forked with other seeds). It is determined only by the current
+  ///   state and the seed. This does not advance the current generator.
+  pub fn fork( &self, seed : u32 ) -> Rng {
+    let state = hash_u32( self.state ^ hash_u32( seed ) );
+    // The xorshift state must not be 0
+    Rng::with_state( state.max( 1 ) )
   }
 
   /// Uniformly generates a f32 in the range of [0,1]
@@ -20,13 +47,42 @@ impl Rng {
     self.next_u32( ) as f32 * ( 1.0 / 0xFFFFFFFFu32 as f32 )
   }
 
+  /// Uniformly generates a f32 in the range of [low,high]
+  pub fn next_f32_in_range( &mut self, low : f32, high : f32 ) -> f32 {
+    low + self.next( ) * ( high - low )
+  }
+
+  /// Generates a normally distributed f32 with the provided mean and standard
+  ///   deviation
+  /// The Box-Muller transform produces two independent samples from two
+  ///   uniform samples. The second is cached, and returned by the next call
+  pub fn next_gaussian( &mut self, mean : f32, stddev : f32 ) -> f32 {
+    if let Some( z ) = self.cached.take( ) {
+      return mean + stddev * z;
+    }
+
+    // `ln(0)` is undefined, so avoid `u1 == 0`
+    let mut u1;
+    while {
+      u1 = self.next( );
+      u1 <= 0.0
+    } { }
+    let u2 = self.next( );
+
+    let r     = ( -2.0 * u1.ln( ) ).sqrt( );
+    let theta = 2.0 * std::f32::consts::PI * u2;
+
+    self.cached = Some( r * theta.sin( ) );
+    mean + stddev * r * theta.cos( )
+  }
+
   /// Returns a random element in the range [low, high)
   /// (So it includes the low value, and excludes the high one)
   pub fn next_in_range( &mut self, low : usize, high : usize ) -> usize {
     if high <= low {
       panic!( "Invalid range" );
     } else if high == low + 1 {
-      0
+      low
     } else {
       let f = self.next( );
       if f == 1.0 {
@@ -48,17 +104,13 @@ impl Rng {
 
   // Returns a random point on the hemisphere, for which `normal` is the normal
   pub fn next_hemisphere( &mut self, normal : &Vec3 ) -> Vec3 {
-    let (mut x, mut y, mut z) : (f32,f32,f32);
+    // Uniformly picks a point on the sphere. (As the area of a spherical
+    // zone is proportional to its height, its cosine is uniform)
+    let phi       = 2.0 * std::f32::consts::PI * self.next( );
+    let cos_theta = 2.0 * self.next( ) - 1.0;
+    let sin_theta = ( 1.0 - cos_theta * cos_theta ).max( 0.0 ).sqrt( );
 
-    while {
-      x = self.next( ) * 2.0 - 1.0;
-      y = self.next( ) * 2.0 - 1.0;
-      z = self.next( ) * 2.0 - 1.0;
-      let len_sq = x * x + y * y + z * z;
-      len_sq > 1.0
-    } { }
-    
-    let v = Vec3::unit( x, y, z );
+    let v = Vec3::new( sin_theta * phi.cos( ), sin_theta * phi.sin( ), cos_theta );
 
     if v.dot( *normal ) < 0.0 {
       -v
@@ -67,10 +119,58 @@ impl Rng {
     }
   }
 
+  /// Uniformly shuffles the elements (Fisher-Yates). Every element is swapped
+  /// with a random element *at or after* it, such that every permutation is
+  /// equally likely
   pub fn shuffle< T >( &mut self, xs : &mut [T] ) {
     for i in 0..xs.len( ) {
-      let new_i = self.next_in_range( 0, xs.len( ) );
+      let new_i = self.next_in_range( i, xs.len( ) );
       xs.swap( i, new_i );
     }
   }
 }
+
+/// The hash function of the PCG random number generator. It thoroughly mixes
+///   the bits of its input, so similar inputs produce unrelated outputs
+fn hash_u32( x : u32 ) -> u32 {
+  let state = x.wrapping_mul( 747796405 ).wrapping_add( 2891336453 );
+  let word  = ( ( state >> ( ( state >> 28 ) + 4 ) ) ^ state ).wrapping_mul( 277803737 );
+  ( word >> 22 ) ^ word
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn next_f32_in_range_is_uniform( ) {
+    let mut rng = Rng::with_state( 1234 );
+    let n = 100000;
+    let xs : Vec< f64 > = ( 0..n ).map( |_| rng.next_f32_in_range( -1.0, 1.0 ) as f64 ).collect( );
+
+    let mean     = xs.iter( ).sum::< f64 >( ) / n as f64;
+    let variance = xs.iter( ).map( |x| ( x - mean ) * ( x - mean ) ).sum::< f64 >( ) / n as f64;
+
+    assert!( mean.abs( ) < 0.01, "mean: {}", mean );
+    assert!( ( variance - 1.0 / 3.0 ).abs( ) < 0.01, "variance: {}", variance );
+  }
+
+  #[test]
+  fn shuffle_produces_all_permutations_equally( ) {
+    let mut rng = Rng::with_state( 1234 );
+    let n = 60000;
+    let mut counts = std::collections::HashMap::new( );
+
+    for _i in 0..n {
+      let mut xs = [ 0, 1, 2 ];
+      rng.shuffle( &mut xs );
+      *counts.entry( xs ).or_insert( 0 ) += 1;
+    }
+
+    // All 3! = 6 permutations should be (about) equally likely
+    assert_eq!( counts.len( ), 6 );
+    for (perm, count) in counts {
+      assert!( ( count as i32 - n / 6 ).abs( ) < n / 6 / 20, "{:?} occurred {} times", perm, count );
+    }
+  }
+}
@@ -2,7 +2,7 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 // Local imports
-use crate::graphics::{ Color3, Material, Scene };
+use crate::graphics::{ Color3, Material, Scene, SceneBuilder };
 use crate::graphics::primitives::{ AARect, Plane, Triangle, Torus };
 use crate::graphics::ray::{ Tracable };
 use crate::graphics::Mesh;
@@ -15,7 +15,8 @@ static MESH_BUNNY_HIGH : u32 = 1;
 pub fn setup_scene_museum( ) -> Scene {
   let mut shapes: Vec< Rc< dyn Tracable > > = Vec::new( );
 
-  shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Material::diffuse( Color3::new( 0.7, 0.7, 0.7 ) ) ) ) );
+  let floor_mat = Material::diffuse_checker( Color3::new( 0.7, 0.7, 0.7 ), Color3::new( 0.5, 0.5, 0.5 ), 2.0 );
+  shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), floor_mat ) ) );
 
   // ## Render the tori
   let xs = vec![ -16.0, -12.0, -8.0, -4.0, 0.0, 4.0, 8.0, 12.0, 16.0 ];
@@ -33,7 +34,7 @@ pub fn setup_scene_museum( ) -> Scene {
 
   for y in vec![ -7.5, 0.0, 7.5 ] {
     for i in 0..xs.len( ) {
-      shapes.push( Rc::new( Torus::new(  Vec3::new(  xs[ i ], -0.5, y ), 1.3, 0.3, Material::diffuse( Color3::new( 1.0, 1.0, 1.0 ) ) ) ) );
+      shapes.push( Rc::new( Torus::new(  Vec3::new(  xs[ i ], -0.5, y ), 1.3, 0.3, Material::diffuse( Color3::WHITE ) ) ) );
       museum_lights( &mut shapes, xs[ i ], y, colors[ i ].to_vec3( ) * 2.5 );
     }
     rng.shuffle( &mut colors );
@@ -48,7 +49,10 @@ pub fn setup_scene_museum( ) -> Scene {
   shapes.push( Rc::new( AARect::new( -20.0, 20.0, -1.0, 2.0,  3.75 - 0.1,  3.75 + 0.1, Material::diffuse( Color3::new( 0.7, 0.7, 0.7 ) ) ) ) );
   shapes.push( Rc::new( AARect::new( -20.0, 20.0, -1.0, 2.0, -3.75 - 0.1, -3.75 + 0.1, Material::diffuse( Color3::new( 0.7, 0.7, 0.7 ) ) ) ) );
 
-  Scene::new( Color3::BLACK, vec![ ], shapes )
+  SceneBuilder::new( )
+    .background( Color3::BLACK )
+    .add_shapes( shapes )
+    .build( )
 }
 
 fn museum_lights( dst : &mut Vec< Rc< dyn Tracable > >, x : f32, y : f32, color : Vec3 ) {
@@ -80,7 +84,7 @@ fn display_obj( meshes : &HashMap< u32, Mesh >, mesh_id : u32 ) -> Scene {
     if let Some( Mesh::Triangled( ts ) ) = meshes.get( &mesh_id ) {
       let num_triangles = ts.len( );
       let mut shapes : Vec< Rc< dyn Tracable > > = Vec::with_capacity( num_triangles + 2 );
-      shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Material::diffuse( Color3::new( 1.0, 1.0, 1.0 ) ) ) ) );
+      shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Material::diffuse( Color3::WHITE ) ) ) );
       shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, 0.0, 13.0 ), Vec3::new( 0.0, 0.0, -1.0 ), Material::diffuse( Color3::new( 0.8, 1.0, 0.8 ) ) ) ) );
       
       for t in ts {
@@ -90,7 +94,7 @@ fn display_obj( meshes : &HashMap< u32, Mesh >, mesh_id : u32 ) -> Scene {
       shapes
     } else {
       let mut shapes : Vec< Rc< dyn Tracable > > = Vec::new( );
-      shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Material::diffuse( Color3::new( 1.0, 1.0, 1.0 ) ) ) ) );
+      shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Material::diffuse( Color3::WHITE ) ) ) );
       shapes.push( Rc::new( Plane::new( Vec3::new( 0.0, 0.0, 13.0 ), Vec3::new( 0.0, 0.0, -1.0 ), Material::diffuse( Color3::new( 0.8, 1.0, 0.8 ) ) ) ) );
       shapes
     };
@@ -107,7 +111,12 @@ fn display_obj( meshes : &HashMap< u32, Mesh >, mesh_id : u32 ) -> Scene {
   shapes.push( Rc::new( Triangle::new( lc3, lc2, lc1, Material::emissive( Vec3::new( 16.0, 16.0, 16.0 ) ) ) ) );
   shapes.push( Rc::new( Triangle::new( lc4, lc3, lc1, Material::emissive( Vec3::new( 16.0, 16.0, 16.0 ) ) ) ) );
 
-  Scene::new( Color3::BLACK, vec![ /*light, light2*/ ], shapes )
+  SceneBuilder::new( )
+    .background( Color3::BLACK )
+    // .add_light( light )
+    // .add_light( light2 )
+    .add_shapes( shapes )
+    .build( )
 }
 
 // Turner Whitted's scene
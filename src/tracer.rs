@@ -3,25 +3,64 @@ use std::f32::INFINITY;
 use std::rc::Rc;
 use std::cell::RefCell;
 // Local imports
-use crate::graphics::{PointMaterial, Scene, LightEnum};
+use crate::graphics::{PointMaterial, Scene, LightEnum, luminance};
+use crate::graphics::lights::Light;
 use crate::graphics::ray::{Ray};
-use crate::math::{EPSILON, Vec3};
+use crate::math::{EPSILON, Vec3, Quat};
 use crate::render_target::RenderTarget;
 use crate::data::PhotonTree;
-use crate::graphics::{SamplingStrategy};
+use crate::graphics::{SamplingStrategy, SamplingStats};
 use crate::rng::Rng;
 
+/// The number of photons that is gathered for PNEE, before rendering starts
+static PHOTONS_NEEDED : usize = 300000;
+
+/// The number of photons that remain in the tree after gathering completes.
+///   As the tree's distributions are built during gathering, this only frees
+///   memory. (See `PhotonTree::resample()`)
+static PHOTONS_KEPT : usize = PHOTONS_NEEDED / 8;
+
 /// The scene camera.
-/// It first rotates around the x-axis, then around the y-axis, then it translates
+/// It first rotates, then it translates
 pub struct Camera {
   pub location : Vec3,
-  pub rot_x    : f32,
-  pub rot_y    : f32
+  pub rotation : Quat
 }
 
 impl Camera {
+  /// Constructs a camera that first rotates around the x-axis, then around the
+  /// y-axis, then it translates
   pub fn new( location : Vec3, rot_x : f32, rot_y : f32 ) -> Camera {
-    Camera { location, rot_x, rot_y }
+    let qx = Quat::from_axis_angle( Vec3::new( 1.0, 0.0, 0.0 ), rot_x );
+    let qy = Quat::from_axis_angle( Vec3::new( 0.0, 1.0, 0.0 ), rot_y );
+    Camera::with_rotation( location, qy * qx )
+  }
+
+  /// Constructs a camera with an arbitrary rotation (which may include roll)
+  pub fn with_rotation( location : Vec3, rotation : Quat ) -> Camera {
+    Camera { location, rotation: rotation.normalize( ) }
+  }
+
+  /// Constructs a camera at `location` that looks toward `target`, where the
+  ///   top of the view is oriented toward `up` as much as possible. (So `up`
+  ///   need not be orthogonal to the viewing direction)
+  /// The target should differ from the location.
+  pub fn look_at( location : Vec3, target : Vec3, up : Vec3 ) -> Camera {
+    // The camera looks along the z-axis before it is rotated
+    let dir   = ( target - location ).normalize( );
+    let rot_x = -dir.y.max( -1.0 ).min( 1.0 ).asin( );
+    let rot_y = dir.x.atan2( dir.z );
+    let base  = Camera::new( location, rot_x, rot_y ).rotation;
+
+    // Roll around the viewing direction, such that the camera's up-vector
+    // aligns with the component of `up` orthogonal to the viewing direction
+    let cam_up = base * Vec3::new( 0.0, 1.0, 0.0 );
+    let up_p   = up - dir * up.dot( dir );
+    if up_p.len_sq( ) < 1e-12 { // `up` is parallel to the viewing direction
+      return Camera::with_rotation( location, base );
+    }
+    let roll = dir.dot( cam_up.cross( up_p ) ).atan2( cam_up.dot( up_p ) );
+    Camera::with_rotation( location, Quat::from_axis_angle( dir, roll ) * base )
   }
 }
 
@@ -48,7 +87,33 @@ pub struct RenderInstance {
   is_debug_photons  : bool,
 
   photons     : PhotonTree,
-  num_photons : usize
+  num_photons : usize,
+  // The (min, max) corners of the photon octree. `None` for the default bounds
+  photon_bounds : Option< (Vec3, Vec3) >,
+
+  // The maximum value of every color channel of a single contribution to a
+  // path. Clamping introduces bias, but removes "fireflies" (single-sample
+  // bright spots). Disabled (INFINITY) by default.
+  firefly_clamp : f32,
+
+  // Russian roulette is only applied to paths whose throughput (its largest
+  // channel) drops below `rr_threshold`. Those are killed with probability
+  // `rr_kill_prob`.
+  rr_threshold : f32,
+  rr_kill_prob : f32,
+
+  // The maximum number of bounces of a path. This guarantees termination in
+  // degenerate scenes (e.g. a ray trapped between two parallel mirrors)
+  max_bounces : u32,
+
+  // The viewport is divided into tiles of (width, height) pixels, which each
+  // have their own random number generator. These are stored row by row.
+  // They are seeded from the shared generator, and never advanced; every
+  // sample forks its own stream from the generator of its tile.
+  tile_size : (usize, usize),
+  tile_rngs : Vec< Rng >,
+  // Incremented upon every reset, such that the tiles' streams differ per frame
+  frame_id  : u32
 }
 
 type ShapeId = usize;
@@ -69,6 +134,14 @@ impl RenderInstance {
       , is_debug_photons
       , photons:            PhotonTree::new( num_lights )
       , num_photons:        0
+      , photon_bounds:      None
+      , firefly_clamp:      INFINITY
+      , rr_threshold:       0.01
+      , rr_kill_prob:       0.9
+      , max_bounces:        32
+      , tile_size:          (16, 16)
+      , tile_rngs:          Vec::new( )
+      , frame_id:           0
       };
     ins.reset( );
     ins
@@ -85,33 +158,145 @@ impl RenderInstance {
     // Note: The `target` is reset externally
     self.num_bvh_hits = 0;
     self.sampling_strategy.reset( );
+    self.frame_id = self.frame_id.wrapping_add( 1 );
+    self.reset_tile_rngs( );
+  }
+
+  /// Sets the size of the tiles into which the viewport is divided, which each
+  ///   have their own random number generator
+  /// Note that this does *not* reset the renderer
+  pub fn set_tile_size( &mut self, width : usize, height : usize ) {
+    self.tile_size = ( width.max( 1 ), height.max( 1 ) );
+    self.reset_tile_rngs( );
+  }
+
+  /// Seeds the generator of every tile by its coordinates and the frame, which
+  ///   are hashed together with the state of the shared generator
+  /// Note that this does *not* reset the renderer
+  pub fn reset_tile_rngs( &mut self ) {
+    let (tiles_x, tiles_y) = self.num_tiles( );
+    let rng = self.rng.borrow( );
+
+    self.tile_rngs.clear( );
+    for ty in 0..tiles_y {
+      for tx in 0..tiles_x {
+        self.tile_rngs.push( rng.fork( tx as u32 ).fork( ty as u32 ).fork( self.frame_id ) );
+      }
+    }
+  }
+
+  /// Returns the number of tiles horizontally and vertically
+  fn num_tiles( &self ) -> (usize, usize) {
+    let target = self.target.borrow( );
+    let (tw, th) = self.tile_size;
+    ( target.viewport_width.div_ceil( tw ), target.viewport_height.div_ceil( th ) )
+  }
+
+  /// Returns the number of BVH nodes traversed since the last reset
+  /// Note that `reset()` (and thus `update_scene(..)`) also resets this count
+  pub fn num_bvh_hits( &self ) -> usize {
+    self.num_bvh_hits
+  }
+
+  /// Resets the number of traversed BVH nodes, without restarting the renderer
+  pub fn reset_bvh_hits( &mut self ) {
+    self.num_bvh_hits = 0;
+  }
+
+  /// Sets the maximum value of every color channel of a single contribution to
+  ///   a path. Use `INFINITY` to disable clamping.
+  /// Note that this does *not* reset the renderer
+  pub fn set_firefly_clamp( &mut self, firefly_clamp : f32 ) {
+    self.firefly_clamp = firefly_clamp;
+  }
+
+  /// Sets the Russian roulette parameters. Paths whose throughput drops below
+  ///   `threshold` are killed with probability `kill_prob`.
+  /// Note that this does *not* reset the renderer
+  pub fn set_russian_roulette( &mut self, threshold : f32, kill_prob : f32 ) {
+    self.rr_threshold = threshold;
+    self.rr_kill_prob = kill_prob.min( 1.0 ).max( 0.0 );
+  }
+
+  /// Sets the maximum number of bounces of a path
+  /// Note that this does *not* reset the renderer
+  pub fn set_max_bounces( &mut self, max_bounces : u32 ) {
+    self.max_bounces = max_bounces;
+  }
+
+  /// Sets the bounds of the photon octree (used for PNEE), to the box with
+  ///   corners `min` and `max`. This discards all photons, and restarts the
+  ///   renderer
+  pub fn set_photon_tree_bounds( &mut self, min : Vec3, max : Vec3 ) {
+    self.photon_bounds = Some( ( min, max ) );
+    self.num_photons   = 0;
+    self.photons       = self.new_photon_tree( self.scene.lights.len( ) );
+    self.reset( );
+  }
+
+  /// Returns the statistics of the sampling strategy
+  pub fn sampling_stats( &self ) -> SamplingStats {
+    self.sampling_strategy.stats( )
+  }
+
+  /// Returns the number of samples taken since the last reset
+  pub fn total_samples( &self ) -> usize {
+    self.sampling_strategy.total_samples( )
+  }
+
+  /// Returns the photons (used for PNEE)
+  pub fn photons( &self ) -> &PhotonTree {
+    &self.photons
+  }
+
+  /// Serializes the photons (used for PNEE). See `PhotonTree::to_bytes()`
+  pub fn save_photons( &self ) -> Vec< u8 > {
+    self.photons.to_bytes( )
+  }
+
+  /// Replaces the photons (used for PNEE) by `photons`, which must be for the
+  ///   same scene. (So its number of lights must match) These are used as-is;
+  ///   no further photons are gathered. This restarts the renderer
+  pub fn load_photons( &mut self, photons : PhotonTree ) {
+    // Saved trees may have been resampled, so their size says nothing about
+    // how many photons were gathered
+    self.num_photons = PHOTONS_NEEDED;
+    self.photons     = photons;
+    self.reset( );
   }
 
   /// From now on, the render instance will render the provided scene
   /// This restarts the renderer
   pub fn update_scene( &mut self, scene : Rc< Scene > ) {
     self.num_photons = 0;
-    self.photons     = PhotonTree::new( scene.lights.len( ) );
+    self.photons     = self.new_photon_tree( scene.lights.len( ) );
     self.scene       = scene;
     self.reset( );
   }
 
+  /// Constructs an empty photon tree with the configured bounds
+  fn new_photon_tree( &self, num_lights : usize ) -> PhotonTree {
+    if let Some( ( min, max ) ) = self.photon_bounds {
+      PhotonTree::new_with_bounds( num_lights, min, max )
+    } else {
+      PhotonTree::new( num_lights )
+    }
+  }
+
   /// Performs several computation "ticks"
   /// Typically, each tick corresponds to tracing one ray
   /// However, when PNEE is enabled, it may also correspond to tracing 32
   ///   photons into the scene.
   pub fn compute( &mut self, num_ticks : usize ) {
-    let total_photons_needed = 300000;
-
-    if self.option == RenderType::PNEE && self.num_photons < total_photons_needed {
-      let num_to_compute = ( total_photons_needed - self.num_photons ).min( num_ticks * 32 );
+    if self.option == RenderType::PNEE && self.num_photons < PHOTONS_NEEDED {
+      let num_to_compute = ( PHOTONS_NEEDED - self.num_photons ).min( num_ticks * 32 );
       // Note that calling this may not actually hit `num_to_compute` photons
       // it only shoots them, but they're only counted when hit
       self.preprocess_photons( num_to_compute );
 
       let mut ticks_left = num_ticks - num_to_compute / 32;
-      while ticks_left > 0 && self.num_photons < total_photons_needed {
-        let num_to_compute = ( total_photons_needed - self.num_photons ).min( ticks_left * 32 );
+      while ticks_left > 0 && self.num_photons < PHOTONS_NEEDED {
+        let num_to_compute = ( PHOTONS_NEEDED - self.num_photons ).min( ticks_left * 32 );
         self.preprocess_photons( num_to_compute );
         ticks_left -= num_to_compute / 32;
       }
@@ -123,17 +308,40 @@ impl RenderInstance {
   }
 
   /// Preprocess some photons (only applicable for PNEE)
+  /// Once all photons are gathered, the tree is thinned to `PHOTONS_KEPT`
   fn preprocess_photons( &mut self, num_ticks : usize ) {
     let mut rng = self.rng.borrow_mut( );
     let scene   = &self.scene;
+    // The photons are inserted into the tree all at once, afterward
+    let mut photons = Vec::with_capacity( num_ticks );
 
     for _i in 0..num_ticks {
       let light_id = rng.next_in_range( 0, scene.lights.len( ) );
       match &scene.lights[ light_id ] {
-        LightEnum::Point( _ ) => panic!( "Pointlight unsupported" ),
-        LightEnum::Area( shape_id ) => {
+        LightEnum::Point( Light::Point( light ) ) => {
+          let (point_on_light, intensity, _) = light.pick_random( );
+          // Uniformly over the sphere
+          let mut light_dir = rng.next_hemisphere( &Vec3::new( 0.0, 1.0, 0.0 ) );
+          if rng.next( ) < 0.5 {
+            light_dir = -light_dir;
+          }
+          let ray = Ray::new( point_on_light, light_dir );
+          let (num_bvh_hits, m_hit) = scene.trace( &ray );
+          self.num_bvh_hits += num_bvh_hits;
+
+          if let Some( hit ) = m_hit {
+            let photon_hitpoint = ray.at( hit.distance ) + hit.normal * EPSILON;
+            if hit.mat.is_diffuse( ) {
+              photons.push( ( light_id, photon_hitpoint, luminance( intensity ) ) );
+              self.num_photons += 1;
+            }
+          }
+        },
+        LightEnum::Point( _ ) => panic!( "Only point lights are supported, of the 0-sized lights" ),
+        LightEnum::Area( shape_id, _ ) => {
           let light_shape = &scene.shapes[ *shape_id ];
-          let (point_on_light, ln, intensity) = light_shape.pick_random( &mut rng );
+          // Area lights always have a surface (see `Scene::update_area_lights()`)
+          let (point_on_light, ln, intensity) = light_shape.pick_random( &mut rng ).unwrap( );
           let light_normal = rng.next_hemisphere( &ln );
           let ray = Ray::new( point_on_light + light_normal * EPSILON, light_normal );
           let (num_bvh_hits, m_hit) = scene.trace( &ray );
@@ -142,13 +350,20 @@ impl RenderInstance {
           if let Some( hit ) = m_hit {
             let photon_hitpoint = ray.at( hit.distance ) + hit.normal * EPSILON;
             if hit.mat.is_diffuse( ) {
-              self.photons.insert( light_id, photon_hitpoint, ln.dot( light_normal ) * intensity.x.max( intensity.y ).max( intensity.z ) );
+              photons.push( ( light_id, photon_hitpoint, ln.dot( light_normal ) * luminance( intensity ) ) );
               self.num_photons += 1;
             }
           }
         }
       }
     }
+
+    self.photons.insert_batch( &photons );
+
+    // This is only called while gathering, so this happens once
+    if self.num_photons >= PHOTONS_NEEDED {
+      self.photons.resample( &mut rng, PHOTONS_KEPT );
+    }
   }
 
   /// Shoots several rays into the scene
@@ -171,29 +386,39 @@ impl RenderInstance {
       h_inv = 1.0 / fh as f32;
       ar    = fw / fh;
     }
+
+    let (tiles_x, _) = self.num_tiles( );
+    let (tw, th)     = self.tile_size;
+    let width        = self.target.borrow( ).viewport_width;
     
     for _i in 0..num_ticks {
       let (x,y) = self.sampling_strategy.next( );
 
-      let (fx, fy) =
+      // Every sample uses its own stream, forked from the generator of its
+      // tile by the pixel and the number of samples it already has. This makes
+      // a pixel's samples independent of the order in which pixels are sampled.
+      let tile = ( y / th ) * tiles_x + x / tw;
+      let mut pixel_rng =
         {
-          let mut rng = self.rng.borrow_mut( );
-          let fx = ( ( x as f32 + rng.next( ) ) * w_inv - 0.5_f32 ) * ar;
-          let fy = 0.5_f32 - ( y as f32 + rng.next( ) ) * h_inv;
-          (fx, fy)
+          let num_samples = self.target.borrow( ).sample_count( x, y );
+          self.tile_rngs[ tile ].fork( ( y * width + x ) as u32 ).fork( num_samples as u32 )
         };
+
+      let fx = ( ( x as f32 + pixel_rng.next( ) ) * w_inv - 0.5_f32 ) * ar;
+      let fy = 0.5_f32 - ( y as f32 + pixel_rng.next( ) ) * h_inv;
   
       let pixel = Vec3::new( fx, fy, 0.8 );
       let dir   = 
         {
           let camera = self.camera.borrow( );
-          pixel.normalize( ).rot_x( camera.rot_x ).rot_y( camera.rot_y )
+          camera.rotation * pixel.normalize( )
         };
       
-      let ray = Ray::new( origin, dir );
+      // The ray covers (approximately) the whole pixel, for texture filtering
+      let ray = Ray::new( origin, dir ).with_spread( h_inv / pixel.len( ) );
 
       // Note that `mat_stack` already contains the "material" for air (so now it's a stack of air)
-      let res = self.trace_original_color( &ray );
+      let res = self.trace_original_color( &ray, &mut pixel_rng );
 
       let mut target = self.target.borrow_mut( );
       target.write( x, y, res );
@@ -221,10 +446,11 @@ impl RenderInstance {
   /// Traces an original ray, and produces a color for that ray
   /// Note that the returned value can exceed (1,1,1), but it's *expected value*
   ///   is always between (0,0,0) and (1,1,1)
-  pub fn trace_original_color( &mut self, original_ray : &Ray ) -> Vec3 {
+  /// All random choices along the path are taken from `rng`
+  pub fn trace_original_color( &mut self, original_ray : &Ray, rng : &mut Rng ) -> Vec3 {
     let scene   = &self.scene;
-    let mut rng = self.rng.borrow_mut( );
     let has_nee = self.option == RenderType::NormalNEE || self.option == RenderType::PNEE;
+    let firefly_clamp = self.firefly_clamp;
 
     // The acculumator
     let mut color      = Vec3::ZERO;
@@ -233,6 +459,20 @@ impl RenderInstance {
     // Other status structures
     let mut ray = *original_ray;
     let mut has_diffuse_bounced = false;
+    let mut bounce_count = 0;
+
+    // Without photons, the lights are stratified over the NEE events of the
+    // path; the `k`-th event samples light `(light_offset + k) % num_lights`.
+    // The random offset keeps every event's light uniformly distributed, while
+    // a path samples every light once per `num_lights` events.
+    let num_lights = scene.lights.len( );
+    let light_offset =
+      if self.option == RenderType::NormalNEE && num_lights > 0 {
+        rng.next_in_range( 0, num_lights )
+      } else {
+        0
+      };
+    let mut nee_count = 0;
 
     loop {
       let (num_bvh_hits, m_hit) = scene.trace( &ray );
@@ -248,14 +488,25 @@ impl RenderInstance {
                 color += throughput * intensity;
               }
             } else if !has_nee || !has_diffuse_bounced {
-              color += throughput * intensity;
+              color += ( throughput * intensity ).clamp_scalar( 0.0, firefly_clamp );
             } // otherwise NEE is enabled, so ignore it
             return color;
           },
+          PointMaterial::Volume { albedo, .. } => {
+            // The phase function is sampled exactly, so only the albedo
+            // remains of the path weight
+            let (wi, _) = hit.mat.sample_hemisphere( rng, &-ray.dir, &hit.normal );
+            throughput = throughput * albedo;
+            ray = Ray::new( hit_point, wi );
+
+            // NEE is not performed inside volumes. So, any light that is hit
+            // next should be counted.
+            has_diffuse_bounced = false;
+          },
           _ => {
             let wo = -ray.dir;
             // A random next direction, with the probability of picking that direction
-            let (wi, pdf) = hit.mat.sample_hemisphere( &mut rng, &wo, &hit.normal );
+            let (wi, pdf) = hit.mat.sample_hemisphere( rng, &wo, &hit.normal );
             // The contribution of the path
             let brdf = hit.mat.brdf( &hit.normal, &wo, &wi );
             let cos_i = wi.dot( hit.normal ); // Geometry term
@@ -265,65 +516,48 @@ impl RenderInstance {
             has_diffuse_bounced = true;
 
             if has_nee {
-              // Pick a random light source
-
-              let (light_id, light_chance) =
+              let (num_bvh_hits, _, radiance, _) =
                 if self.option == RenderType::PNEE {
-                  self.photons.sample( &mut rng, hit_point )
-                  // let num_lights = scene.lights.len( );
-                  // (rng.next_in_range( 0, num_lights ), 1.0 / num_lights as f32)
+                  scene.lights_contribution( hit_point, hit.normal, rng, Some( &mut self.photons ), self.is_debug_photons )
                 } else {
-                  let num_lights = scene.lights.len( );
-                  (rng.next_in_range( 0, num_lights ), 1.0 / num_lights as f32)
+                  let light_id = ( light_offset + nee_count ) % num_lights;
+                  scene.light_contribution( hit_point, hit.normal, rng, light_id, 1.0 / num_lights as f32, self.is_debug_photons )
                 };
+              self.num_bvh_hits += num_bvh_hits;
+              nee_count += 1;
 
-              match scene.lights[ light_id ] {
-                LightEnum::Point { .. } => {
-                  // Point lights are not supported, for now
-                  panic!( "TODO: PointLight" );
-                },
-                LightEnum::Area( light_shape_id ) => {
-                  let light_shape = &scene.shapes[ light_shape_id ];
-
-                  let (point_on_light, light_normal, intensity) = light_shape.pick_random( &mut rng );
-                  let mut to_light = point_on_light - hit_point;
-                  let dis_sq = to_light.len_sq( );
-                  to_light = to_light / dis_sq.sqrt( );
-
-                  let cos_i = to_light.dot( hit.normal );
-                  let cos_o = (-to_light).dot( light_normal );
-
-                  if cos_i > 0.0 && cos_o > 0.0 {
-                    if self.is_debug_photons {
-                      // Physically *inaccurate* light-selection debug render
-                      color += throughput * intensity;
-                    } else {
-                      let (num_bvh_hits, is_occluded) = scene.shadow_ray( &hit_point, &point_on_light, Some( light_shape_id ) );
-                      self.num_bvh_hits += num_bvh_hits;
-
-                      if !is_occluded {
-                        let solid_angle = ( light_shape.surface_area( ) * cos_o ) / dis_sq;
-  
-                        color += throughput * intensity * solid_angle * cos_i * ( 1.0 / light_chance );
-                      }
-                    }
-                  }
-                }
+              if self.is_debug_photons {
+                color += throughput * radiance;
+              } else {
+                color += ( throughput * radiance ).clamp_scalar( 0.0, firefly_clamp );
               }
             }
           }
         }
 
+        bounce_count += 1;
+        if bounce_count > self.max_bounces {
+          return color;
+        }
+
         // Russian roulette
-        let keep_chance = throughput.x.max( throughput.y ).max( throughput.z ).min( 0.9 ).max( 0.1 );
+        if throughput.max_component( ) < self.rr_threshold {
+          let keep_chance = 1.0 - self.rr_kill_prob;
 
-        if rng.next( ) < keep_chance {
-          throughput = throughput * ( 1.0 / keep_chance );
-        } else {
-          return color;
+          if rng.next( ) < keep_chance {
+            throughput = throughput * ( 1.0 / keep_chance );
+          } else {
+            return color;
+          }
         }
       } else {
-        color += throughput * scene.background.to_vec3( );
+        let background =
+          if let Some( ref env_map ) = scene.env_map {
+            env_map.sample_equirectangular( ray.dir )
+          } else {
+            scene.background.to_vec3( )
+          };
+        color += ( throughput * background ).clamp_scalar( 0.0, firefly_clamp );
         return color;
       }
     }
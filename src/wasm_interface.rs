@@ -3,18 +3,20 @@ use wasm_bindgen::prelude::*;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::f32::INFINITY;
 // Local imports
 use crate::graphics::{Scene};
-use crate::graphics::ray::{Tracable};
-use crate::graphics::primitives::{Triangle};
-use crate::graphics::{Mesh, Texture, Color3};
-use crate::math::{Vec3};
+use crate::graphics::ray::{Ray, Tracable};
+use crate::graphics::primitives::{Triangle, SmoothTriangle, Sphere, Instance, Volume};
+use crate::graphics::{Mesh, Texture, HdrTexture, Color3, AABB};
+use crate::math::{Vec2, Vec3, Quat};
 use crate::scenes::{setup_scene_museum, setup_scene_bunny_high};
 use crate::tracer::{RenderInstance, RenderType, Camera};
 use crate::graphics::{Material};
 use crate::rng::Rng;
 use crate::render_target::{RenderTarget, SimpleRenderTarget};
 use crate::graphics::{SamplingStrategy, RandomSamplingStrategy, AdaptiveSamplingStrategy};
+use crate::data::PhotonTree;
 
 // This file contains all the functions that are exposed through WebAssembly
 // Interfacing with JavaScript is a bit annoying, as only primitives (i32, i64, f32, f64)
@@ -38,6 +40,10 @@ struct Config {
   // ## Global State
   meshes          : HashMap< u32, Mesh >,
   textures        : HashMap< u32, Texture >,
+  hdr_textures    : HashMap< u32, HdrTexture >,
+  // The HDR texture that is the environment map of every scene (See
+  // `set_env_map(..)`)
+  env_map_id      : Option< u32 >,
   rng             : Rc< RefCell< Rng > >,
 
   // ## Session State
@@ -49,6 +55,37 @@ struct Config {
   scene_id        : u32,
   scene           : Rc< Scene >,
   camera          : Rc< RefCell< Camera > >,
+  // See `RenderInstance::set_firefly_clamp(..)`
+  firefly_clamp   : f32,
+  // See `RenderInstance::set_russian_roulette(..)`
+  rr_threshold    : f32,
+  rr_kill_prob    : f32,
+  // See `RenderInstance::set_max_bounces(..)`
+  max_bounces     : u32,
+  // See `RenderInstance::set_tile_size(..)`
+  tile_size       : (usize, usize),
+  // See `RenderInstance::set_photon_tree_bounds(..)`
+  photon_bounds   : Option< (Vec3, Vec3) >,
+  // Storage for serialized photon trees (See `save_photon_tree()`)
+  photon_bytes    : Vec< u8 >,
+  // Storage for the photon density grid (See `get_photon_density_buffer(..)`)
+  photon_density  : Vec< f32 >,
+  // Storage for the sampling statistics (See `get_sampling_stats_json()`)
+  sampling_stats  : String,
+  // Storage for the unclamped render result (See `get_raw_f32_buffer()`)
+  raw_f32         : Vec< f32 >,
+  // See `Scene::set_bvh_leaf_size(..)`
+  bvh_leaf_size   : usize,
+  // See `Scene::set_bvh_ropes_enabled(..)`
+  is_bvh_ropes_enabled : bool,
+  // See `Scene::set_infinite_shapes_enabled(..)`
+  is_infinite_shapes_enabled : bool,
+  // Scenes containing only a mesh, whose BVH is shared by its instances
+  // (See `add_instance(..)`)
+  mesh_scenes     : HashMap< u32, Rc< Scene > >,
+  // The instances in the current scene, by their identifier
+  instances       : HashMap< u32, Rc< dyn Tracable > >,
+  next_instance_id : u32,
 
   // The viewport is split into two halves. The different parts can have
   // different rendering settings. Which is mainly useful for debugging.
@@ -61,18 +98,71 @@ struct Config {
 ///   module as a single encapsulated entity, with its own state.
 static mut CONFIG : Option< Config > = None;
 
+/// A buffer for returning colors to JavaScript, as tuples cannot cross the
+///   boundary. Functions that return a pointer to it return (r,g,b) or (h,s,v)
+///   through it. Its contents are only valid until the next such call.
+static mut COLOR_RESULT : [f32; 3] = [0.0; 3];
+
+/// A buffer for returning the scene bounds to JavaScript, as
+///   [x_min, y_min, z_min, x_max, y_max, z_max]. (See `get_scene_bounds_ptr`)
+static mut BOUNDS_RESULT : [f32; 6] = [0.0; 6];
+
+/// The message of the most recent error (See `get_last_error()`)
+/// Rather than panicking (which leaves the WASM instance in an undefined
+///   state), exported functions store their error here, and return a sentinel
+///   value (such as 0, `false` or a null pointer). Every exported function
+///   first clears it, so it only describes the error of the latest call.
+static mut LAST_ERROR : String = String::new( );
+
+/// Stores the error message, such that JavaScript can obtain it through
+///   `get_last_error()`
+fn set_last_error( msg : &str ) {
+  unsafe {
+    LAST_ERROR = msg.to_string( );
+  }
+}
+
+/// Clears the error message. (See `LAST_ERROR`)
+fn clear_last_error( ) {
+  unsafe {
+    LAST_ERROR = String::new( );
+  }
+}
+
+/// Returns a pointer to the (UTF-8) message of the most recent error. Its
+///   length is obtained with `get_last_error_len()`. It is empty if the latest
+///   call succeeded.
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_last_error( ) -> *const u8 {
+  unsafe {
+    ( &*std::ptr::addr_of!( LAST_ERROR ) ).as_ptr( )
+  }
+}
+
+/// Returns the length (in bytes) of the most recent error message
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_last_error_len( ) -> usize {
+  unsafe {
+    ( &*std::ptr::addr_of!( LAST_ERROR ) ).len( )
+  }
+}
+
 /// Initialises the *Session State*.
 #[wasm_bindgen]
 #[allow(dead_code)]
 pub fn init( width : u32, height : u32, scene_id : u32
            , cam_x : f32, cam_y : f32, cam_z : f32, cam_rot_x : f32, cam_rot_y : f32 ) {
+  clear_last_error( );
   unsafe {
     // Here is quite some code duplication, but this is hard to avoid as global state needs
     // to remain preserved. Doing this otherwise causes Rust to allocate a copy of this global
     // state, which is too expensive. (It contains all triangle meshes)
     
-    if !CONFIG.is_none( ) {
-      panic!( "Cannot init again" );
+    if ( &*std::ptr::addr_of!( CONFIG ) ).is_some( ) {
+      set_last_error( "Cannot init again. Call destroy first" );
+      return;
     }
 
     let left_width = ( width / 2 ) as usize;
@@ -81,10 +171,17 @@ pub fn init( width : u32, height : u32, scene_id : u32
     let target          = Rc::new( RefCell::new( RenderTarget::new( width as usize, height as usize ) ) );
     let sampling_target = Rc::new( RefCell::new( SimpleRenderTarget::new( width as usize, height as usize ) ) );
     
-    let meshes   = HashMap::new( );
-    let textures = HashMap::new( );
-    let scene    = Rc::new( select_scene( scene_id, &meshes, &textures ) );
-    let rng      = Rc::new( RefCell::new( Rng::new( ) ) );
+    let meshes       = HashMap::new( );
+    let textures     = HashMap::new( );
+    let hdr_textures = HashMap::new( );
+    let scene =
+      if let Some( scene ) = select_scene( scene_id, &meshes, &textures ) {
+        Rc::new( scene )
+      } else {
+        set_last_error( "Invalid scene" );
+        return;
+      };
+    let rng          = Rc::new( RefCell::new( Rng::new( ) ) );
 
     // The initial settings in the Elm panel are reflected here.
     let left_sampling  = Box::new( RandomSamplingStrategy::new( 0, 0, left_width, height as usize, rng.clone( ), sampling_target.clone( ) ) );
@@ -97,6 +194,8 @@ pub fn init( width : u32, height : u32, scene_id : u32
       // ## Global State
       meshes
     , textures
+    , hdr_textures
+    , env_map_id:       None
     , rng:              rng.clone( )
 
       // ## Session State
@@ -105,6 +204,22 @@ pub fn init( width : u32, height : u32, scene_id : u32
     , scene_id
     , scene:            scene.clone( )
     , camera
+    , firefly_clamp:    INFINITY
+    , rr_threshold:     0.01
+    , rr_kill_prob:     0.9
+    , max_bounces:      32
+    , tile_size:        (16, 16)
+    , photon_bounds:    None
+    , photon_bytes:     Vec::new( )
+    , photon_density:   Vec::new( )
+    , sampling_stats:   String::new( )
+    , raw_f32:          Vec::new( )
+    , bvh_leaf_size:    1
+    , is_bvh_ropes_enabled: false
+    , is_infinite_shapes_enabled: true
+    , mesh_scenes:      HashMap::new( )
+    , instances:        HashMap::new( )
+    , next_instance_id: 1
 
     , left_instance
     , right_instance
@@ -112,12 +227,25 @@ pub fn init( width : u32, height : u32, scene_id : u32
   }
 }
 
+/// Destroys the *Session State* and the *Global State*, which frees all
+///   associated memory (including meshes and textures).
+/// Afterwards `init(..)` may be called again.
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn destroy( ) {
+  clear_last_error( );
+  unsafe {
+    CONFIG = None;
+  }
+}
+
 /// Returns a pointer to the resulting buffer
 /// This buffer is of size `viewport_width * viewport_height`
 /// If `is_show_sampling` is 1, the pixel sampling frequency is shown instead
 #[wasm_bindgen]
 #[allow(dead_code)]
 pub fn results( is_show_sampling : u32 ) -> *const u8 {
+  clear_last_error( );
   unsafe {
     if let Some( ref conf ) = CONFIG {
       if is_show_sampling == 1 {
@@ -128,7 +256,205 @@ pub fn results( is_show_sampling : u32 ) -> *const u8 {
         target.results( ).as_ptr( )
       }
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
+      std::ptr::null( )
+    }
+  }
+}
+
+/// Returns a pointer to the averaged linear-light colors of all pixels, as
+///   packed `[r, g, b, r, g, b, ...]` floats that are not clamped. This allows
+///   tone mapping (or compositing) to happen on the JavaScript side. The buffer
+///   remains valid until the next call. See `RenderTarget::to_raw_f32()`
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_raw_f32_buffer( ) -> *const f32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.raw_f32 = conf.target.borrow( ).to_raw_f32( );
+      conf.raw_f32.as_ptr( )
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null( )
+    }
+  }
+}
+
+/// Returns the number of floats in the last obtained raw buffer
+///   (See `get_raw_f32_buffer()`)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_raw_f32_size( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.raw_f32.len( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Returns the number of BVH nodes traversed (by both halves of the viewport)
+///   since the last reset. Changing the scene (or restarting the rendering)
+///   also resets it.
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_bvh_traversal_count( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.left_instance.num_bvh_hits( ) + conf.right_instance.num_bvh_hits( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Resets the number of traversed BVH nodes, without restarting the rendering
+/// This allows measuring BVH performance over a particular time span
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn reset_bvh_traversal_count( ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.left_instance.reset_bvh_hits( );
+      conf.right_instance.reset_bvh_hits( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Returns a pointer to the statistics of the sampling strategies of both
+///   halves of the viewport, as the JSON string
+///   `{ "total": N, "max_spp": M, "queue": Q }`, which is valid until the next
+///   call. Its length is obtained with `get_sampling_stats_json_size()`. The
+///   total and queue length are summed over both halves. The samples per
+///   pixel is their maximum. (See `SamplingStats`)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_sampling_stats_json( ) -> *const u8 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      let l = conf.left_instance.sampling_stats( );
+      let r = conf.right_instance.sampling_stats( );
+      conf.sampling_stats =
+        format!( "{{ \"total\": {}, \"max_spp\": {}, \"queue\": {} }}"
+          , l.total_samples + r.total_samples
+          , l.max_spp_this_pass.max( r.max_spp_this_pass )
+          , l.queue_len + r.queue_len
+          );
+      conf.sampling_stats.as_ptr( )
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null( )
+    }
+  }
+}
+
+/// Returns the length (in bytes) of the last obtained sampling statistics
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_sampling_stats_json_size( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.sampling_stats.len( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Returns the number of samples taken for the left half of the viewport since
+///   rendering last restarted
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_total_samples_left( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.left_instance.total_samples( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Returns the number of samples taken for the right half of the viewport
+///   since rendering last restarted
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_total_samples_right( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.right_instance.total_samples( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Returns the number of samples taken for the pixel at (x,y). For pixels
+///   outside the viewport, this fails and returns 0
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_sample_count( x : u32, y : u32 ) -> u32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      let target = conf.target.borrow( );
+      if ( x as usize ) < target.viewport_width && ( y as usize ) < target.viewport_height {
+        target.sample_count( x as usize, y as usize ) as u32
+      } else {
+        set_last_error( "Pixel outside viewport" );
+        0
+      }
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Returns the smallest number of samples taken for any pixel
+/// Together with `get_max_sample_count()`, this allows normalizing the sample
+///   counts without querying every pixel
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_min_sample_count( ) -> u32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.target.borrow( ).min_sample_count( ) as u32
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Returns the largest number of samples taken for any pixel
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_max_sample_count( ) -> u32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.target.borrow( ).max_sample_count( ) as u32
+    } else {
+      set_last_error( "init not called" );
+      0
     }
   }
 }
@@ -142,7 +468,7 @@ pub fn reset( ) {
       conf.left_instance.reset( );
       conf.right_instance.reset( );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
@@ -152,17 +478,33 @@ pub fn reset( ) {
 #[wasm_bindgen]
 #[allow(dead_code)]
 pub fn update_scene( scene_id : u32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
+      let mut scene =
+        if let Some( scene ) = select_scene( scene_id, &conf.meshes, &conf.textures ) {
+          scene
+        } else {
+          set_last_error( "Invalid scene" );
+          return;
+        };
+      if conf.bvh_leaf_size != 1 {
+        scene.set_bvh_leaf_size( conf.bvh_leaf_size );
+      }
+      scene.set_bvh_ropes_enabled( conf.is_bvh_ropes_enabled );
+      scene.set_infinite_shapes_enabled( conf.is_infinite_shapes_enabled );
+      scene.set_env_map( env_map( conf ) );
+
       conf.scene_id = scene_id;
-      conf.scene    = Rc::new( select_scene( scene_id, &conf.meshes, &conf.textures ) );
+      conf.scene    = Rc::new( scene );
+      conf.instances.clear( );
       conf.target.borrow_mut( ).clear( );
       conf.sampling_target.borrow_mut( ).clear( );
 
       conf.left_instance.update_scene( conf.scene.clone( ) );
       conf.right_instance.update_scene( conf.scene.clone( ) );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
@@ -171,8 +513,18 @@ pub fn update_scene( scene_id : u32 ) {
 #[wasm_bindgen]
 #[allow(dead_code)]
 pub fn update_settings( left_type : u32, right_type : u32, is_left_adaptive : u32, is_right_adaptive : u32, is_light_debug : u32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
+      let (left_type, right_type) =
+        match ( to_render_type( left_type ), to_render_type( right_type ) ) {
+          ( Some( l ), Some( r ) ) => ( l, r ),
+          _ => {
+            set_last_error( "Invalid render type" );
+            return;
+          }
+        };
+
       let mut target = conf.target.borrow_mut( );
 
       let width  = target.viewport_width as usize;
@@ -195,204 +547,1064 @@ pub fn update_settings( left_type : u32, right_type : u32, is_left_adaptive : u3
     
       target.clear( );
       conf.sampling_target.borrow_mut( ).clear( );
-      conf.left_instance  = RenderInstance::new( conf.scene.clone( ), conf.camera.clone( ), conf.rng.clone( ), left_sampling,  is_light_debug == 1, conf.target.clone( ), to_render_type( left_type ) );
-      conf.right_instance = RenderInstance::new( conf.scene.clone( ), conf.camera.clone( ), conf.rng.clone( ), right_sampling, is_light_debug == 1, conf.target.clone( ), to_render_type( right_type ) );
+      conf.left_instance  = RenderInstance::new( conf.scene.clone( ), conf.camera.clone( ), conf.rng.clone( ), left_sampling,  is_light_debug == 1, conf.target.clone( ), left_type );
+      conf.right_instance = RenderInstance::new( conf.scene.clone( ), conf.camera.clone( ), conf.rng.clone( ), right_sampling, is_light_debug == 1, conf.target.clone( ), right_type );
+      conf.left_instance.set_firefly_clamp( conf.firefly_clamp );
+      conf.right_instance.set_firefly_clamp( conf.firefly_clamp );
+      conf.left_instance.set_russian_roulette( conf.rr_threshold, conf.rr_kill_prob );
+      conf.right_instance.set_russian_roulette( conf.rr_threshold, conf.rr_kill_prob );
+      conf.left_instance.set_max_bounces( conf.max_bounces );
+      conf.right_instance.set_max_bounces( conf.max_bounces );
+      conf.left_instance.set_tile_size( conf.tile_size.0, conf.tile_size.1 );
+      conf.right_instance.set_tile_size( conf.tile_size.0, conf.tile_size.1 );
+      if let Some( ( min, max ) ) = conf.photon_bounds {
+        conf.left_instance.set_photon_tree_bounds( min, max );
+        conf.right_instance.set_photon_tree_bounds( min, max );
+      }
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
 
-/// Converts a render type "magic number" to its actual render type
-fn to_render_type( t : u32 ) -> RenderType {
-  match t {
-    0 => RenderType::NoNEE,
-    1 => RenderType::NormalNEE,
-    2 => RenderType::PNEE,
-    _ => panic!( "Invalid RenderType magic number" )
+/// Sets the maximum value of every color channel of a single path
+///   contribution, which removes "fireflies" at the cost of some bias. Pass
+///   `Infinity` to disable it (which is the initial setting). A value of `10.0`
+///   is sensible for production renders. Doing this restarts the rendering
+///   process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn set_firefly_clamp( max_val : f32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.firefly_clamp = max_val;
+      conf.left_instance.set_firefly_clamp( max_val );
+      conf.right_instance.set_firefly_clamp( max_val );
+      reset( );
+    } else {
+      set_last_error( "init not called" );
+    }
   }
 }
 
-/// Updates the viewport, and thus the render buffer
+/// Sets the Russian roulette parameters. Paths whose throughput drops below
+///   `threshold` are killed with probability `kill_prob`. Doing this restarts
+///   the rendering process
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn update_viewport( width : u32, height : u32 ) {
+pub fn update_russian_roulette( threshold : f32, kill_prob : f32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
-      *conf.target.borrow_mut( )          = RenderTarget::new( width as usize, height as usize );
-      *conf.sampling_target.borrow_mut( ) = SimpleRenderTarget::new( width as usize, height as usize );
-      let left_width = width / 2;
-      conf.left_instance.resize( 0, 0, left_width as usize, height as usize );
-      conf.right_instance.resize( left_width as usize, 0, ( width - left_width ) as usize, height as usize );
+      conf.rr_threshold = threshold;
+      conf.rr_kill_prob = kill_prob;
+      conf.left_instance.set_russian_roulette( threshold, kill_prob );
+      conf.right_instance.set_russian_roulette( threshold, kill_prob );
       reset( );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
 
-/// Updates the camera in the session
-/// Other aspects of the session remain the same
-/// Note that the camera first rotates around the x-axis, then around the y-axis, then it translates
+/// Sets the maximum number of bounces of a path. Doing this restarts the
+///   rendering process
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn update_camera( cam_x : f32, cam_y : f32, cam_z : f32, cam_rot_x : f32, cam_rot_y : f32 ) {
+pub fn update_max_bounces( n : u32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
-      *conf.camera.borrow_mut( ) = Camera::new( Vec3::new( cam_x, cam_y, cam_z ), cam_rot_x, cam_rot_y );
+      conf.max_bounces = n;
+      conf.left_instance.set_max_bounces( n );
+      conf.right_instance.set_max_bounces( n );
       reset( );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
 
-// Mesh allocation happens in three stages:
-// * First the space for the vertices is allocated
-// * Then TypeScript stores the vertices in WASM's memory
-// * Then, if the current scene is supposed to contain that mesh,
-//     it is rebuilt with the mesh
-//
-// This is the first stage
+/// Sets the size (in pixels) of the tiles into which the viewport is divided.
+///   Every tile has its own random number generator. Doing this restarts the
+///   rendering process
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn allocate_mesh( id : u32, num_vertices : u32 ) {
+pub fn set_tile_size( width : u32, height : u32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
-      conf.meshes.insert(
-          id
-        , Mesh::Preload( vec![Vec3::ZERO; num_vertices as usize] )
-        );
+      conf.tile_size = ( width as usize, height as usize );
+      conf.left_instance.set_tile_size( width as usize, height as usize );
+      conf.right_instance.set_tile_size( width as usize, height as usize );
+      reset( );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
 
-/// Obtains a pointer to the mesh vertices
+/// Returns the state of the random number generator, which is shared by the
+///   renderers. Restoring it with `set_rng_state(..)` replays the same samples
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn mesh_vertices( id : u32 ) -> *mut Vec3 {
+pub fn get_rng_state( ) -> u32 {
+  clear_last_error( );
   unsafe {
-    if let Some( ref mut conf ) = CONFIG {
-      if let Some( Mesh::Preload( ref mut m ) ) = conf.meshes.get_mut( &id ) {
-        m.as_mut_ptr( )
-      } else {
-        panic!( "Mesh not allocated" )
-      }
+    if let Some( ref conf ) = CONFIG {
+      conf.rng.borrow( ).get_state( )
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
+      0
     }
   }
 }
 
-/// Notifies the raytracer that all the mesh vertices are placed in WASM
-/// memory. Returns `true` if a scene with the loaded mesh is currently rendering
+/// Restores the state of the random number generator, as obtained from
+///   `get_rng_state()`. Unlike most settings, this does *not* restart the
+///   rendering process; so the next samples continue from this state
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn notify_mesh_loaded( id : u32 ) -> bool {
+pub fn set_rng_state( state : u32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
-      if let Some( Mesh::Preload( ref m ) ) = conf.meshes.get_mut( &id ) {
-        let num_triangles = m.len( ) / 3;
-        let mut triangles : Vec< Rc< dyn Tracable > > = Vec::with_capacity( num_triangles );
+      conf.rng.borrow_mut( ).set_state( state );
+      // The viewport tiles have generators of their own, which are seeded from
+      // the shared generator
+      conf.left_instance.reset_tile_rngs( );
+      conf.right_instance.reset_tile_rngs( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
 
-        let mat = Material::diffuse( Color3::new( 1.0, 0.4, 0.4 ) );
+/// Adds a sphere to the current scene. See `to_material(..)` for the material
+///   magic numbers. Emissive spheres become area lights.
+/// Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn add_sphere( x : f32, y : f32, z : f32, r : f32, material_id : u32 ) {
+  clear_last_error( );
+  if let Some( mat ) = to_material( material_id ) {
+    add_shape( Rc::new( Sphere::new( Vec3::new( x, y, z ), r, mat ) ) );
+  } else {
+    set_last_error( "Invalid material" );
+  }
+}
 
-        for i in 0..num_triangles {
-          // These are actually transformations within the scene
-          // But do perform them here, instead of upon each scene construction
-          let mut triangle =
-            Triangle::new( m[ i * 3 + 0 ] * 0.5, m[ i * 3 + 1 ] * 0.5, m[ i * 3 + 2 ] * 0.5
-                , mat.clone( ) );
-          triangle = triangle.translate( Vec3::new( 0.0, 0.0, 5.0 ) );
+/// Adds a triangle with the three provided vertices to the current scene. See
+///   `to_material(..)` for the material magic numbers.
+/// Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+// wasm_bindgen cannot cheaply pass structs from JS, so take plain scalars
+#[allow(clippy::too_many_arguments)]
+pub fn add_triangle( x0 : f32, y0 : f32, z0 : f32
+                   , x1 : f32, y1 : f32, z1 : f32
+                   , x2 : f32, y2 : f32, z2 : f32
+                   , material_id : u32 ) {
+  clear_last_error( );
+  if let Some( mat ) = to_material( material_id ) {
+    add_shape( Rc::new( Triangle::new( Vec3::new( x0, y0, z0 ), Vec3::new( x1, y1, z1 ), Vec3::new( x2, y2, z2 ), mat ) ) );
+  } else {
+    set_last_error( "Invalid material" );
+  }
+}
 
-          triangles.push( Rc::new( triangle ) );
-        }
+/// Adds a homogeneous participating medium (such as fog) to the current
+///   scene, which fills the box between the two provided corners. The density
+///   is the expected number of scattering events per unit of distance. The
+///   albedo (r,g,b) is the fraction of light that is scattered, rather than
+///   absorbed. `phase_g` in (-1,1) is the asymmetry of the scattering. (See
+///   `Volume`)
+/// Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+// wasm_bindgen cannot cheaply pass structs from JS, so take plain scalars
+#[allow(clippy::too_many_arguments)]
+pub fn add_volume( x_min : f32, y_min : f32, z_min : f32
+                 , x_max : f32, y_max : f32, z_max : f32
+                 , density : f32, r : f32, g : f32, b : f32, phase_g : f32 ) {
+  clear_last_error( );
+  let bounds = AABB::new1( x_min, y_min, z_min, x_max, y_max, z_max );
+  add_shape( Rc::new( Volume::new( bounds, density, Vec3::new( r, g, b ), phase_g ) ) );
+}
 
-        conf.meshes.insert( id, Mesh::Triangled( triangles ) );
-      }
+/// Adds the shape to the current scene, and restarts the renderers with it
+/// Note that these shapes are lost when the scene is updated (or reloaded)
+fn add_shape( shape : Rc< dyn Tracable > ) {
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      // The render instances share the scene, so modify a copy. Shapes
+      // themselves are shared, so this is cheap.
+      let mut scene = ( *conf.scene ).clone( );
+      scene.add_shape( shape );
+      conf.scene = Rc::new( scene );
 
-      // Scene 1 uses mesh 0. Scene 2 uses mesh 1. Scene 3 uses mesh 2
-      if ( id == 0 && conf.scene_id == 1 ) ||
-         ( id == 1 && conf.scene_id == 2 ) ||
-         ( id == 2 && conf.scene_id == 3 ) {
-        update_scene( conf.scene_id );
-        true
-      } else {
-        false
-      }
+      conf.target.borrow_mut( ).clear( );
+      conf.sampling_target.borrow_mut( ).clear( );
+      conf.left_instance.update_scene( conf.scene.clone( ) );
+      conf.right_instance.update_scene( conf.scene.clone( ) );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
 
-/// Allocates a texture identifier by the provided `id` with the provided size
-/// Returns a pointer to the u8 RGB store location
+/// Sets the number of shapes below which BVH nodes always become leaves, and
+///   rebuilds the BVH. (So 1 means only the SAH decides.) Scenes with a 4-way
+///   BVH use at most 15. This also applies to scenes that are selected later.
+///   Doing this restarts the rendering process
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn allocate_texture( id : u32, width : u32, height : u32 ) -> *mut (u8,u8,u8) {
+pub fn set_bvh_leaf_size( n : u32 ) {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
-      conf.textures.insert(
-          id
-        , Texture::new( width, height )
-        );
-      if let Some( t ) = conf.textures.get_mut( &id ) {
-        t.data.as_mut_ptr( )
-      } else {
-        // Shouldn't happen
-        panic!( "HashMap error" )
-      }
+      conf.bvh_leaf_size = ( n as usize ).max( 1 );
+
+      // The render instances share the scene, so modify a copy
+      let mut scene = ( *conf.scene ).clone( );
+      scene.set_bvh_leaf_size( conf.bvh_leaf_size );
+      conf.scene = Rc::new( scene );
+
+      conf.target.borrow_mut( ).clear( );
+      conf.sampling_target.borrow_mut( ).clear( );
+      conf.left_instance.update_scene( conf.scene.clone( ) );
+      conf.right_instance.update_scene( conf.scene.clone( ) );
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
     }
   }
 }
 
-/// Notifies the raytracer that the texture RGB data has been put into WASM's
-/// memory. If the current scene is using that texture, the scene is updated
+/// Adds a copy of the (loaded) mesh to the scene, which is rotated by `rx`,
+///   `ry` and `rz` radians around the x-, y- and z-axes, and then translated
+///   by (tx,ty,tz). (See `Instance::new(..)`) The BVH of the mesh is shared
+///   among all its instances. Returns the identifier of the instance, which
+///   is used to remove it. (See `remove_instance(..)`) Identifiers start at
+///   1, as 0 is returned on error. (See `get_last_error()`)
+/// Instances are removed when another scene is selected. Doing this restarts
+///   the rendering process
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn notify_texture_loaded( _id : u32 ) -> bool {
+pub fn add_instance( mesh_id : u32, tx : f32, ty : f32, tz : f32, rx : f32, ry : f32, rz : f32 ) -> u32 {
+  clear_last_error( );
   unsafe {
-    if let Some( ref mut _conf ) = CONFIG {
-      false
+    if let Some( ref mut conf ) = CONFIG {
+      if !conf.mesh_scenes.contains_key( &mesh_id ) {
+        if let Some( Mesh::Triangled( ts ) ) = conf.meshes.get( &mesh_id ) {
+          let mesh_scene = Scene::new( Color3::BLACK, vec![ ], ts.clone( ) );
+          conf.mesh_scenes.insert( mesh_id, Rc::new( mesh_scene ) );
+        } else {
+          set_last_error( "Mesh not loaded" );
+          return 0;
+        }
+      }
+
+      let mesh_scene = conf.mesh_scenes[ &mesh_id ].clone( );
+      let instance : Rc< dyn Tracable > =
+        Rc::new( Instance::new( mesh_scene, Vec3::new( tx, ty, tz ), rx, ry, rz ) );
+
+      let id = conf.next_instance_id;
+      conf.next_instance_id += 1;
+      conf.instances.insert( id, instance.clone( ) );
+
+      add_shape( instance );
+      id
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
+      0
     }
   }
 }
 
-/// Actually traces the rays
-/// Note that it only traces rays whose pixels are assigned to this instance.
-///   (in multi-threading different instances are assigned different pixels)
-/// Returns the number of intersected BVH nodes
+/// Removes the instance (obtained from `add_instance(..)`) from the scene
+/// Returns `false` if no such instance is in the scene. Doing this restarts
+///   the rendering process
 #[wasm_bindgen]
 #[allow(dead_code)]
-pub fn compute( num_samples : usize ) {
+pub fn remove_instance( id : u32 ) -> bool {
+  clear_last_error( );
   unsafe {
     if let Some( ref mut conf ) = CONFIG {
-      let num_samples_left = num_samples / 2;
-      conf.left_instance.compute( num_samples_left );
-      conf.right_instance.compute( num_samples - num_samples_left );
+      if let Some( instance ) = conf.instances.remove( &id ) {
+        // The render instances share the scene, so modify a copy
+        let mut scene = ( *conf.scene ).clone( );
+        scene.remove_shape( &instance );
+        conf.scene = Rc::new( scene );
+
+        conf.target.borrow_mut( ).clear( );
+        conf.sampling_target.borrow_mut( ).clear( );
+        conf.left_instance.update_scene( conf.scene.clone( ) );
+        conf.right_instance.update_scene( conf.scene.clone( ) );
+        true
+      } else {
+        false
+      }
     } else {
-      panic!( "init not called" )
+      set_last_error( "init not called" );
+      false
     }
   }
 }
 
-// Scenes are numbered in the interface. This functions performs the mapping
+/// Enables (1) or disables (0) stackless traversal of the 2-way BVH with ropes
+///   (See `Scene::set_bvh_ropes_enabled(..)`) This also applies to scenes
+///   that are selected later. Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn set_bvh_ropes_enabled( enable : u32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.is_bvh_ropes_enabled = enable == 1;
+
+      // The render instances share the scene, so modify a copy
+      let mut scene = ( *conf.scene ).clone( );
+      scene.set_bvh_ropes_enabled( conf.is_bvh_ropes_enabled );
+      conf.scene = Rc::new( scene );
+
+      conf.target.borrow_mut( ).clear( );
+      conf.sampling_target.borrow_mut( ).clear( );
+      conf.left_instance.update_scene( conf.scene.clone( ) );
+      conf.right_instance.update_scene( conf.scene.clone( ) );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Enables (1) or disables (0) the infinite shapes (e.g. planes) in the scene
+///   (See `Scene::set_infinite_shapes_enabled(..)`) This also applies to
+///   scenes that are selected later. Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn set_infinite_shapes_enabled( enable : u32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.is_infinite_shapes_enabled = enable == 1;
+
+      // The render instances share the scene, so modify a copy
+      let mut scene = ( *conf.scene ).clone( );
+      scene.set_infinite_shapes_enabled( conf.is_infinite_shapes_enabled );
+      conf.scene = Rc::new( scene );
+
+      conf.target.borrow_mut( ).clear( );
+      conf.sampling_target.borrow_mut( ).clear( );
+      conf.left_instance.update_scene( conf.scene.clone( ) );
+      conf.right_instance.update_scene( conf.scene.clone( ) );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Converts a material "magic number" to its actual material
+/// Returns `None` for an unknown magic number
+fn to_material( id : u32 ) -> Option< Material > {
+  match id {
+    0 => Some( Material::diffuse( Color3::WHITE ) ),
+    1 => Some( Material::diffuse( Color3::new( 0.7, 0.7, 0.7 ) ) ),
+    2 => Some( Material::diffuse( Color3::new( 1.0, 0.4, 0.4 ) ) ),
+    3 => Some( Material::emissive( Vec3::new( 16.0, 16.0, 16.0 ) ) ),
+    _ => None
+  }
+}
+
+/// Sets the bounds of the photon octree that is used for PNEE. Photons outside
+///   it are ignored, so it should contain the scene. This discards all photons.
+///   Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn set_photon_tree_bounds( x_min : f32, y_min : f32, z_min : f32, x_max : f32, y_max : f32, z_max : f32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      let min = Vec3::new( x_min, y_min, z_min );
+      let max = Vec3::new( x_max, y_max, z_max );
+      conf.photon_bounds = Some( ( min, max ) );
+      conf.left_instance.set_photon_tree_bounds( min, max );
+      conf.right_instance.set_photon_tree_bounds( min, max );
+      reset( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+// Photon trees (for PNEE) can be saved and loaded, such that these need not
+// be recomputed for every session:
+// * Saving: Call `save_photon_tree()`, and copy `save_photon_tree_size()`
+//     bytes from the returned pointer
+// * Loading: Copy the bytes into the buffer returned by
+//     `allocate_photon_tree(..)`, and call `load_photon_tree(..)` with it
+
+/// Serializes the photon tree of the right half of the viewport (which uses
+///   PNEE by default). Returns a pointer to the serialized bytes, which remain
+///   valid until the next save or allocation. See `PhotonTree::to_bytes()`
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn save_photon_tree( ) -> *const u8 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.photon_bytes = conf.right_instance.save_photons( );
+      conf.photon_bytes.as_ptr( )
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null( )
+    }
+  }
+}
+
+/// Returns the number of bytes of the last saved (or allocated) photon tree
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn save_photon_tree_size( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.photon_bytes.len( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Allocates a buffer of `len` bytes into which JavaScript can store a
+///   serialized photon tree. Returns a pointer to it
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn allocate_photon_tree( len : usize ) -> *mut u8 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.photon_bytes = vec![ 0; len ];
+      conf.photon_bytes.as_mut_ptr( )
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null_mut( )
+    }
+  }
+}
+
+/// Loads the serialized photon tree at `ptr` (of `len` bytes) into both
+///   halves of the viewport. It must be obtained for the current scene.
+///   Returns `false` (and loads nothing) if the data is invalid. Doing this
+///   restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn load_photon_tree( ptr : *const u8, len : usize ) -> bool {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      let data = std::slice::from_raw_parts( ptr, len );
+      match PhotonTree::from_bytes( data ) {
+        // The light ids must correspond with those of the scene
+        Ok( ref photons ) if photons.num_lights( ) != conf.scene.lights.len( ) => {
+          set_last_error( "Photon tree is for a different scene" );
+          false
+        },
+        Ok( photons ) => {
+          conf.left_instance.load_photons( photons.clone( ) );
+          conf.right_instance.load_photons( photons );
+          reset( );
+          true
+        },
+        Err( msg ) => {
+          set_last_error( msg );
+          false
+        }
+      }
+    } else {
+      set_last_error( "init not called" );
+      false
+    }
+  }
+}
+
+/// Computes the photon density of the right half of the viewport (which uses
+///   PNEE by default) in a `resolution^3` grid over the photon tree bounds.
+///   Returns a pointer to the densities, normalised to [0,1], which remain
+///   valid until the next call. See `PhotonTree::sample_density_grid(..)`
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_photon_density_buffer( resolution : u32 ) -> *const f32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.photon_density = conf.right_instance.photons( ).sample_density_grid( resolution as usize );
+      conf.photon_density.as_ptr( )
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null( )
+    }
+  }
+}
+
+/// Returns the number of cells in the last computed photon density grid
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_photon_density_buffer_size( ) -> usize {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      conf.photon_density.len( )
+    } else {
+      set_last_error( "init not called" );
+      0
+    }
+  }
+}
+
+/// Converts a render type "magic number" to its actual render type
+/// Returns `None` for an unknown magic number
+fn to_render_type( t : u32 ) -> Option< RenderType > {
+  match t {
+    0 => Some( RenderType::NoNEE ),
+    1 => Some( RenderType::NormalNEE ),
+    2 => Some( RenderType::PNEE ),
+    _ => None
+  }
+}
+
+/// Updates the viewport, and thus the render buffer
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn update_viewport( width : u32, height : u32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      *conf.target.borrow_mut( )          = RenderTarget::new( width as usize, height as usize );
+      *conf.sampling_target.borrow_mut( ) = SimpleRenderTarget::new( width as usize, height as usize );
+      let left_width = width / 2;
+      conf.left_instance.resize( 0, 0, left_width as usize, height as usize );
+      conf.right_instance.resize( left_width as usize, 0, ( width - left_width ) as usize, height as usize );
+      reset( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Updates the camera in the session
+/// Other aspects of the session remain the same
+/// Note that the camera first rotates around the x-axis, then around the y-axis, then it translates
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn update_camera( cam_x : f32, cam_y : f32, cam_z : f32, cam_rot_x : f32, cam_rot_y : f32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      *conf.camera.borrow_mut( ) = Camera::new( Vec3::new( cam_x, cam_y, cam_z ), cam_rot_x, cam_rot_y );
+      reset( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Updates the rotation of the camera in the session, as the quaternion
+///   (x,y,z,w). The camera location remains the same.
+/// Unlike `update_camera(..)`, this can represent any rotation (including roll)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn update_camera_quat( x : f32, y : f32, z : f32, w : f32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      {
+        let mut camera = conf.camera.borrow_mut( );
+        *camera = Camera::with_rotation( camera.location, Quat::new( x, y, z, w ) );
+      }
+      reset( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Updates the camera in the session, such that it is located at (lx,ly,lz)
+///   and looks toward (tx,ty,tz). The top of the view is oriented upward (along
+///   the positive y-axis).
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn update_camera_look_at( lx : f32, ly : f32, lz : f32, tx : f32, ty : f32, tz : f32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      *conf.camera.borrow_mut( ) =
+        Camera::look_at( Vec3::new( lx, ly, lz ), Vec3::new( tx, ty, tz ), Vec3::new( 0.0, 1.0, 0.0 ) );
+      reset( );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+// Mesh allocation happens in three stages:
+// * First the space for the vertices is allocated
+// * Then TypeScript stores the vertices in WASM's memory
+// * Then, if the current scene is supposed to contain that mesh,
+//     it is rebuilt with the mesh
+//
+// This is the first stage
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn allocate_mesh( id : u32, num_vertices : u32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.meshes.insert(
+          id
+        , Mesh::Preload( vec![Vec3::ZERO; num_vertices as usize], None )
+        );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Obtains a pointer to the mesh vertices
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn mesh_vertices( id : u32 ) -> *mut Vec3 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      if let Some( Mesh::Preload( ref mut m, _ ) ) = conf.meshes.get_mut( &id ) {
+        m.as_mut_ptr( )
+      } else {
+        set_last_error( "Mesh not allocated" );
+        std::ptr::null_mut( )
+      }
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null_mut( )
+    }
+  }
+}
+
+/// Allocates UV-coordinates for the (already allocated) mesh, one for every
+/// vertex. Returns a pointer to them. This is optional; if it is not called,
+/// the mesh has no UV-coordinates
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn allocate_mesh_uvs( id : u32 ) -> *mut Vec2 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      if let Some( Mesh::Preload( ref m, ref mut uvs ) ) = conf.meshes.get_mut( &id ) {
+        uvs.get_or_insert_with( || vec![Vec2::ZERO; m.len( )] ).as_mut_ptr( )
+      } else {
+        set_last_error( "Mesh not allocated" );
+        std::ptr::null_mut( )
+      }
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null_mut( )
+    }
+  }
+}
+
+/// Notifies the raytracer that all the mesh vertices are placed in WASM
+/// memory. Returns `true` if a scene with the loaded mesh is currently rendering
+/// If `has_normals == 1`, the vertices of every triangle are followed by their
+/// normals; so the stored data is interleaved as `(v0, v1, v2, n0, n1, n2)`
+/// per triangle. Those normals are interpolated over the triangles (which
+/// gives smooth shading). The UV-coordinates have the same layout (where those
+/// of the normals are ignored)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn notify_mesh_loaded( id : u32, has_normals : u32 ) -> bool {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      if let Some( Mesh::Preload( ref m, ref uvs ) ) = conf.meshes.get_mut( &id ) {
+        // The number of stored values per triangle
+        let stride = if has_normals == 1 { 6 } else { 3 };
+        let num_triangles = m.len( ) / stride;
+        let mut triangles : Vec< Rc< dyn Tracable > > = Vec::with_capacity( num_triangles );
+
+        let mat = Material::diffuse( Color3::new( 1.0, 0.4, 0.4 ) );
+
+        for i in 0..num_triangles {
+          let j = i * stride;
+          // These are actually transformations within the scene
+          // But do perform them here, instead of upon each scene construction
+          let offset = Vec3::new( 0.0, 0.0, 5.0 );
+
+          if has_normals == 1 {
+            let mut triangle =
+              SmoothTriangle::new( m[ j + 0 ], m[ j + 1 ], m[ j + 2 ], m[ j + 3 ], m[ j + 4 ], m[ j + 5 ], mat.clone( ) );
+            triangle = triangle.scale( 0.5 ).translate( offset );
+
+            if let Some( uvs ) = uvs {
+              triangle = triangle.with_uvs( uvs[ j + 0 ], uvs[ j + 1 ], uvs[ j + 2 ] );
+            }
+
+            triangles.push( Rc::new( triangle ) );
+          } else {
+            let mut triangle = Triangle::new( m[ j + 0 ], m[ j + 1 ], m[ j + 2 ], mat.clone( ) );
+            triangle = triangle.scale( 0.5 ).translate( offset );
+
+            if let Some( uvs ) = uvs {
+              triangle = triangle.with_uvs( uvs[ j + 0 ], uvs[ j + 1 ], uvs[ j + 2 ] );
+            }
+
+            triangles.push( Rc::new( triangle ) );
+          }
+        }
+
+        conf.meshes.insert( id, Mesh::Triangled( triangles ) );
+        // Its instances should use the new mesh
+        conf.mesh_scenes.remove( &id );
+      }
+
+      // Scene 1 uses mesh 0. Scene 2 uses mesh 1. Scene 3 uses mesh 2
+      if ( id == 0 && conf.scene_id == 1 ) ||
+         ( id == 1 && conf.scene_id == 2 ) ||
+         ( id == 2 && conf.scene_id == 3 ) {
+        update_scene( conf.scene_id );
+        true
+      } else {
+        false
+      }
+    } else {
+      set_last_error( "init not called" );
+      false
+    }
+  }
+}
+
+/// Allocates a texture identifier by the provided `id` with the provided size
+/// Returns a pointer to the u8 RGB store location
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn allocate_texture( id : u32, width : u32, height : u32 ) -> *mut (u8,u8,u8) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      // Any earlier texture with this id is replaced
+      conf.textures.remove( &id );
+      let t = conf.textures.entry( id ).or_insert( Texture::new( width, height ) );
+      t.data.as_mut_ptr( )
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null_mut( )
+    }
+  }
+}
+
+/// Notifies the raytracer that the texture RGB data has been put into WASM's
+/// memory. If the current scene is using that texture, the scene is updated
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn notify_texture_loaded( id : u32 ) -> bool {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      // The data was written through the pointer from `allocate_texture(..)`
+      if let Some( t ) = conf.textures.get( &id ) {
+        t.invalidate_luminance_cdf( );
+      }
+      false
+    } else {
+      set_last_error( "init not called" );
+      false
+    }
+  }
+}
+
+/// Allocates a HDR texture identifier by the provided `id` with the provided
+/// size. Returns a pointer to the f32 RGB store location (so, three `f32`s
+/// per pixel)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn allocate_hdr_texture( id : u32, width : u32, height : u32 ) -> *mut f32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      // Any earlier texture with this id is replaced
+      conf.hdr_textures.remove( &id );
+      let t = conf.hdr_textures.entry( id ).or_insert( HdrTexture::new( width as usize, height as usize ) );
+      t.data.as_mut_ptr( ) as *mut f32
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null_mut( )
+    }
+  }
+}
+
+/// Notifies the raytracer that the HDR texture data has been put into WASM's
+/// memory. If the current scene is using that texture, the scene is updated
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn notify_hdr_texture_loaded( id : u32 ) -> bool {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      if conf.env_map_id == Some( id ) {
+        update_env_map( conf );
+        true
+      } else {
+        false
+      }
+    } else {
+      set_last_error( "init not called" );
+      false
+    }
+  }
+}
+
+/// Uses the HDR texture `id` as equirectangular environment map, which gives
+///   the radiance of rays that hit nothing (instead of the background color).
+///   This also applies to scenes that are selected later. If the texture is
+///   not loaded yet, it is applied once it is. (See
+///   `notify_hdr_texture_loaded(..)`)
+/// Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn set_env_map( id : u32 ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.env_map_id = Some( id );
+      update_env_map( conf );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Removes the environment map, such that rays that hit nothing obtain the
+///   background color again
+/// Doing this restarts the rendering process
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn clear_env_map( ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      conf.env_map_id = None;
+      update_env_map( conf );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+/// Returns the environment map (See `set_env_map(..)`), if its HDR texture is
+///   allocated
+fn env_map( conf : &Config ) -> Option< Rc< HdrTexture > > {
+  conf.env_map_id
+    .and_then( |id| conf.hdr_textures.get( &id ) )
+    .map( |t| Rc::new( t.clone( ) ) )
+}
+
+/// Applies the environment map to the current scene, and restarts the
+///   renderers with it
+fn update_env_map( conf : &mut Config ) {
+  // The render instances share the scene, so modify a copy
+  let mut scene = ( *conf.scene ).clone( );
+  scene.set_env_map( env_map( conf ) );
+  conf.scene = Rc::new( scene );
+
+  conf.target.borrow_mut( ).clear( );
+  conf.sampling_target.borrow_mut( ).clear( );
+  conf.left_instance.update_scene( conf.scene.clone( ) );
+  conf.right_instance.update_scene( conf.scene.clone( ) );
+}
+
+/// Returns a pointer to the AABB around all finite shapes in the current
+///   scene, stored as [x_min, y_min, z_min, x_max, y_max, z_max]. It is valid
+///   until the next call. If the scene contains only infinite shapes, a null
+///   pointer is returned.
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn get_scene_bounds_ptr( ) -> *mut f32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      if let Some( b ) = conf.scene.bounds( ) {
+        BOUNDS_RESULT = [b.x_min, b.y_min, b.z_min, b.x_max, b.y_max, b.z_max];
+        std::ptr::addr_of_mut!( BOUNDS_RESULT ) as *mut f32
+      } else {
+        std::ptr::null_mut( )
+      }
+    } else {
+      set_last_error( "init not called" );
+      std::ptr::null_mut( )
+    }
+  }
+}
+
+/// Returns the identifier of the shape in the current scene that is first hit
+///   by the ray from (ox,oy,oz) in the direction (dx,dy,dz); or -1 if it hits
+///   nothing. This is used to select shapes by clicking in the viewport.
+///   (See `Scene::pick_shape(..)`)
+/// The identifier is only valid until the scene next changes (e.g. through
+///   `add_sphere(..)`, `remove_instance(..)` or `set_bvh_leaf_size(..)`), as
+///   rebuilding the BVH reorders the shapes.
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn pick_shape( ox : f32, oy : f32, oz : f32, dx : f32, dy : f32, dz : f32 ) -> i32 {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref conf ) = CONFIG {
+      let ray = Ray::new( Vec3::new( ox, oy, oz ), Vec3::unit( dx, dy, dz ) );
+      if let Some( shape_id ) = conf.scene.pick_shape( &ray ) {
+        shape_id as i32
+      } else {
+        -1
+      }
+    } else {
+      set_last_error( "init not called" );
+      -1
+    }
+  }
+}
+
+/// Converts the provided hue (in degrees), saturation, and value to linear RGB
+/// Returns a pointer to the resulting (r,g,b), which is valid until the next
+///   color conversion call. (See `COLOR_RESULT`)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn hsv_to_rgb( h : f32, s : f32, v : f32 ) -> *const f32 {
+  clear_last_error( );
+  let c = Color3::from_hsv( h, s, v );
+  store_color_result( c.red, c.green, c.blue )
+}
+
+/// Converts the provided linear RGB color to (hue, saturation, value)
+/// Returns a pointer to the resulting (h,s,v), which is valid until the next
+///   color conversion call. (See `COLOR_RESULT`)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn rgb_to_hsv( r : f32, g : f32, b : f32 ) -> *const f32 {
+  clear_last_error( );
+  let (h, s, v) = Color3::new( r, g, b ).to_hsv( );
+  store_color_result( h, s, v )
+}
+
+/// Converts the provided color temperature (in Kelvin) to linear RGB
+/// Returns a pointer to the resulting (r,g,b), which is valid until the next
+///   color conversion call. (See `COLOR_RESULT`)
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn color_from_kelvin( temperature : f32 ) -> *const f32 {
+  clear_last_error( );
+  let c = Color3::from_kelvin( temperature );
+  store_color_result( c.red, c.green, c.blue )
+}
+
+/// Stores the three values in `COLOR_RESULT`, and returns a pointer to it
+fn store_color_result( a : f32, b : f32, c : f32 ) -> *const f32 {
+  unsafe {
+    COLOR_RESULT = [a, b, c];
+    std::ptr::addr_of!( COLOR_RESULT ) as *const f32
+  }
+}
+
+/// Actually traces the rays
+/// Note that it only traces rays whose pixels are assigned to this instance.
+///   (in multi-threading different instances are assigned different pixels)
+/// Returns the number of intersected BVH nodes
+#[wasm_bindgen]
+#[allow(dead_code)]
+pub fn compute( num_samples : usize ) {
+  clear_last_error( );
+  unsafe {
+    if let Some( ref mut conf ) = CONFIG {
+      let num_samples_left = num_samples / 2;
+      conf.left_instance.compute( num_samples_left );
+      conf.right_instance.compute( num_samples - num_samples_left );
+    } else {
+      set_last_error( "init not called" );
+    }
+  }
+}
+
+// Scenes are numbered in the interface. This functions performs the mapping
+// Returns `None` for an unknown scene
 // Note that some scenes require externally obtained meshes, that's why these
 //   are passed along as well
 fn select_scene( id       : u32
                , meshes   : &HashMap< u32, Mesh >
                , _textures : &HashMap< u32, Texture >
-               ) -> Scene {
+               ) -> Option< Scene > {
   match id {
-    0 => setup_scene_museum( ),
-    2 => setup_scene_bunny_high( meshes ),
-    _ => panic!( "Invalid scene" )
+    0 => Some( setup_scene_museum( ) ),
+    2 => Some( setup_scene_bunny_high( meshes ) ),
+    _ => None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Mutex;
+
+  // The exports share global state, so tests that use it cannot run in parallel
+  static GLOBAL_STATE : Mutex< () > = Mutex::new( () );
+
+  /// Returns the message stored by `set_last_error(..)`, as JavaScript sees it
+  fn last_error( ) -> String {
+    unsafe {
+      let msg = std::slice::from_raw_parts( get_last_error( ), get_last_error_len( ) );
+      String::from_utf8_lossy( msg ).into_owned( )
+    }
+  }
+
+  #[test]
+  fn init_after_destroy_succeeds( ) {
+    let _lock = GLOBAL_STATE.lock( ).unwrap_or_else( |e| e.into_inner( ) );
+
+    init( 8, 4, 0, 0.0, 0.0, 0.0, 0.0, 0.0 );
+    destroy( );
+    init( 8, 4, 0, 0.0, 0.0, 0.0, 0.0, 0.0 );
+
+    unsafe {
+      assert!( ( &*std::ptr::addr_of!( CONFIG ) ).is_some( ) );
+    }
+    destroy( );
+  }
+
+  #[test]
+  fn invalid_input_sets_error_instead_of_panicking( ) {
+    let _lock = GLOBAL_STATE.lock( ).unwrap_or_else( |e| e.into_inner( ) );
+
+    init( 8, 4, 99, 0.0, 0.0, 0.0, 0.0, 0.0 );
+    assert_eq!( last_error( ), "Invalid scene" );
+
+    init( 8, 4, 0, 0.0, 0.0, 0.0, 0.0, 0.0 );
+    assert_eq!( last_error( ), "" );
+
+    init( 8, 4, 0, 0.0, 0.0, 0.0, 0.0, 0.0 );
+    assert_eq!( last_error( ), "Cannot init again. Call destroy first" );
+
+    update_scene( 99 );
+    assert_eq!( last_error( ), "Invalid scene" );
+
+    update_settings( 7, 1, 0, 0, 0 );
+    assert_eq!( last_error( ), "Invalid render type" );
+
+    add_sphere( 0.0, 0.0, 0.0, 1.0, 99 );
+    assert_eq!( last_error( ), "Invalid material" );
+
+    get_sample_count( 8, 0 );
+    assert_eq!( last_error( ), "Pixel outside viewport" );
+
+    // A successful call clears the earlier error
+    get_sample_count( 7, 3 );
+    assert_eq!( last_error( ), "" );
+
+    destroy( );
   }
 }
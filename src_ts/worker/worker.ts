@@ -175,7 +175,7 @@ function handleStoreMesh( msg : MsgC2WStoreMesh ) {
   let ptrVertices = exps.mesh_vertices( msg.id );
   let dst = new Float32Array( exps.memory.buffer, ptrVertices, msg.mesh.vertices.length );
   dst.set( msg.mesh.vertices );
-  exps.notify_mesh_loaded( msg.id );
+  exps.notify_mesh_loaded( msg.id, 0 );
 }
 
 // Passes a texture to the WASM client